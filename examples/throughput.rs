@@ -0,0 +1,80 @@
+//! Measures how throughput scales with the number of server and worker threads
+//!
+//! `cargo run --release --example throughput`
+
+extern crate hapi_rs;
+
+use hapi_rs::http::{HTTPError, HTTPMethod, HTTPVersion};
+use hapi_rs::server::{Route, Server, ServerOptions};
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CLIENTS: usize = 32;
+const DURATION: Duration = Duration::from_secs(3);
+const HANDLER_LATENCY: Duration = Duration::from_millis(2);
+
+fn main() {
+    println!("threads  requests/s");
+
+    for (i, threads) in [1, 2, 4, 8, 16].iter().enumerate() {
+        let port = 4100 + i as u32;
+        start_server(port, *threads);
+        println!("{:>7}  {:>10.0}", threads, run_clients(port));
+    }
+}
+
+fn start_server(port: u32, threads: usize) {
+    let mut options = ServerOptions::new(String::from("127.0.0.1"), port, threads, threads, HTTPVersion::HTTP11);
+    options.set_queue_capacity(threads * 2);
+
+    let mut server = Server::new(&options);
+    server.route(Route::new(vec![HTTPMethod::GET], String::from("/"), |_req| -> Result<&'static str, HTTPError> {
+        // Simulate waiting on a database
+        thread::sleep(HANDLER_LATENCY);
+        Ok("hapi")
+    }));
+
+    thread::spawn(move || server.start());
+
+    // Give the server threads a moment to bind
+    thread::sleep(Duration::from_millis(200));
+}
+
+/// Hammer the server from many clients and return the requests per second it answered
+fn run_clients(port: u32) -> f64 {
+    let completed = Arc::new(AtomicUsize::new(0));
+    let start = Instant::now();
+
+    let clients: Vec<_> = (0..CLIENTS).map(|_| {
+        let completed = Arc::clone(&completed);
+        thread::spawn(move || {
+            while start.elapsed() < DURATION {
+                if request(port) {
+                    completed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        })
+    }).collect();
+
+    for client in clients {
+        client.join().unwrap();
+    }
+
+    completed.load(Ordering::Relaxed) as f64 / start.elapsed().as_secs_f64()
+}
+
+fn request(port: u32) -> bool {
+    let mut stream = match TcpStream::connect(("127.0.0.1", port as u16)) {
+        Ok(stream) => stream,
+        Err(_) => return false
+    };
+
+    let mut response = vec![];
+    stream.write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").is_ok()
+        && stream.read_to_end(&mut response).is_ok()
+        && response.starts_with(b"HTTP/1.1 200")
+}
@@ -0,0 +1,183 @@
+use super::HTTPError;
+use super::super::Response;
+use serde_json::{Map, Value};
+
+/// How the server renders error responses
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ErrorFormat {
+    /// `{"statusCode", "error", "message"}` JSON bodies
+    #[default]
+    Json,
+    /// [`RFC 7807`](https://tools.ietf.org/html/rfc7807) `application/problem+json` bodies, plain text if the client won't accept JSON
+    Problem
+}
+
+impl ErrorFormat {
+    /// Render the error for a client sending the given `Accept` header, `instance` identifies the failed request
+    /// Messages of server errors are only rendered with `expose_server_errors`
+    pub fn render(self, error: HTTPError, accept: Option<String>, instance: Option<String>, expose_server_errors: bool) -> Response {
+        match self {
+            ErrorFormat::Json => error.into_json_response(expose_server_errors),
+            ErrorFormat::Problem => match accepts_json(accept) {
+                true => render_problem(error, instance, expose_server_errors),
+                false => render_text(error, expose_server_errors)
+            }
+        }
+    }
+}
+
+fn render_problem(error: HTTPError, instance: Option<String>, expose_server_errors: bool) -> Response {
+    let mut problem = Map::new();
+    problem.insert(String::from("type"), Value::from(error.get_type()));
+    problem.insert(String::from("title"), Value::from(error.get_code().get_generic_reason()));
+    problem.insert(String::from("status"), Value::from(error.get_code().to_int()));
+    problem.insert(String::from("detail"), Value::from(error.get_client_message(expose_server_errors)));
+    if let Some(instance) = instance {
+        problem.insert(String::from("instance"), Value::from(instance));
+    }
+
+    // Extension members can't shadow the standard members
+    for (key, value) in error.get_extensions() {
+        if !problem.contains_key(&key) {
+            problem.insert(key, value);
+        }
+    }
+
+    let mut response = with_error_headers(Response::from_code(error.get_code()), &error)
+        .type_("application/problem+json");
+    response.set_body(Value::Object(problem).to_string().into_bytes());
+
+    response
+}
+
+fn render_text(error: HTTPError, expose_server_errors: bool) -> Response {
+    let body = format!("{} {}: {}", error.get_code().to_int(), error.get_code().get_generic_reason(), error.get_client_message(expose_server_errors));
+
+    let mut response = with_error_headers(Response::from_code(error.get_code()), &error)
+        .type_("text/plain; charset=utf-8");
+    response.set_body(body.into_bytes());
+
+    response
+}
+
+fn with_error_headers(mut response: Response, error: &HTTPError) -> Response {
+    for (key, value) in error.get_headers().iter() {
+        response.set_header(key.clone(), value.clone());
+    }
+
+    response
+}
+
+/// Whether the client prefers a JSON problem document, JSON wins ties with `text/html`
+fn accepts_json(accept: Option<String>) -> bool {
+    let accept = match accept {
+        None => return true,
+        Some(t) => t
+    };
+
+    let mut json = 0.0;
+    let mut html = 0.0;
+    for range in accept.split(',') {
+        let (media, quality) = match parse_media_range(range) {
+            None => continue,
+            Some(t) => t
+        };
+
+        if is_json_range(&media) && quality > json {
+            json = quality;
+        }
+        if is_html_range(&media) && quality > html {
+            html = quality;
+        }
+    }
+
+    json > 0.0 && json >= html
+}
+
+/// Media type and `q` weight of one `Accept` entry, `None` when the weight isn't a number
+fn parse_media_range(range: &str) -> Option<(String, f32)> {
+    let mut params = range.split(';');
+    let media = params.next().unwrap_or("").trim().to_lowercase();
+    if media.is_empty() {
+        return None;
+    }
+
+    let mut quality = 1.0;
+    for param in params {
+        let mut pair = param.splitn(2, '=');
+        let key = pair.next().unwrap_or("").trim();
+        if key.eq_ignore_ascii_case("q") {
+            quality = match pair.next().unwrap_or("").trim().parse::<f32>() {
+                Ok(q) if q.is_finite() => q.clamp(0.0, 1.0),
+                _ => return None
+            };
+        }
+    }
+
+    Some((media, quality))
+}
+
+fn is_json_range(media: &str) -> bool {
+    matches!(media, "application/problem+json" | "application/*+json" | "application/json" | "application/*" | "*/*")
+}
+
+fn is_html_range(media: &str) -> bool {
+    matches!(media, "text/html" | "text/*" | "*/*")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::accepts_json;
+
+    fn accepts(accept: &str) -> bool {
+        accepts_json(Some(String::from(accept)))
+    }
+
+    #[test]
+    fn missing_accept_gets_json() {
+        assert!(accepts_json(None));
+    }
+
+    #[test]
+    fn json_ranges_are_accepted() {
+        assert!(accepts("application/problem+json"));
+        assert!(accepts("application/*+json"));
+        assert!(accepts("application/json"));
+        assert!(accepts("application/*"));
+        assert!(accepts("*/*"));
+        assert!(accepts("Application/JSON; charset=utf-8"));
+    }
+
+    #[test]
+    fn other_ranges_are_refused() {
+        assert!(!accepts("text/plain"));
+        assert!(!accepts("application/xml, image/png"));
+        assert!(!accepts(""));
+    }
+
+    #[test]
+    fn zero_quality_refuses_a_range() {
+        assert!(!accepts("application/json;q=0"));
+        assert!(!accepts("application/json; q=0.000"));
+        assert!(!accepts("*/*;Q=0"));
+        assert!(!accepts("application/json;q=-1"));
+        assert!(accepts("application/json;q=0.001"));
+    }
+
+    #[test]
+    fn malformed_quality_is_ignored() {
+        assert!(!accepts("application/json;q=abc"));
+        assert!(!accepts("application/json;q=NaN"));
+        assert!(accepts("application/json;q=, application/problem+json"));
+    }
+
+    #[test]
+    fn html_preference_is_respected() {
+        assert!(!accepts("text/html, application/json;q=0.9"));
+        assert!(!accepts("text/html, */*;q=0.8"));
+        assert!(!accepts("text/*;q=0.5, application/json;q=0.4"));
+        assert!(accepts("text/html;q=0.9, application/json"));
+        assert!(accepts("text/html, application/problem+json"));
+        assert!(accepts("text/html;q=0, */*"));
+    }
+}
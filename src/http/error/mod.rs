@@ -0,0 +1,313 @@
+pub(crate) mod format;
+
+use super::{Header, HTTPStatusCodes, IntoResponse, Response};
+use serde_json::{json, Map, Value};
+use std::error::Error;
+use std::fmt;
+
+/// An HTTP error with everything needed to answer the client
+#[derive(Debug, Clone)]
+pub struct HTTPError {
+    code: HTTPStatusCodes,
+    message: String,
+    // Boxed so handler results stay small
+    details: Box<ErrorDetails>
+}
+
+#[derive(Debug, Clone, Default)]
+struct ErrorDetails {
+    data: Option<Value>,
+    headers: Header,
+    problem_type: Option<String>,
+    extensions: Map<String, Value>
+}
+
+impl HTTPError {
+    pub fn new(code: HTTPStatusCodes, message: &str) -> Self {
+        Self {
+            code,
+            message: String::from(message),
+            details: Box::default()
+        }
+    }
+
+    /// Attach application data to the error, it is never sent to the client
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.details.data = Some(data);
+        self
+    }
+
+    /// Add a header to the error response, e.g. `WWW-Authenticate` for a 401
+    pub fn with_header(mut self, key: &str, value: &str) -> Self {
+        self.details.headers.insert(String::from(key), String::from(value));
+        self
+    }
+
+    /// URI identifying the problem type when rendered as `application/problem+json`
+    pub fn with_type(mut self, problem_type: &str) -> Self {
+        self.details.problem_type = Some(String::from(problem_type));
+        self
+    }
+
+    /// Add an extension member to the `application/problem+json` document
+    pub fn with_extension(mut self, key: &str, value: Value) -> Self {
+        self.details.extensions.insert(String::from(key), value);
+        self
+    }
+
+    /**
+     * Getters
+    */
+
+    pub fn get_code(&self) -> HTTPStatusCodes {
+        self.code
+    }
+
+    pub fn get_message(&self) -> String {
+        self.message.clone()
+    }
+
+    pub fn get_data(&self) -> Option<Value> {
+        self.details.data.clone()
+    }
+
+    pub fn get_headers(&self) -> Header {
+        self.details.headers.clone()
+    }
+
+    pub fn get_type(&self) -> String {
+        match self.details.problem_type {
+            None => String::from("about:blank"),
+            Some(ref t) => t.clone()
+        }
+    }
+
+    pub fn get_extensions(&self) -> Map<String, Value> {
+        self.details.extensions.clone()
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        self.code.to_int() >= 500
+    }
+
+    /// The message safe to show the client, server error details are replaced with a generic message
+    pub fn get_public_message(&self) -> String {
+        self.get_client_message(false)
+    }
+
+    /// The message sent to the client, server error details only when `expose_server_errors` is set
+    pub fn get_client_message(&self, expose_server_errors: bool) -> String {
+        match self.is_server_error() && !expose_server_errors {
+            true => String::from("An internal server error occurred"),
+            false => self.get_message()
+        }
+    }
+
+    /// The `{"statusCode", "error", "message"}` JSON response for this error
+    pub fn into_json_response(self, expose_server_errors: bool) -> Response {
+        let payload = json!({
+            "statusCode": self.code.to_int(),
+            "error": self.code.get_generic_reason(),
+            "message": self.get_client_message(expose_server_errors)
+        });
+
+        let mut response = Response::from_code(self.code)
+            .type_("application/json; charset=utf-8");
+        for (key, value) in self.details.headers.iter() {
+            response.set_header(key.clone(), value.clone());
+        }
+        response.set_body(payload.to_string().into_bytes());
+
+        response
+    }
+
+    /**
+     * 4xx Client Errors
+    */
+
+    pub fn bad_request(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::BadRequest400, message)
+    }
+
+    pub fn unauthorized(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::Unauthorized401, message)
+    }
+
+    pub fn payment_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::PaymentRequired402, message)
+    }
+
+    pub fn forbidden(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::Forbidden403, message)
+    }
+
+    pub fn not_found(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::NotFound404, message)
+    }
+
+    pub fn method_not_allowed(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::MethodNotAllowed405, message)
+    }
+
+    pub fn not_acceptable(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::NotAcceptable406, message)
+    }
+
+    pub fn proxy_authentication_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::ProxyAuthenticationRequired407, message)
+    }
+
+    pub fn request_timeout(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::RequestTimeout408, message)
+    }
+
+    pub fn conflict(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::Conflict409, message)
+    }
+
+    pub fn gone(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::Gone410, message)
+    }
+
+    pub fn length_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::LengthRequired411, message)
+    }
+
+    pub fn precondition_failed(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::PreconditionFailed412, message)
+    }
+
+    pub fn payload_too_large(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::PayloadTooLarge413, message)
+    }
+
+    pub fn uri_too_long(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::URITooLong414, message)
+    }
+
+    pub fn unsupported_media_type(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::UnsupportedMediaType415, message)
+    }
+
+    pub fn range_not_satisfiable(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::RangeNotSatisfiable416, message)
+    }
+
+    pub fn expectation_failed(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::ExpectationFailed417, message)
+    }
+
+    pub fn im_a_teapot(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::ImATeapot418, message)
+    }
+
+    pub fn misdirected_request(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::MisdirectedRequest421, message)
+    }
+
+    pub fn unprocessable_entity(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::UnprocessableEntity422, message)
+    }
+
+    pub fn locked(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::Locked423, message)
+    }
+
+    pub fn failed_dependency(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::FailedDependency424, message)
+    }
+
+    pub fn upgrade_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::UpgradeRequired426, message)
+    }
+
+    pub fn precondition_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::PreconditionRequired428, message)
+    }
+
+    pub fn too_many_requests(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::TooManyRequests429, message)
+    }
+
+    pub fn request_header_fields_too_large(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::RequestHeaderFieldsTooLarge431, message)
+    }
+
+    pub fn unavailable_for_legal_reasons(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::UnavailableForLegalReasons451, message)
+    }
+
+    /**
+     * 5xx Server Errors
+    */
+
+    pub fn internal_server_error(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::InternalServerError500, message)
+    }
+
+    pub fn not_implemented(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::NotImplemented501, message)
+    }
+
+    pub fn bad_gateway(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::BadGateway502, message)
+    }
+
+    pub fn service_unavailable(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::ServiceUnavailable503, message)
+    }
+
+    pub fn gateway_timeout(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::GatewayTimeout504, message)
+    }
+
+    pub fn http_version_not_supported(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::HTTPVersionNotSupported505, message)
+    }
+
+    pub fn variant_also_negotiates(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::VariantAlsoNegotiates506, message)
+    }
+
+    pub fn insufficient_storage(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::InsufficientStorage507, message)
+    }
+
+    pub fn loop_detected(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::LoopDetected508, message)
+    }
+
+    pub fn not_extended(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::NotExtended510, message)
+    }
+
+    pub fn network_authentication_required(message: &str) -> Self {
+        Self::new(HTTPStatusCodes::NetworkAuthenticationRequired511, message)
+    }
+}
+
+impl fmt::Display for HTTPError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}: {}", self.code.to_int(), self.code.get_generic_reason(), self.message)
+    }
+}
+
+impl Error for HTTPError {}
+
+impl From<String> for HTTPError {
+    fn from(message: String) -> Self {
+        Self::internal_server_error(&message)
+    }
+}
+
+impl From<&str> for HTTPError {
+    fn from(message: &str) -> Self {
+        Self::internal_server_error(message)
+    }
+}
+
+impl IntoResponse for HTTPError {
+    fn into_response(self) -> Response {
+        self.into_json_response(false)
+    }
+}
@@ -0,0 +1,478 @@
+use super::{ErrorCode, H2Error};
+use std::convert::TryInto;
+
+/// Length, type, flags and stream id come before every frame's payload
+pub const FRAME_HEADER_SIZE: usize = 9;
+
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const PRIORITY: u8 = 0x2;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PUSH_PROMISE: u8 = 0x5;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY_FLAG: u8 = 0x20;
+
+/// Where a stream sits in the dependency tree and its share of its parent's resources
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Priority {
+    dependency: u32,
+    exclusive: bool,
+    weight: u16
+}
+
+impl Priority {
+    /// `weight` is between 1 and 256
+    pub fn new(dependency: u32, exclusive: bool, weight: u16) -> Self {
+        Self {
+            dependency,
+            exclusive,
+            weight: weight.clamp(1, 256)
+        }
+    }
+
+    pub fn get_dependency(&self) -> u32 {
+        self.dependency
+    }
+
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+
+    pub fn get_weight(&self) -> u16 {
+        self.weight
+    }
+
+    fn parse(bytes: &[u8]) -> Self {
+        let dependency = read_u32(bytes);
+        Self {
+            dependency: dependency & 0x7fff_ffff,
+            exclusive: dependency & 0x8000_0000 != 0,
+            weight: bytes[4] as u16 + 1
+        }
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let exclusive = match self.exclusive {
+            true => 0x8000_0000,
+            false => 0
+        };
+        out.extend_from_slice(&(self.dependency | exclusive).to_be_bytes());
+        out.push((self.weight - 1) as u8);
+    }
+}
+
+/// One HTTP/2 frame, header blocks are left HPACK encoded
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Frame {
+    /// `padding` counts the bytes that padded the frame, flow control counts them too
+    Data { stream_id: u32, data: Vec<u8>, end_stream: bool, padding: usize },
+    Headers { stream_id: u32, block: Vec<u8>, end_stream: bool, end_headers: bool, priority: Option<Priority> },
+    Priority { stream_id: u32, priority: Priority },
+    RstStream { stream_id: u32, error: ErrorCode },
+    /// Identifier and value pairs, see `Settings`
+    Settings { ack: bool, settings: Vec<(u16, u32)> },
+    PushPromise { stream_id: u32, promised_stream_id: u32, block: Vec<u8>, end_headers: bool },
+    Ping { ack: bool, data: [u8; 8] },
+    GoAway { last_stream_id: u32, error: ErrorCode, debug: Vec<u8> },
+    /// On stream 0 for the connection's window
+    WindowUpdate { stream_id: u32, increment: u32 },
+    Continuation { stream_id: u32, block: Vec<u8>, end_headers: bool },
+    /// Frames of extensions this server doesn't speak, to be ignored
+    Unknown { kind: u8, flags: u8, stream_id: u32, payload: Vec<u8> }
+}
+
+impl Frame {
+    /// The frame at the start of `buffer` and its size, `None` until all of it arrived
+    pub fn parse(buffer: &[u8], max_frame_size: u32) -> Result<Option<(Self, usize)>, H2Error> {
+        if buffer.len() < FRAME_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let length = (buffer[0] as usize) << 16 | (buffer[1] as usize) << 8 | buffer[2] as usize;
+        let (kind, flags) = (buffer[3], buffer[4]);
+        let stream_id = read_u32(&buffer[5..9]) & 0x7fff_ffff;
+
+        if length > max_frame_size as usize {
+            return Err(H2Error::connection(ErrorCode::FrameSizeError, "Frame larger than SETTINGS_MAX_FRAME_SIZE"));
+        }
+        if buffer.len() < FRAME_HEADER_SIZE + length {
+            return Ok(None);
+        }
+
+        let payload = &buffer[FRAME_HEADER_SIZE..FRAME_HEADER_SIZE + length];
+        let frame = Self::parse_payload(kind, flags, stream_id, payload)?;
+        Ok(Some((frame, FRAME_HEADER_SIZE + length)))
+    }
+
+    fn parse_payload(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Result<Self, H2Error> {
+        let on_stream = |name: &str| match stream_id {
+            0 => Err(H2Error::connection(ErrorCode::ProtocolError, &format!("{} frame on stream 0", name))),
+            _ => Ok(())
+        };
+        let on_connection = |name: &str| match stream_id {
+            0 => Ok(()),
+            _ => Err(H2Error::connection(ErrorCode::ProtocolError, &format!("{} frame on stream {}", name, stream_id)))
+        };
+        let sized = |name: &str, size: usize| match payload.len() == size {
+            true => Ok(()),
+            false => Err(H2Error::connection(ErrorCode::FrameSizeError, &format!("{} frame of {} bytes", name, payload.len())))
+        };
+
+        let frame = match kind {
+            DATA => {
+                on_stream("DATA")?;
+                let data = unpad(flags, payload)?;
+                Frame::Data {
+                    stream_id,
+                    data: data.to_vec(),
+                    end_stream: flags & END_STREAM != 0,
+                    padding: payload.len() - data.len()
+                }
+            },
+            HEADERS => {
+                on_stream("HEADERS")?;
+                let block = unpad(flags, payload)?;
+                let (priority, block) = match flags & PRIORITY_FLAG != 0 {
+                    true if block.len() < 5 => return Err(H2Error::connection(ErrorCode::FrameSizeError, "HEADERS frame too short for its priority")),
+                    true => (Some(Priority::parse(block)), &block[5..]),
+                    false => (None, block)
+                };
+                Frame::Headers {
+                    stream_id,
+                    block: block.to_vec(),
+                    end_stream: flags & END_STREAM != 0,
+                    end_headers: flags & END_HEADERS != 0,
+                    priority
+                }
+            },
+            PRIORITY => {
+                on_stream("PRIORITY")?;
+                if payload.len() != 5 {
+                    return Err(H2Error::stream(stream_id, ErrorCode::FrameSizeError, "PRIORITY frame not 5 bytes"));
+                }
+                Frame::Priority { stream_id, priority: Priority::parse(payload) }
+            },
+            RST_STREAM => {
+                on_stream("RST_STREAM")?;
+                sized("RST_STREAM", 4)?;
+                Frame::RstStream { stream_id, error: ErrorCode::from_u32(read_u32(payload)) }
+            },
+            SETTINGS => {
+                on_connection("SETTINGS")?;
+                let ack = flags & ACK != 0;
+                if (ack && !payload.is_empty()) || !payload.len().is_multiple_of(6) {
+                    return Err(H2Error::connection(ErrorCode::FrameSizeError, "Malformed SETTINGS frame"));
+                }
+                let settings = payload.chunks(6)
+                    .map(|setting| (u16::from_be_bytes([setting[0], setting[1]]), read_u32(&setting[2..])))
+                    .collect();
+                Frame::Settings { ack, settings }
+            },
+            PUSH_PROMISE => {
+                on_stream("PUSH_PROMISE")?;
+                let block = unpad(flags, payload)?;
+                if block.len() < 4 {
+                    return Err(H2Error::connection(ErrorCode::FrameSizeError, "PUSH_PROMISE frame too short"));
+                }
+                Frame::PushPromise {
+                    stream_id,
+                    promised_stream_id: read_u32(block) & 0x7fff_ffff,
+                    block: block[4..].to_vec(),
+                    end_headers: flags & END_HEADERS != 0
+                }
+            },
+            PING => {
+                on_connection("PING")?;
+                sized("PING", 8)?;
+                Frame::Ping { ack: flags & ACK != 0, data: payload.try_into().unwrap_or_default() }
+            },
+            GOAWAY => {
+                on_connection("GOAWAY")?;
+                if payload.len() < 8 {
+                    return Err(H2Error::connection(ErrorCode::FrameSizeError, "GOAWAY frame too short"));
+                }
+                Frame::GoAway {
+                    last_stream_id: read_u32(payload) & 0x7fff_ffff,
+                    error: ErrorCode::from_u32(read_u32(&payload[4..])),
+                    debug: payload[8..].to_vec()
+                }
+            },
+            WINDOW_UPDATE => {
+                sized("WINDOW_UPDATE", 4)?;
+                let increment = read_u32(payload) & 0x7fff_ffff;
+                match (increment, stream_id) {
+                    (0, 0) => return Err(H2Error::connection(ErrorCode::ProtocolError, "WINDOW_UPDATE of 0")),
+                    (0, _) => return Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, "WINDOW_UPDATE of 0")),
+                    _ => Frame::WindowUpdate { stream_id, increment }
+                }
+            },
+            CONTINUATION => {
+                on_stream("CONTINUATION")?;
+                Frame::Continuation { stream_id, block: payload.to_vec(), end_headers: flags & END_HEADERS != 0 }
+            },
+            kind => Frame::Unknown { kind, flags, stream_id, payload: payload.to_vec() }
+        };
+
+        Ok(frame)
+    }
+
+    /// The frame as sent on the wire
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![];
+        let (kind, flags, stream_id) = match self {
+            Frame::Data { stream_id, data, end_stream, padding } => {
+                let flags = flag(*end_stream, END_STREAM) | pad(&mut payload, *padding);
+                payload.extend_from_slice(data);
+                payload.resize(payload.len() + padding.saturating_sub(1), 0);
+                (DATA, flags, *stream_id)
+            },
+            Frame::Headers { stream_id, block, end_stream, end_headers, priority } => {
+                if let Some(priority) = priority {
+                    priority.encode(&mut payload);
+                }
+                payload.extend_from_slice(block);
+                let flags = flag(*end_stream, END_STREAM) | flag(*end_headers, END_HEADERS) | flag(priority.is_some(), PRIORITY_FLAG);
+                (HEADERS, flags, *stream_id)
+            },
+            Frame::Priority { stream_id, priority } => {
+                priority.encode(&mut payload);
+                (PRIORITY, 0, *stream_id)
+            },
+            Frame::RstStream { stream_id, error } => {
+                payload.extend_from_slice(&error.to_u32().to_be_bytes());
+                (RST_STREAM, 0, *stream_id)
+            },
+            Frame::Settings { ack, settings } => {
+                for (id, value) in settings {
+                    payload.extend_from_slice(&id.to_be_bytes());
+                    payload.extend_from_slice(&value.to_be_bytes());
+                }
+                (SETTINGS, flag(*ack, ACK), 0)
+            },
+            Frame::PushPromise { stream_id, promised_stream_id, block, end_headers } => {
+                payload.extend_from_slice(&promised_stream_id.to_be_bytes());
+                payload.extend_from_slice(block);
+                (PUSH_PROMISE, flag(*end_headers, END_HEADERS), *stream_id)
+            },
+            Frame::Ping { ack, data } => {
+                payload.extend_from_slice(data);
+                (PING, flag(*ack, ACK), 0)
+            },
+            Frame::GoAway { last_stream_id, error, debug } => {
+                payload.extend_from_slice(&last_stream_id.to_be_bytes());
+                payload.extend_from_slice(&error.to_u32().to_be_bytes());
+                payload.extend_from_slice(debug);
+                (GOAWAY, 0, 0)
+            },
+            Frame::WindowUpdate { stream_id, increment } => {
+                payload.extend_from_slice(&increment.to_be_bytes());
+                (WINDOW_UPDATE, 0, *stream_id)
+            },
+            Frame::Continuation { stream_id, block, end_headers } => {
+                payload.extend_from_slice(block);
+                (CONTINUATION, flag(*end_headers, END_HEADERS), *stream_id)
+            },
+            Frame::Unknown { kind, flags, stream_id, payload: unknown } => {
+                payload.extend_from_slice(unknown);
+                (*kind, *flags, *stream_id)
+            }
+        };
+
+        let mut frame = Vec::with_capacity(FRAME_HEADER_SIZE + payload.len());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes()[1..]);
+        frame.push(kind);
+        frame.push(flags);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(&payload);
+        frame
+    }
+
+    /// 0 for frames about the whole connection
+    pub fn get_stream_id(&self) -> u32 {
+        match self {
+            Frame::Data { stream_id, .. }
+            | Frame::Headers { stream_id, .. }
+            | Frame::Priority { stream_id, .. }
+            | Frame::RstStream { stream_id, .. }
+            | Frame::PushPromise { stream_id, .. }
+            | Frame::WindowUpdate { stream_id, .. }
+            | Frame::Continuation { stream_id, .. }
+            | Frame::Unknown { stream_id, .. } => *stream_id,
+            Frame::Settings { .. } | Frame::Ping { .. } | Frame::GoAway { .. } => 0
+        }
+    }
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn flag(set: bool, flag: u8) -> u8 {
+    match set {
+        true => flag,
+        false => 0
+    }
+}
+
+/// Write the pad length for `padding` bytes of padding, the pad length byte included
+fn pad(payload: &mut Vec<u8>, padding: usize) -> u8 {
+    match padding {
+        0 => 0,
+        padding => {
+            payload.push((padding - 1).min(255) as u8);
+            PADDED
+        }
+    }
+}
+
+/// The payload without its pad length and padding
+fn unpad(flags: u8, payload: &[u8]) -> Result<&[u8], H2Error> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+
+    match payload.split_first() {
+        Some((&pad_length, rest)) if (pad_length as usize) <= rest.len() => Ok(&rest[..rest.len() - pad_length as usize]),
+        _ => Err(H2Error::connection(ErrorCode::ProtocolError, "Padding longer than the frame"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FRAME_HEADER_SIZE, Frame, Priority};
+    use super::super::{ErrorCode, H2Error};
+
+    const MAX_FRAME_SIZE: u32 = 16384;
+
+    /// A frame as sent on the wire, `payload` after the 9 byte header
+    fn raw(kind: u8, flags: u8, stream_id: u32, payload: &[u8]) -> Vec<u8> {
+        let mut frame = (payload.len() as u32).to_be_bytes()[1..].to_vec();
+        frame.extend_from_slice(&[kind, flags]);
+        frame.extend_from_slice(&stream_id.to_be_bytes());
+        frame.extend_from_slice(payload);
+        frame
+    }
+
+    fn parse_error(frame: &[u8]) -> H2Error {
+        Frame::parse(frame, MAX_FRAME_SIZE).unwrap_err()
+    }
+
+    #[test]
+    fn frames_round_trip() {
+        let frames = vec![
+            Frame::Data { stream_id: 1, data: b"hello".to_vec(), end_stream: true, padding: 0 },
+            Frame::Data { stream_id: 3, data: b"padded".to_vec(), end_stream: false, padding: 8 },
+            Frame::Data { stream_id: 3, data: vec![], end_stream: true, padding: 1 },
+            Frame::Headers { stream_id: 1, block: vec![0x82, 0x86], end_stream: true, end_headers: true, priority: None },
+            Frame::Headers { stream_id: 5, block: vec![0x82], end_stream: false, end_headers: false, priority: Some(Priority::new(3, true, 256)) },
+            Frame::Priority { stream_id: 7, priority: Priority::new(0, false, 1) },
+            Frame::RstStream { stream_id: 1, error: ErrorCode::Cancel },
+            Frame::Settings { ack: false, settings: vec![(0x3, 100), (0x4, 65535)] },
+            Frame::Settings { ack: true, settings: vec![] },
+            Frame::PushPromise { stream_id: 1, promised_stream_id: 2, block: vec![0x82, 0x87], end_headers: true },
+            Frame::Ping { ack: true, data: [1, 2, 3, 4, 5, 6, 7, 8] },
+            Frame::GoAway { last_stream_id: 9, error: ErrorCode::EnhanceYourCalm, debug: b"calm".to_vec() },
+            Frame::WindowUpdate { stream_id: 0, increment: 0x7fff_ffff },
+            Frame::Continuation { stream_id: 1, block: vec![0x84], end_headers: true },
+            Frame::Unknown { kind: 0xfa, flags: 0x3, stream_id: 11, payload: b"extension".to_vec() }
+        ];
+
+        for frame in frames {
+            let encoded = frame.encode();
+            assert_eq!(Frame::parse(&encoded, MAX_FRAME_SIZE).unwrap(), Some((frame, encoded.len())));
+        }
+    }
+
+    #[test]
+    fn wire_format() {
+        let frame = Frame::WindowUpdate { stream_id: 1, increment: 1000 };
+        assert_eq!(frame.encode(), raw(0x8, 0, 1, &1000u32.to_be_bytes()));
+
+        let ping = Frame::Ping { ack: false, data: [0; 8] };
+        assert_eq!(ping.encode(), raw(0x6, 0, 0, &[0; 8]));
+
+        // The pad length byte counts as padding
+        let data = Frame::Data { stream_id: 1, data: vec![0xaa], end_stream: true, padding: 3 };
+        assert_eq!(data.encode(), raw(0x0, 0x9, 1, &[2, 0xaa, 0, 0]));
+    }
+
+    #[test]
+    fn partial_frames_wait_for_the_rest() {
+        let encoded = Frame::Ping { ack: false, data: [7; 8] }.encode();
+        for end in 0..encoded.len() {
+            assert_eq!(Frame::parse(&encoded[..end], MAX_FRAME_SIZE).unwrap(), None);
+        }
+
+        // Only the first frame of the buffer is parsed
+        let mut two = encoded.clone();
+        two.extend_from_slice(&encoded);
+        assert_eq!(Frame::parse(&two, MAX_FRAME_SIZE).unwrap().unwrap().1, FRAME_HEADER_SIZE + 8);
+    }
+
+    #[test]
+    fn frames_over_the_max_size_are_rejected_before_arriving() {
+        // Only the header arrived, its length is enough to reject it
+        let mut oversized = raw(0x0, 0, 1, &[]);
+        oversized[..3].copy_from_slice(&(MAX_FRAME_SIZE + 1).to_be_bytes()[1..]);
+        assert_eq!(parse_error(&oversized).get_code(), ErrorCode::FrameSizeError);
+
+        let exact = raw(0x0, 0, 1, &vec![0; MAX_FRAME_SIZE as usize]);
+        assert!(Frame::parse(&exact, MAX_FRAME_SIZE).unwrap().is_some());
+    }
+
+    #[test]
+    fn fixed_size_frames_of_the_wrong_length_are_rejected() {
+        assert_eq!(parse_error(&raw(0x3, 0, 1, &[0; 3])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x6, 0, 0, &[0; 9])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x8, 0, 1, &[0; 5])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x4, 0, 0, &[0; 7])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x4, 0x1, 0, &[0; 6])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x7, 0, 0, &[0; 7])).get_code(), ErrorCode::FrameSizeError);
+        assert_eq!(parse_error(&raw(0x5, 0, 1, &[0; 3])).get_code(), ErrorCode::FrameSizeError);
+        // HEADERS with the priority flag need room for the priority
+        assert_eq!(parse_error(&raw(0x1, 0x20, 1, &[0; 4])).get_code(), ErrorCode::FrameSizeError);
+        // A bad PRIORITY only resets its stream
+        assert_eq!(parse_error(&raw(0x2, 0, 3, &[0; 4])), H2Error::stream(3, ErrorCode::FrameSizeError, "PRIORITY frame not 5 bytes"));
+    }
+
+    #[test]
+    fn padding_longer_than_the_frame_is_rejected() {
+        assert_eq!(parse_error(&raw(0x0, 0x8, 1, &[4, 0xaa, 0, 0])).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x1, 0x8, 1, &[])).get_code(), ErrorCode::ProtocolError);
+        // Padding filling the whole frame leaves no data
+        assert_eq!(
+            Frame::parse(&raw(0x0, 0x8, 1, &[3, 0, 0, 0]), MAX_FRAME_SIZE).unwrap().unwrap().0,
+            Frame::Data { stream_id: 1, data: vec![], end_stream: false, padding: 4 }
+        );
+    }
+
+    #[test]
+    fn frames_on_the_wrong_stream_are_rejected() {
+        assert_eq!(parse_error(&raw(0x0, 0, 0, b"data")).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x1, 0x4, 0, &[0x82])).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x9, 0x4, 0, &[0x82])).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x4, 0, 1, &[])).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x6, 0, 1, &[0; 8])).get_code(), ErrorCode::ProtocolError);
+        assert_eq!(parse_error(&raw(0x7, 0, 1, &[0; 8])).get_code(), ErrorCode::ProtocolError);
+    }
+
+    #[test]
+    fn zero_window_updates_are_rejected() {
+        assert!(matches!(parse_error(&raw(0x8, 0, 0, &[0; 4])), H2Error::Connection(ErrorCode::ProtocolError, _)));
+        assert!(matches!(parse_error(&raw(0x8, 0, 5, &[0; 4])), H2Error::Stream(5, ErrorCode::ProtocolError, _)));
+        // The reserved bit is ignored
+        assert_eq!(
+            Frame::parse(&raw(0x8, 0, 5, &[0x80, 0, 0, 1]), MAX_FRAME_SIZE).unwrap().unwrap().0,
+            Frame::WindowUpdate { stream_id: 5, increment: 1 }
+        );
+    }
+}
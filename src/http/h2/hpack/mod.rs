@@ -0,0 +1,540 @@
+pub mod huffman;
+pub mod table;
+
+use self::table::{DynamicTable, Match, entry_size};
+use super::{ErrorCode, H2Error};
+
+/// Both ends start with dynamic tables of this size
+pub const DEFAULT_TABLE_SIZE: usize = 4096;
+
+/// Compresses header lists into header blocks, remembering headers in its dynamic table for the next blocks
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    table: DynamicTable,
+    // The smallest size the table went down to since the last block, the decoder has to hear of it
+    resized_to: Option<usize>
+}
+
+impl Encoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Follow the decoder's SETTINGS_HEADER_TABLE_SIZE, the table never grows past the default size
+    pub fn set_max_table_size(&mut self, size: usize) {
+        let size = size.min(DEFAULT_TABLE_SIZE);
+        if size == self.table.get_max_size() {
+            return;
+        }
+
+        self.resized_to = Some(self.resized_to.map_or(size, |smallest| smallest.min(size)));
+        self.table.set_max_size(size);
+    }
+
+    /// Names are sent lowercase, credentials are never indexed
+    pub fn encode(&mut self, headers: &[(String, String)]) -> Vec<u8> {
+        let mut block = vec![];
+
+        if let Some(smallest) = self.resized_to.take() {
+            if smallest < self.table.get_max_size() {
+                encode_integer(smallest, 5, 0x20, &mut block);
+            }
+            encode_integer(self.table.get_max_size(), 5, 0x20, &mut block);
+        }
+
+        for (name, value) in headers {
+            let name = name.to_ascii_lowercase();
+            let found = self.table.find(&name, value);
+
+            // Short secrets are easy to guess one request at a time through the table, keep them out of it for good
+            let sensitive = name == "authorization" || name == "proxy-authorization" || (name == "cookie" && value.len() < 20);
+            let (prefix, flags) = match (sensitive, &found) {
+                (true, _) => (4, 0x10),
+                (false, Some(Match::Full(index))) => {
+                    encode_integer(*index, 7, 0x80, &mut block);
+                    continue;
+                },
+                // Too large to keep, it would only empty the table
+                (false, _) if entry_size(&name, value) > self.table.get_max_size() => (4, 0x00),
+                (false, _) => (6, 0x40)
+            };
+
+            match found {
+                Some(Match::Full(index)) | Some(Match::Name(index)) => encode_integer(index, prefix, flags, &mut block),
+                None => {
+                    block.push(flags);
+                    encode_string(name.as_bytes(), &mut block);
+                }
+            }
+            encode_string(value.as_bytes(), &mut block);
+
+            if flags == 0x40 {
+                self.table.insert(name, value.clone());
+            }
+        }
+
+        block
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            resized_to: None
+        }
+    }
+}
+
+/// Expands header blocks back into header lists, any error leaves the connection's compression state broken
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    table: DynamicTable,
+    // What our SETTINGS_HEADER_TABLE_SIZE lets the encoder use
+    max_table_size: usize,
+    // Our SETTINGS_MAX_HEADER_LIST_SIZE, a few bytes of indexed fields could otherwise expand without bound
+    max_header_list_size: Option<usize>
+}
+
+impl Decoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Our SETTINGS_HEADER_TABLE_SIZE, once the other end acknowledged it
+    pub fn set_max_table_size(&mut self, size: usize) {
+        self.max_table_size = size;
+        if self.table.get_max_size() > size {
+            self.table.set_max_size(size);
+        }
+    }
+
+    /// Our SETTINGS_MAX_HEADER_LIST_SIZE, blocks expanding past it end the connection
+    pub fn set_max_header_list_size(&mut self, size: Option<usize>) {
+        self.max_header_list_size = size;
+    }
+
+    pub fn decode(&mut self, block: &[u8]) -> Result<Vec<(String, String)>, H2Error> {
+        let mut headers = vec![];
+        let mut position = 0;
+        // Sized as in RFC 7541 4.1, each field counts 32 bytes on top of its name and value
+        let mut list_size = 0;
+
+        while position < block.len() {
+            let byte = block[position];
+            match byte {
+                // Indexed
+                0x80..=0xff => {
+                    let index = decode_integer(block, &mut position, 7)?;
+                    headers.push(self.lookup(index)?);
+                },
+                // Literal with incremental indexing
+                0x40..=0x7f => {
+                    let (name, value) = self.decode_literal(block, &mut position, 6)?;
+                    self.table.insert(name.clone(), value.clone());
+                    headers.push((name, value));
+                },
+                // Dynamic table size update
+                0x20..=0x3f => {
+                    if !headers.is_empty() {
+                        return Err(compression_error("Dynamic table size update after a header"));
+                    }
+                    let size = decode_integer(block, &mut position, 5)?;
+                    if size > self.max_table_size {
+                        return Err(compression_error("Dynamic table size update above SETTINGS_HEADER_TABLE_SIZE"));
+                    }
+                    self.table.set_max_size(size);
+                },
+                // Literal without indexing or never indexed
+                _ => headers.push(self.decode_literal(block, &mut position, 4)?)
+            }
+
+            if let Some((name, value)) = headers.last() {
+                list_size += entry_size(name, value);
+                if self.max_header_list_size.is_some_and(|max| list_size > max) {
+                    return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "Header list larger than SETTINGS_MAX_HEADER_LIST_SIZE"));
+                }
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn lookup(&self, index: usize) -> Result<(String, String), H2Error> {
+        self.table.get(index).ok_or_else(|| compression_error(&format!("No header at index {}", index)))
+    }
+
+    fn decode_literal(&self, block: &[u8], position: &mut usize, prefix: u8) -> Result<(String, String), H2Error> {
+        let name = match decode_integer(block, position, prefix)? {
+            0 => decode_string(block, position)?,
+            index => self.lookup(index)?.0
+        };
+        let value = decode_string(block, position)?;
+        Ok((name, value))
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Self {
+            table: DynamicTable::new(DEFAULT_TABLE_SIZE),
+            max_table_size: DEFAULT_TABLE_SIZE,
+            max_header_list_size: None
+        }
+    }
+}
+
+/// An integer in the low `prefix` bits of the first byte, continued 7 bits at a time if it doesn't fit
+fn encode_integer(value: usize, prefix: u8, flags: u8, out: &mut Vec<u8>) {
+    let max = (1usize << prefix) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn decode_integer(block: &[u8], position: &mut usize, prefix: u8) -> Result<usize, H2Error> {
+    let max = (1usize << prefix) - 1;
+    let mut value = block[*position] as usize & max;
+    *position += 1;
+    if value < max {
+        return Ok(value);
+    }
+
+    let mut shift = 0;
+    loop {
+        let byte = *block.get(*position).ok_or_else(|| compression_error("Truncated integer"))?;
+        *position += 1;
+        if shift > 28 {
+            return Err(compression_error("Integer too large"));
+        }
+
+        value += ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// Huffman coded unless that's longer
+fn encode_string(bytes: &[u8], out: &mut Vec<u8>) {
+    let huffman_len = huffman::encoded_len(bytes);
+    match huffman_len <= bytes.len() {
+        true => {
+            encode_integer(huffman_len, 7, 0x80, out);
+            huffman::encode(bytes, out);
+        },
+        false => {
+            encode_integer(bytes.len(), 7, 0x00, out);
+            out.extend_from_slice(bytes);
+        }
+    }
+}
+
+fn decode_string(block: &[u8], position: &mut usize) -> Result<String, H2Error> {
+    let huffman_coded = block.get(*position).ok_or_else(|| compression_error("Missing string"))? & 0x80 != 0;
+    let length = decode_integer(block, position, 7)?;
+    let end = position.checked_add(length).filter(|&end| end <= block.len()).ok_or_else(|| compression_error("Truncated string"))?;
+    let bytes = &block[*position..end];
+    *position = end;
+
+    let bytes = match huffman_coded {
+        true => huffman::decode(bytes)?,
+        false => bytes.to_vec()
+    };
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+fn compression_error(message: &str) -> H2Error {
+    H2Error::connection(ErrorCode::CompressionError, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Decoder, Encoder, decode_integer, encode_integer, huffman};
+    use super::super::ErrorCode;
+
+    /// Bytes of an RFC 7541 hex dump, whitespace ignored
+    fn hex(dump: &str) -> Vec<u8> {
+        let digits: Vec<u8> = dump.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+        digits.chunks(2).map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap(), 16).unwrap()).collect()
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter().map(|&(name, value)| (String::from(name), String::from(value))).collect()
+    }
+
+    /// Dynamic table entries from index 62 on, newest first, and the table size
+    fn assert_table(decoder: &Decoder, entries: &[(&str, &str)], size: usize) {
+        for (i, entry) in headers(entries).into_iter().enumerate() {
+            assert_eq!(decoder.table.get(62 + i), Some(entry));
+        }
+        assert_eq!(decoder.table.get(62 + entries.len()), None);
+        assert_eq!(decoder.table.get_size(), size);
+    }
+
+    fn integer(value: usize, prefix: u8) -> Vec<u8> {
+        let mut out = vec![];
+        encode_integer(value, prefix, 0, &mut out);
+        out
+    }
+
+    fn decoded_integer(block: &[u8], prefix: u8) -> usize {
+        let mut position = 0;
+        let value = decode_integer(block, &mut position, prefix).unwrap();
+        assert_eq!(position, block.len());
+        value
+    }
+
+    const REQUESTS: [&[(&str, &str)]; 3] = [
+        &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com")],
+        &[(":method", "GET"), (":scheme", "http"), (":path", "/"), (":authority", "www.example.com"), ("cache-control", "no-cache")],
+        &[(":method", "GET"), (":scheme", "https"), (":path", "/index.html"), (":authority", "www.example.com"), ("custom-key", "custom-value")]
+    ];
+
+    const RESPONSES: [&[(&str, &str)]; 3] = [
+        &[(":status", "302"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com")],
+        &[(":status", "307"), ("cache-control", "private"), ("date", "Mon, 21 Oct 2013 20:13:21 GMT"), ("location", "https://www.example.com")],
+        &[
+            (":status", "200"),
+            ("cache-control", "private"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT"),
+            ("location", "https://www.example.com"),
+            ("content-encoding", "gzip"),
+            ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1")
+        ]
+    ];
+
+    /// Tables after each request of C.3 and C.4
+    const REQUEST_TABLES: [(&[(&str, &str)], usize); 3] = [
+        (&[(":authority", "www.example.com")], 57),
+        (&[("cache-control", "no-cache"), (":authority", "www.example.com")], 110),
+        (&[("custom-key", "custom-value"), ("cache-control", "no-cache"), (":authority", "www.example.com")], 164)
+    ];
+
+    /// Tables after each response of C.5 and C.6, with a 256 byte table
+    const RESPONSE_TABLES: [(&[(&str, &str)], usize); 3] = [
+        (&[
+            ("location", "https://www.example.com"),
+            ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("cache-control", "private"),
+            (":status", "302")
+        ], 222),
+        (&[
+            (":status", "307"),
+            ("location", "https://www.example.com"),
+            ("date", "Mon, 21 Oct 2013 20:13:21 GMT"),
+            ("cache-control", "private")
+        ], 222),
+        (&[
+            ("set-cookie", "foo=ASDJKHQKBZXOQWEOPIUAXQWEOIU; max-age=3600; version=1"),
+            ("content-encoding", "gzip"),
+            ("date", "Mon, 21 Oct 2013 20:13:22 GMT")
+        ], 215)
+    ];
+
+    #[test]
+    fn integers_match_c_1() {
+        assert_eq!(integer(10, 5), vec![0x0a]);
+        assert_eq!(integer(1337, 5), vec![0x1f, 0x9a, 0x0a]);
+        assert_eq!(integer(42, 8), vec![0x2a]);
+
+        assert_eq!(decoded_integer(&[0x0a], 5), 10);
+        assert_eq!(decoded_integer(&[0x1f, 0x9a, 0x0a], 5), 1337);
+        assert_eq!(decoded_integer(&[0x2a], 8), 42);
+    }
+
+    #[test]
+    fn integers_on_the_prefix_boundary() {
+        assert_eq!(integer(30, 5), vec![0x1e]);
+        assert_eq!(integer(31, 5), vec![0x1f, 0x00]);
+        assert_eq!(decoded_integer(&[0x1f, 0x00], 5), 31);
+        assert_eq!(decoded_integer(&[0xe0 | 0x1e], 5), 30);
+    }
+
+    #[test]
+    fn truncated_and_oversized_integers_are_rejected() {
+        let mut position = 0;
+        assert!(decode_integer(&[0x1f, 0x9a], &mut position, 5).is_err());
+
+        let mut position = 0;
+        assert!(decode_integer(&[0x1f, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01], &mut position, 5).is_err());
+    }
+
+    #[test]
+    fn requests_without_huffman_decode_as_c_3() {
+        let blocks = [
+            "8286 8441 0f77 7777 2e65 7861 6d70 6c65 2e63 6f6d",
+            "8286 84be 5808 6e6f 2d63 6163 6865",
+            "8287 85bf 400a 6375 7374 6f6d 2d6b 6579 0c63 7573 746f 6d2d 7661 6c75 65"
+        ];
+
+        let mut decoder = Decoder::new();
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), headers(REQUESTS[i]));
+            assert_table(&decoder, REQUEST_TABLES[i].0, REQUEST_TABLES[i].1);
+        }
+    }
+
+    #[test]
+    fn requests_with_huffman_match_c_4() {
+        let blocks = [
+            "8286 8441 8cf1 e3c2 e5f2 3a6b a0ab 90f4 ff",
+            "8286 84be 5886 a8eb 1064 9cbf",
+            "8287 85bf 4088 25a8 49e9 5ba9 7d7f 8925 a849 e95b b8e8 b4bf"
+        ];
+
+        let (mut encoder, mut decoder) = (Encoder::new(), Decoder::new());
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(encoder.encode(&headers(REQUESTS[i])), hex(block));
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), headers(REQUESTS[i]));
+            assert_table(&decoder, REQUEST_TABLES[i].0, REQUEST_TABLES[i].1);
+        }
+    }
+
+    #[test]
+    fn responses_without_huffman_decode_as_c_5() {
+        let blocks = [
+            "4803 3330 3258 0770 7269 7661 7465 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133
+             2032 303a 3133 3a32 3120 474d 546e 1768 7474 7073 3a2f 2f77 7777 2e65 7861 6d70
+             6c65 2e63 6f6d",
+            "4803 3330 37c1 c0bf",
+            "88c1 611d 4d6f 6e2c 2032 3120 4f63 7420 3230 3133 2032 303a 3133 3a32 3220 474d
+             54c0 5a04 677a 6970 7738 666f 6f3d 4153 444a 4b48 514b 425a 584f 5157 454f 5049
+             5541 5851 5745 4f49 553b 206d 6178 2d61 6765 3d33 3630 303b 2076 6572 7369 6f6e
+             3d31"
+        ];
+
+        let mut decoder = Decoder::new();
+        decoder.set_max_table_size(256);
+        for (i, block) in blocks.iter().enumerate() {
+            assert_eq!(decoder.decode(&hex(block)).unwrap(), headers(RESPONSES[i]));
+            assert_table(&decoder, RESPONSE_TABLES[i].0, RESPONSE_TABLES[i].1);
+        }
+    }
+
+    #[test]
+    fn responses_with_huffman_match_c_6() {
+        let blocks = [
+            "4882 6402 5885 aec3 771a 4b61 96d0 7abe 9410 54d4 44a8 2005 9504 0b81 66e0 82a6
+             2d1b ff6e 919d 29ad 1718 63c7 8f0b 97c8 e9ae 82ae 43d3",
+            "4883 640e ffc1 c0bf",
+            "88c1 6196 d07a be94 1054 d444 a820 0595 040b 8166 e084 a62d 1bff c05a 839b d9ab
+             77ad 94e7 821d d7f2 e6c7 b335 dfdf cd5b 3960 d5af 2708 7f36 72c1 ab27 0fb5 291f
+             9587 3160 65c0 03ed 4ee5 b106 3d50 07"
+        ];
+
+        let (mut encoder, mut decoder) = (Encoder::new(), Decoder::new());
+        encoder.set_max_table_size(256);
+        decoder.set_max_table_size(256);
+        for (i, block) in blocks.iter().enumerate() {
+            let mut expected = match i {
+                // The encoder announces its smaller table in the first block
+                0 => vec![0x3f, 0xe1, 0x01],
+                _ => vec![]
+            };
+            expected.extend(hex(block));
+
+            assert_eq!(encoder.encode(&headers(RESPONSES[i])), expected);
+            assert_eq!(decoder.decode(&expected).unwrap(), headers(RESPONSES[i]));
+            assert_table(&decoder, RESPONSE_TABLES[i].0, RESPONSE_TABLES[i].1);
+        }
+    }
+
+    #[test]
+    fn credentials_are_never_indexed() {
+        let (mut encoder, mut decoder) = (Encoder::new(), Decoder::new());
+        let block = encoder.encode(&headers(&[("Authorization", "Bearer secret")]));
+
+        assert_eq!(block[0] & 0xf0, 0x10);
+        assert_eq!(decoder.decode(&block).unwrap(), headers(&[("authorization", "Bearer secret")]));
+        assert_table(&decoder, &[], 0);
+    }
+
+    #[test]
+    fn invalid_indexes_are_rejected() {
+        let mut decoder = Decoder::new();
+        assert!(decoder.decode(&[0x80]).is_err());
+        assert!(decoder.decode(&[0xbe]).is_err());
+        assert!(decoder.decode(&[0x7f, 0x00]).is_err());
+    }
+
+    #[test]
+    fn misplaced_or_oversized_table_updates_are_rejected() {
+        let mut decoder = Decoder::new();
+        assert_eq!(decoder.decode(&[0x82, 0x20]).unwrap_err().get_code(), ErrorCode::CompressionError);
+
+        decoder.set_max_table_size(256);
+        assert!(decoder.decode(&[0x3f, 0xe2, 0x01]).is_err());
+        assert!(decoder.decode(&[0x3f, 0xe1, 0x01, 0x82]).is_ok());
+    }
+
+    #[test]
+    fn truncated_strings_are_rejected() {
+        let mut decoder = Decoder::new();
+        assert!(decoder.decode(&[0x40]).is_err());
+        assert!(decoder.decode(&hex("400a 6375 7374 6f6d")).is_err());
+    }
+
+    #[test]
+    fn header_lists_past_the_limit_are_rejected() {
+        // One large entry in the dynamic table, then a one byte reference to it per field
+        let value = "a".repeat(4000);
+        let mut bomb = vec![0x40, 0x01, b'x'];
+        encode_integer(value.len(), 7, 0x00, &mut bomb);
+        bomb.extend_from_slice(value.as_bytes());
+        bomb.extend_from_slice(&[0xbe; 1000]);
+
+        let mut unlimited = Decoder::new();
+        assert_eq!(unlimited.decode(&bomb).unwrap().len(), 1001);
+
+        let mut decoder = Decoder::new();
+        decoder.set_max_header_list_size(Some(64 * 1024));
+        assert_eq!(decoder.decode(&bomb).unwrap_err().get_code(), ErrorCode::EnhanceYourCalm);
+
+        // Fields adding up to exactly the limit are fine
+        let mut decoder = Decoder::new();
+        decoder.set_max_header_list_size(Some(2 * (1 + 4000 + 32)));
+        assert_eq!(decoder.decode(&bomb[..bomb.len() - 999]).unwrap().len(), 2);
+        assert!(decoder.decode(&[0xbe, 0xbe, 0xbe]).is_err());
+    }
+
+    #[test]
+    fn huffman_round_trips() {
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        let samples: [&[u8]; 5] = [b"", b"a", b"www.example.com", b"Mon, 21 Oct 2013 20:13:21 GMT", &all_bytes];
+
+        for sample in samples.iter() {
+            let mut encoded = vec![];
+            huffman::encode(sample, &mut encoded);
+            assert_eq!(encoded.len(), huffman::encoded_len(sample));
+            assert_eq!(huffman::decode(&encoded).unwrap(), sample.to_vec());
+        }
+    }
+
+    #[test]
+    fn huffman_rejects_eos() {
+        assert!(huffman::decode(&[0xff, 0xff, 0xff, 0xff]).is_err());
+        assert!(huffman::decode(&[0x1f, 0xff, 0xff, 0xff, 0xff]).is_err());
+    }
+
+    #[test]
+    fn huffman_rejects_bad_padding() {
+        // Eight bits of padding
+        assert!(huffman::decode(&[0xff]).is_err());
+        assert!(huffman::decode(&[0x1f, 0xff]).is_err());
+        // Padding that isn't a prefix of EOS
+        assert!(huffman::decode(&[0x18]).is_err());
+        // Seven bits of padding are fine
+        assert_eq!(huffman::decode(&[0x1f]).unwrap(), b"a".to_vec());
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::VecDeque;
+
+/// Headers both ends know from the start, indexed from 1, RFC 7541 Appendix A
+pub const STATIC_TABLE: [(&str, &str); 61] = [
+    (":authority", ""),
+    (":method", "GET"),
+    (":method", "POST"),
+    (":path", "/"),
+    (":path", "/index.html"),
+    (":scheme", "http"),
+    (":scheme", "https"),
+    (":status", "200"),
+    (":status", "204"),
+    (":status", "206"),
+    (":status", "304"),
+    (":status", "400"),
+    (":status", "404"),
+    (":status", "500"),
+    ("accept-charset", ""),
+    ("accept-encoding", "gzip, deflate"),
+    ("accept-language", ""),
+    ("accept-ranges", ""),
+    ("accept", ""),
+    ("access-control-allow-origin", ""),
+    ("age", ""),
+    ("allow", ""),
+    ("authorization", ""),
+    ("cache-control", ""),
+    ("content-disposition", ""),
+    ("content-encoding", ""),
+    ("content-language", ""),
+    ("content-length", ""),
+    ("content-location", ""),
+    ("content-range", ""),
+    ("content-type", ""),
+    ("cookie", ""),
+    ("date", ""),
+    ("etag", ""),
+    ("expect", ""),
+    ("expires", ""),
+    ("from", ""),
+    ("host", ""),
+    ("if-match", ""),
+    ("if-modified-since", ""),
+    ("if-none-match", ""),
+    ("if-range", ""),
+    ("if-unmodified-since", ""),
+    ("last-modified", ""),
+    ("link", ""),
+    ("location", ""),
+    ("max-forwards", ""),
+    ("proxy-authenticate", ""),
+    ("proxy-authorization", ""),
+    ("range", ""),
+    ("referer", ""),
+    ("refresh", ""),
+    ("retry-after", ""),
+    ("server", ""),
+    ("set-cookie", ""),
+    ("strict-transport-security", ""),
+    ("transfer-encoding", ""),
+    ("user-agent", ""),
+    ("vary", ""),
+    ("via", ""),
+    ("www-authenticate", "")
+];
+
+/// How a header matched the tables
+pub enum Match {
+    /// Name and value, send the index alone
+    Full(usize),
+    /// Only the name, send the index and the value
+    Name(usize)
+}
+
+/// Headers the encoder chose to remember, newest first, indexed after the static table
+#[derive(Debug, Clone)]
+pub struct DynamicTable {
+    entries: VecDeque<(String, String)>,
+    size: usize,
+    max_size: usize
+}
+
+impl DynamicTable {
+    pub fn new(max_size: usize) -> Self {
+        Self {
+            entries: VecDeque::new(),
+            size: 0,
+            max_size
+        }
+    }
+
+    pub fn get_size(&self) -> usize {
+        self.size
+    }
+
+    pub fn get_max_size(&self) -> usize {
+        self.max_size
+    }
+
+    /// Evicts the oldest entries until the table fits
+    pub fn set_max_size(&mut self, max_size: usize) {
+        self.max_size = max_size;
+        self.evict(0);
+    }
+
+    /// An entry larger than the whole table empties it and isn't kept
+    pub fn insert(&mut self, name: String, value: String) {
+        let size = entry_size(&name, &value);
+        self.evict(size);
+        if size <= self.max_size {
+            self.size += size;
+            self.entries.push_front((name, value));
+        }
+    }
+
+    /// Look up an index of the static table or this one
+    pub fn get(&self, index: usize) -> Option<(String, String)> {
+        match index {
+            0 => None,
+            1..=61 => STATIC_TABLE.get(index - 1).map(|&(name, value)| (String::from(name), String::from(value))),
+            _ => self.entries.get(index - 62).cloned()
+        }
+    }
+
+    /// The best match across the static table and this one, a full match over one of just the name
+    pub fn find(&self, name: &str, value: &str) -> Option<Match> {
+        let indexed = STATIC_TABLE.iter().map(|&(name, value)| (name, value))
+            .chain(self.entries.iter().map(|(name, value)| (name.as_str(), value.as_str())))
+            .enumerate();
+
+        let mut name_match = None;
+        for (i, (entry_name, entry_value)) in indexed {
+            if entry_name != name {
+                continue;
+            }
+            if entry_value == value {
+                return Some(Match::Full(i + 1));
+            }
+            name_match = name_match.or(Some(Match::Name(i + 1)));
+        }
+        name_match
+    }
+
+    /// Make room for `incoming` bytes
+    fn evict(&mut self, incoming: usize) {
+        while self.size + incoming > self.max_size {
+            match self.entries.pop_back() {
+                None => break,
+                Some((name, value)) => self.size -= entry_size(&name, &value)
+            }
+        }
+    }
+}
+
+/// Each entry counts its name, value and 32 bytes of overhead
+pub fn entry_size(name: &str, value: &str) -> usize {
+    name.len() + value.len() + 32
+}
@@ -0,0 +1,105 @@
+pub mod frame;
+pub mod hpack;
+pub mod settings;
+
+use std::fmt;
+
+/// Every HTTP/2 connection opens with the client sending this, then its SETTINGS
+pub const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+/// Why a stream or connection was ended, sent in RST_STREAM and GOAWAY frames
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ErrorCode {
+    NoError,
+    ProtocolError,
+    InternalError,
+    FlowControlError,
+    SettingsTimeout,
+    StreamClosed,
+    FrameSizeError,
+    RefusedStream,
+    Cancel,
+    CompressionError,
+    ConnectError,
+    EnhanceYourCalm,
+    InadequateSecurity,
+    Http11Required,
+    /// Codes this server doesn't know are to be treated like `InternalError`
+    Unknown(u32)
+}
+
+impl ErrorCode {
+    pub fn from_u32(code: u32) -> Self {
+        match code {
+            0x0 => ErrorCode::NoError,
+            0x1 => ErrorCode::ProtocolError,
+            0x2 => ErrorCode::InternalError,
+            0x3 => ErrorCode::FlowControlError,
+            0x4 => ErrorCode::SettingsTimeout,
+            0x5 => ErrorCode::StreamClosed,
+            0x6 => ErrorCode::FrameSizeError,
+            0x7 => ErrorCode::RefusedStream,
+            0x8 => ErrorCode::Cancel,
+            0x9 => ErrorCode::CompressionError,
+            0xa => ErrorCode::ConnectError,
+            0xb => ErrorCode::EnhanceYourCalm,
+            0xc => ErrorCode::InadequateSecurity,
+            0xd => ErrorCode::Http11Required,
+            other => ErrorCode::Unknown(other)
+        }
+    }
+
+    pub fn to_u32(&self) -> u32 {
+        match self {
+            ErrorCode::NoError => 0x0,
+            ErrorCode::ProtocolError => 0x1,
+            ErrorCode::InternalError => 0x2,
+            ErrorCode::FlowControlError => 0x3,
+            ErrorCode::SettingsTimeout => 0x4,
+            ErrorCode::StreamClosed => 0x5,
+            ErrorCode::FrameSizeError => 0x6,
+            ErrorCode::RefusedStream => 0x7,
+            ErrorCode::Cancel => 0x8,
+            ErrorCode::CompressionError => 0x9,
+            ErrorCode::ConnectError => 0xa,
+            ErrorCode::EnhanceYourCalm => 0xb,
+            ErrorCode::InadequateSecurity => 0xc,
+            ErrorCode::Http11Required => 0xd,
+            ErrorCode::Unknown(code) => *code
+        }
+    }
+}
+
+/// A broken rule of the protocol, ending either one stream or the whole connection
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum H2Error {
+    /// Answered with a GOAWAY before closing the connection
+    Connection(ErrorCode, String),
+    /// Answered with a RST_STREAM, the connection carries on
+    Stream(u32, ErrorCode, String)
+}
+
+impl H2Error {
+    pub fn connection(code: ErrorCode, message: &str) -> Self {
+        H2Error::Connection(code, String::from(message))
+    }
+
+    pub fn stream(stream_id: u32, code: ErrorCode, message: &str) -> Self {
+        H2Error::Stream(stream_id, code, String::from(message))
+    }
+
+    pub fn get_code(&self) -> ErrorCode {
+        match self {
+            H2Error::Connection(code, _) | H2Error::Stream(_, code, _) => *code
+        }
+    }
+}
+
+impl fmt::Display for H2Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            H2Error::Connection(code, message) => write!(f, "{:?}: {}", code, message),
+            H2Error::Stream(stream_id, code, message) => write!(f, "{:?} on stream {}: {}", code, stream_id, message)
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use super::frame::Frame;
+use super::{ErrorCode, H2Error};
+
+pub const HEADER_TABLE_SIZE: u16 = 0x1;
+pub const ENABLE_PUSH: u16 = 0x2;
+pub const MAX_CONCURRENT_STREAMS: u16 = 0x3;
+pub const INITIAL_WINDOW_SIZE: u16 = 0x4;
+pub const MAX_FRAME_SIZE: u16 = 0x5;
+pub const MAX_HEADER_LIST_SIZE: u16 = 0x6;
+
+/// Flow control windows start at this size on every stream and on the connection
+pub const DEFAULT_WINDOW_SIZE: u32 = 65_535;
+pub const DEFAULT_MAX_FRAME_SIZE: u32 = 16_384;
+pub const MAX_WINDOW_SIZE: u32 = (1 << 31) - 1;
+const LARGEST_MAX_FRAME_SIZE: u32 = (1 << 24) - 1;
+
+/// What one end of a connection announced in its SETTINGS frames, the protocol's defaults until then
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Settings {
+    header_table_size: u32,
+    enable_push: bool,
+    max_concurrent_streams: Option<u32>,
+    initial_window_size: u32,
+    max_frame_size: u32,
+    max_header_list_size: Option<u32>
+}
+
+impl Settings {
+    /// The most the other end's HPACK encoder may keep in its dynamic table
+    pub fn get_header_table_size(&self) -> u32 {
+        self.header_table_size
+    }
+
+    pub fn get_enable_push(&self) -> bool {
+        self.enable_push
+    }
+
+    /// `None` for no limit
+    pub fn get_max_concurrent_streams(&self) -> Option<u32> {
+        self.max_concurrent_streams
+    }
+
+    pub fn get_initial_window_size(&self) -> u32 {
+        self.initial_window_size
+    }
+
+    pub fn get_max_frame_size(&self) -> u32 {
+        self.max_frame_size
+    }
+
+    /// `None` for no limit
+    pub fn get_max_header_list_size(&self) -> Option<u32> {
+        self.max_header_list_size
+    }
+
+    pub fn set_header_table_size(&mut self, size: u32) {
+        self.header_table_size = size;
+    }
+
+    pub fn set_enable_push(&mut self, enable: bool) {
+        self.enable_push = enable;
+    }
+
+    pub fn set_max_concurrent_streams(&mut self, max: Option<u32>) {
+        self.max_concurrent_streams = max;
+    }
+
+    pub fn set_initial_window_size(&mut self, size: u32) {
+        self.initial_window_size = size.min(MAX_WINDOW_SIZE);
+    }
+
+    pub fn set_max_frame_size(&mut self, size: u32) {
+        self.max_frame_size = size.clamp(DEFAULT_MAX_FRAME_SIZE, LARGEST_MAX_FRAME_SIZE);
+    }
+
+    pub fn set_max_header_list_size(&mut self, max: Option<u32>) {
+        self.max_header_list_size = max;
+    }
+
+    /// Take on the values of a SETTINGS frame, identifiers this server doesn't know are ignored
+    pub fn apply(&mut self, settings: &[(u16, u32)]) -> Result<(), H2Error> {
+        for &(id, value) in settings {
+            match id {
+                HEADER_TABLE_SIZE => self.header_table_size = value,
+                ENABLE_PUSH => self.enable_push = match value {
+                    0 => false,
+                    1 => true,
+                    _ => return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_ENABLE_PUSH not 0 or 1"))
+                },
+                MAX_CONCURRENT_STREAMS => self.max_concurrent_streams = Some(value),
+                INITIAL_WINDOW_SIZE => match value > MAX_WINDOW_SIZE {
+                    true => return Err(H2Error::connection(ErrorCode::FlowControlError, "SETTINGS_INITIAL_WINDOW_SIZE above 2^31-1")),
+                    false => self.initial_window_size = value
+                },
+                MAX_FRAME_SIZE => match (DEFAULT_MAX_FRAME_SIZE..=LARGEST_MAX_FRAME_SIZE).contains(&value) {
+                    true => self.max_frame_size = value,
+                    false => return Err(H2Error::connection(ErrorCode::ProtocolError, "SETTINGS_MAX_FRAME_SIZE out of range"))
+                },
+                MAX_HEADER_LIST_SIZE => self.max_header_list_size = Some(value),
+                _ => ()
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The SETTINGS frame announcing every value that differs from the defaults
+    pub fn to_frame(&self) -> Frame {
+        let defaults = Settings::default();
+        let mut settings = vec![];
+
+        if self.header_table_size != defaults.header_table_size {
+            settings.push((HEADER_TABLE_SIZE, self.header_table_size));
+        }
+        if self.enable_push != defaults.enable_push {
+            settings.push((ENABLE_PUSH, self.enable_push as u32));
+        }
+        if let Some(max) = self.max_concurrent_streams {
+            settings.push((MAX_CONCURRENT_STREAMS, max));
+        }
+        if self.initial_window_size != defaults.initial_window_size {
+            settings.push((INITIAL_WINDOW_SIZE, self.initial_window_size));
+        }
+        if self.max_frame_size != defaults.max_frame_size {
+            settings.push((MAX_FRAME_SIZE, self.max_frame_size));
+        }
+        if let Some(max) = self.max_header_list_size {
+            settings.push((MAX_HEADER_LIST_SIZE, max));
+        }
+
+        Frame::Settings { ack: false, settings }
+    }
+
+    /// Acknowledges the other end's SETTINGS, which take effect for it once it receives this
+    pub fn ack() -> Frame {
+        Frame::Settings { ack: true, settings: vec![] }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            header_table_size: 4096,
+            enable_push: true,
+            max_concurrent_streams: None,
+            initial_window_size: DEFAULT_WINDOW_SIZE,
+            max_frame_size: DEFAULT_MAX_FRAME_SIZE,
+            max_header_list_size: None
+        }
+    }
+}
+
+/// The values of an `HTTP2-Settings` header, a SETTINGS payload in base64url sent along with an h2c upgrade
+/// `None` if it doesn't decode or holds a value a SETTINGS frame couldn't
+pub fn decode_header(value: &str) -> Option<Vec<(u16, u32)>> {
+    let mut payload = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for byte in value.trim().trim_end_matches('=').bytes() {
+        let sextet = match byte {
+            b'A'..=b'Z' => byte - b'A',
+            b'a'..=b'z' => byte - b'a' + 26,
+            b'0'..=b'9' => byte - b'0' + 52,
+            b'-' => 62,
+            b'_' => 63,
+            _ => return None
+        };
+        bits = bits << 6 | sextet as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            payload.push((bits >> count) as u8);
+        }
+    }
+
+    if !payload.len().is_multiple_of(6) {
+        return None;
+    }
+    let settings = payload.chunks(6)
+        .map(|setting| (u16::from_be_bytes([setting[0], setting[1]]), u32::from_be_bytes([setting[2], setting[3], setting[4], setting[5]])))
+        .collect::<Vec<(u16, u32)>>();
+    Settings::default().apply(&settings).ok()?;
+    Some(settings)
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-#[derive(Eq, PartialEq, Clone)]
+#[derive(Debug, Eq, PartialEq, Clone, Default)]
 pub struct Header {
     values: HashMap<String, String>
 }
@@ -24,6 +24,21 @@ impl Header {
         self.values.get(&key)
     }
 
+    /// Look a header up ignoring the case of its name
+    pub fn find(&self, key: &str) -> Option<&String> {
+        self.values.iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &String)> {
+        self.values.iter()
+    }
+
     pub fn get_headers_formatted(&self) -> String {
         let mut header_vec = vec![];
 
@@ -1,11 +1,21 @@
+pub(crate) mod error;
+pub mod h2;
 pub(crate) mod header;
 pub(crate) mod request;
 pub(crate) mod response;
 pub(crate) mod util;
 
+pub use self::error::{HTTPError, format::ErrorFormat};
 pub use self::header::Header;
 pub use self::request::Request;
+#[cfg(feature = "tls")]
+pub use self::request::certificate::{ClientCertificate, SubjectAltName};
+pub use self::request::credentials::Credentials;
+pub use self::request::info::{Client, RequestInfo};
+pub use self::request::log::{LogLevel, RequestChannel, RequestLogger};
+pub use self::request::trace::{Span, SpanKind, TraceContext};
 pub use self::response::Response;
+pub use self::response::toolkit::{IntoResponse, Json, Redirect};
 pub use self::util::{
     methods::HTTPMethod,
     status_codes::HTTPStatusCodes,
@@ -0,0 +1,430 @@
+use std::fmt;
+use std::net::IpAddr;
+
+/// A name the certificate is issued for besides its subject
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Email(String),
+    /// Such as a SPIFFE id, `spiffe://example.com/billing`
+    Uri(String),
+    Ip(IpAddr)
+}
+
+impl fmt::Display for SubjectAltName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SubjectAltName::Dns(name) => write!(f, "DNS:{}", name),
+            SubjectAltName::Email(email) => write!(f, "email:{}", email),
+            SubjectAltName::Uri(uri) => write!(f, "URI:{}", uri),
+            SubjectAltName::Ip(ip) => write!(f, "IP:{}", ip)
+        }
+    }
+}
+
+/// The certificate a client proved it holds the key of, verified against the configured CA bundle
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct ClientCertificate {
+    subject: String,
+    issuer: String,
+    serial: String,
+    subject_alt_names: Vec<SubjectAltName>,
+    fingerprint: String,
+    der: Vec<u8>
+}
+
+impl ClientCertificate {
+    pub(crate) fn new(subject: String, issuer: String, serial: String, subject_alt_names: Vec<SubjectAltName>, fingerprint: String, der: Vec<u8>) -> Self {
+        Self {
+            subject,
+            issuer,
+            serial,
+            subject_alt_names,
+            fingerprint,
+            der
+        }
+    }
+
+    /// The subject's distinguished name as in RFC 4514, `CN=billing,O=Example,C=US`
+    pub fn get_subject(&self) -> String {
+        self.subject.clone()
+    }
+
+    pub fn get_issuer(&self) -> String {
+        self.issuer.clone()
+    }
+
+    /// Lowercase hex without leading zeros
+    pub fn get_serial(&self) -> String {
+        self.serial.clone()
+    }
+
+    pub fn get_subject_alt_names(&self) -> Vec<SubjectAltName> {
+        self.subject_alt_names.clone()
+    }
+
+    /// SHA-256 of the DER encoding in lowercase hex
+    pub fn get_fingerprint(&self) -> String {
+        self.fingerprint.clone()
+    }
+
+    pub fn get_der(&self) -> Vec<u8> {
+        self.der.clone()
+    }
+}
+
+// Reading the certificate out of the handshake, only TLS connections have one
+#[cfg(feature = "tls")]
+mod der {
+    use super::{ClientCertificate, SubjectAltName};
+    use std::convert::TryFrom;
+    use std::net::IpAddr;
+
+    impl ClientCertificate {
+        /// `None` if the DER isn't an X.509 certificate
+        pub fn parse(der: &[u8]) -> Option<Self> {
+            let mut certificate = Der::new(der).expect(SEQUENCE)?;
+            let mut tbs = certificate.expect(SEQUENCE)?;
+
+            tbs.skip_optional(VERSION);
+            let serial = tbs.expect_raw(INTEGER)?;
+            tbs.expect(SEQUENCE)?; // signature algorithm
+            let issuer = format_name(tbs.expect(SEQUENCE)?)?;
+            tbs.expect(SEQUENCE)?; // validity
+            let subject = format_name(tbs.expect(SEQUENCE)?)?;
+            tbs.expect(SEQUENCE)?; // public key
+            tbs.skip_optional(ISSUER_UID);
+            tbs.skip_optional(SUBJECT_UID);
+
+            let subject_alt_names = match tbs.optional(EXTENSIONS) {
+                Some(mut extensions) => find_subject_alt_names(extensions.expect(SEQUENCE)?)?,
+                None => vec![]
+            };
+
+            let serial = match serial.iter().position(|&byte| byte != 0) {
+                Some(start) => to_hex(&serial[start..]),
+                None => String::from("00")
+            };
+
+            let fingerprint = to_hex(ring::digest::digest(&ring::digest::SHA256, der).as_ref());
+            Some(Self::new(subject, issuer, serial, subject_alt_names, fingerprint, der.to_vec()))
+        }
+    }
+
+    const SEQUENCE: u8 = 0x30;
+    const SET: u8 = 0x31;
+    const INTEGER: u8 = 0x02;
+    const BOOLEAN: u8 = 0x01;
+    const OCTET_STRING: u8 = 0x04;
+    const OID: u8 = 0x06;
+    const VERSION: u8 = 0xa0;
+    const ISSUER_UID: u8 = 0x81;
+    const SUBJECT_UID: u8 = 0x82;
+    const EXTENSIONS: u8 = 0xa3;
+
+    const SUBJECT_ALT_NAME: &str = "2.5.29.17";
+
+    /// Reads DER values one after the other, enough of it for certificates
+    struct Der<'a> {
+        input: &'a [u8]
+    }
+
+    impl<'a> Der<'a> {
+        fn new(input: &'a [u8]) -> Self {
+            Self {
+                input
+            }
+        }
+
+        fn is_empty(&self) -> bool {
+            self.input.is_empty()
+        }
+
+        fn peek(&self) -> Option<u8> {
+            self.input.first().copied()
+        }
+
+        fn next(&mut self) -> Option<(u8, &'a [u8])> {
+            let (&tag, rest) = self.input.split_first()?;
+            let (&first, rest) = rest.split_first()?;
+
+            let (length, rest) = match first {
+                0..=0x7f => (first as usize, rest),
+                0x81..=0x84 => {
+                    let size = (first & 0x7f) as usize;
+                    if rest.len() < size {
+                        return None;
+                    }
+                    let length = rest[..size].iter().fold(0usize, |length, &byte| (length << 8) | byte as usize);
+                    (length, &rest[size..])
+                },
+                _ => return None
+            };
+
+            if rest.len() < length {
+                return None;
+            }
+            self.input = &rest[length..];
+            Some((tag, &rest[..length]))
+        }
+
+        fn expect_raw(&mut self, tag: u8) -> Option<&'a [u8]> {
+            match self.next()? {
+                (found, contents) if found == tag => Some(contents),
+                _ => None
+            }
+        }
+
+        fn expect(&mut self, tag: u8) -> Option<Der<'a>> {
+            self.expect_raw(tag).map(Der::new)
+        }
+
+        fn optional(&mut self, tag: u8) -> Option<Der<'a>> {
+            match self.peek() == Some(tag) {
+                true => self.expect(tag),
+                false => None
+            }
+        }
+
+        fn skip_optional(&mut self, tag: u8) {
+            self.optional(tag);
+        }
+    }
+
+    /// RFC 4514 lists the most specific part first, the reverse of how it's encoded
+    fn format_name(mut name: Der) -> Option<String> {
+        let mut rdns = vec![];
+        while !name.is_empty() {
+            let mut set = name.expect(SET)?;
+            let mut attributes = vec![];
+            while !set.is_empty() {
+                let mut attribute = set.expect(SEQUENCE)?;
+                let oid = format_oid(attribute.expect_raw(OID)?);
+                let (tag, value) = attribute.next()?;
+                let value = match decode_string(tag, value) {
+                    Some(value) => escape_value(&value),
+                    None => format!("#{}", to_hex(value))
+                };
+                attributes.push(format!("{}={}", attribute_name(&oid).map(String::from).unwrap_or(oid), value));
+            }
+            rdns.push(attributes.join("+"));
+        }
+
+        rdns.reverse();
+        Some(rdns.join(","))
+    }
+
+    fn attribute_name(oid: &str) -> Option<&'static str> {
+        match oid {
+            "2.5.4.3" => Some("CN"),
+            "2.5.4.5" => Some("serialNumber"),
+            "2.5.4.6" => Some("C"),
+            "2.5.4.7" => Some("L"),
+            "2.5.4.8" => Some("ST"),
+            "2.5.4.9" => Some("STREET"),
+            "2.5.4.10" => Some("O"),
+            "2.5.4.11" => Some("OU"),
+            "0.9.2342.19200300.100.1.1" => Some("UID"),
+            "0.9.2342.19200300.100.1.25" => Some("DC"),
+            "1.2.840.113549.1.9.1" => Some("emailAddress"),
+            _ => None
+        }
+    }
+
+    fn decode_string(tag: u8, value: &[u8]) -> Option<String> {
+        match tag {
+            // UTF8String, PrintableString, IA5String, NumericString, VisibleString
+            0x0c | 0x13 | 0x16 | 0x12 | 0x1a => String::from_utf8(value.to_vec()).ok(),
+            // TeletexString, in practice Latin-1
+            0x14 => Some(value.iter().map(|&byte| byte as char).collect()),
+            // BMPString
+            0x1e => {
+                let units: Vec<u16> = value.chunks(2).map(|pair| u16::from_be_bytes([pair[0], *pair.get(1).unwrap_or(&0)])).collect();
+                String::from_utf16(&units).ok()
+            },
+            _ => None
+        }
+    }
+
+    fn escape_value(value: &str) -> String {
+        let (mut escaped, last) = (String::new(), value.chars().count().saturating_sub(1));
+        for (i, c) in value.chars().enumerate() {
+            let special = matches!(c, ',' | '+' | '"' | '\\' | '<' | '>' | ';')
+                || (i == 0 && (c == '#' || c == ' '))
+                || (i == last && c == ' ');
+            if special {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    fn format_oid(oid: &[u8]) -> String {
+        let mut arcs = vec![];
+        let mut arc: u64 = 0;
+        for &byte in oid {
+            arc = (arc << 7) | (byte & 0x7f) as u64;
+            if byte & 0x80 == 0 {
+                match arcs.is_empty() {
+                    // The first byte packs the first two arcs
+                    true => {
+                        let first = (arc / 40).min(2);
+                        arcs.push(first);
+                        arcs.push(arc - first * 40);
+                    },
+                    false => arcs.push(arc)
+                }
+                arc = 0;
+            }
+        }
+
+        arcs.iter().map(u64::to_string).collect::<Vec<String>>().join(".")
+    }
+
+    fn find_subject_alt_names(mut extensions: Der) -> Option<Vec<SubjectAltName>> {
+        while !extensions.is_empty() {
+            let mut extension = extensions.expect(SEQUENCE)?;
+            let oid = format_oid(extension.expect_raw(OID)?);
+            extension.skip_optional(BOOLEAN);
+            let value = extension.expect_raw(OCTET_STRING)?;
+
+            if oid == SUBJECT_ALT_NAME {
+                return parse_subject_alt_names(Der::new(value).expect(SEQUENCE)?);
+            }
+        }
+
+        Some(vec![])
+    }
+
+    fn parse_subject_alt_names(mut names: Der) -> Option<Vec<SubjectAltName>> {
+        let mut alt_names = vec![];
+        while !names.is_empty() {
+            let (tag, value) = names.next()?;
+            let text = || String::from_utf8_lossy(value).into_owned();
+            match tag {
+                0x81 => alt_names.push(SubjectAltName::Email(text())),
+                0x82 => alt_names.push(SubjectAltName::Dns(text())),
+                0x86 => alt_names.push(SubjectAltName::Uri(text())),
+                0x87 => match value.len() {
+                    4 => alt_names.push(SubjectAltName::Ip(IpAddr::from(<[u8; 4]>::try_from(value).ok()?))),
+                    16 => alt_names.push(SubjectAltName::Ip(IpAddr::from(<[u8; 16]>::try_from(value).ok()?))),
+                    _ => ()
+                },
+                // Other names and directory names
+                _ => ()
+            }
+        }
+
+        Some(alt_names)
+    }
+
+    fn to_hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::{escape_value, format_oid, parse_subject_alt_names, Der};
+        use super::super::{ClientCertificate, SubjectAltName};
+        use rustls::pki_types::CertificateDer;
+        use rustls::pki_types::pem::PemObject;
+        use std::net::IpAddr;
+
+        // Self-signed, with a `jurisdictionC` attribute for a multi-byte OID and one SAN of every kind
+        const CERTIFICATE: &str = "\
+-----BEGIN CERTIFICATE-----\n\
+MIICRzCCAeygAwIBAgIEAMD/7jAKBggqhkjOPQQDAjBLMQswCQYDVQQGEwJVUzEV\n\
+MBMGA1UECgwMRXhhbXBsZSwgSW5jMRAwDgYDVQQDDAdiaWxsaW5nMRMwEQYLKwYB\n\
+BAGCNzwCAQMTAlVTMCAXDTI2MTAxOTA5MTE0M1oYDzIxMjYwOTI1MDkxMTQzWjBL\n\
+MQswCQYDVQQGEwJVUzEVMBMGA1UECgwMRXhhbXBsZSwgSW5jMRAwDgYDVQQDDAdi\n\
+aWxsaW5nMRMwEQYLKwYBBAGCNzwCAQMTAlVTMFkwEwYHKoZIzj0CAQYIKoZIzj0D\n\
+AQcDQgAEc4ZBvBHUE6ZNr2gt1AlFCwP75Xa1g68CpAX1pDN+FfmN7CbXsvKWd6Vd\n\
+zCKEXs7cFEfF3DcQwuQiUphPUgQRvqOBuzCBuDAdBgNVHQ4EFgQUsaI/5WpNr4Vj\n\
+o56eGauX3x78rIYwHwYDVR0jBBgwFoAUsaI/5WpNr4Vjo56eGauX3x78rIYwDwYD\n\
+VR0TAQH/BAUwAwEB/zBlBgNVHREEXjBcghNiaWxsaW5nLmV4YW1wbGUuY29tgQ9v\n\
+cHNAZXhhbXBsZS5jb22GHHNwaWZmZTovL2V4YW1wbGUuY29tL2JpbGxpbmeHBAoA\n\
+AAGHEAAAAAAAAAAAAAAAAAAAAAEwCgYIKoZIzj0EAwIDSQAwRgIhAIzx+QAcUNOH\n\
+PPCUXlHAqlTIGru8B0Hi0yg/7DzSNIy8AiEAjb+NJPOqYqLF1fAa0FJkpfjqGhxv\n\
+raoTKmQVJshmBk8=\n\
+-----END CERTIFICATE-----\n";
+
+        fn der() -> Vec<u8> {
+            CertificateDer::from_pem_slice(CERTIFICATE.as_bytes()).unwrap().to_vec()
+        }
+
+        #[test]
+        fn real_certificates_parse() {
+            let certificate = ClientCertificate::parse(&der()).unwrap();
+            let name = "1.3.6.1.4.1.311.60.2.1.3=US,CN=billing,O=Example\\, Inc,C=US";
+            assert_eq!(certificate.get_subject(), name);
+            assert_eq!(certificate.get_issuer(), name);
+            assert_eq!(certificate.get_serial(), "c0ffee");
+            assert_eq!(certificate.get_fingerprint(), "c2eb7753012a46d99576da84e2bce44ff0d45455152b25de311556518ccb4ab6");
+            assert_eq!(certificate.get_der(), der());
+            assert_eq!(certificate.get_subject_alt_names(), vec![
+                SubjectAltName::Dns(String::from("billing.example.com")),
+                SubjectAltName::Email(String::from("ops@example.com")),
+                SubjectAltName::Uri(String::from("spiffe://example.com/billing")),
+                SubjectAltName::Ip(IpAddr::from([10, 0, 0, 1])),
+                SubjectAltName::Ip("::1".parse().unwrap())
+            ]);
+        }
+
+        #[test]
+        fn truncated_certificates_are_rejected() {
+            let der = der();
+            for length in 0..der.len() {
+                assert_eq!(ClientCertificate::parse(&der[..length]), None, "{} bytes", length);
+            }
+            assert_eq!(ClientCertificate::parse(b"not a certificate"), None);
+        }
+
+        #[test]
+        fn lengths_past_the_input_are_rejected() {
+            assert_eq!(Der::new(&[0x30, 0x03, 0x01, 0x02]).next(), None);
+            assert_eq!(Der::new(&[0x30, 0x82, 0xff, 0xff, 0x00]).next(), None);
+            assert_eq!(Der::new(&[0x30, 0x84, 0xff, 0xff, 0xff, 0xff, 0x00]).next(), None);
+            // Length bytes cut short, indefinite lengths and lengths over four bytes
+            assert_eq!(Der::new(&[0x30, 0x82, 0x01]).next(), None);
+            assert_eq!(Der::new(&[0x30, 0x80, 0x00, 0x00]).next(), None);
+            assert_eq!(Der::new(&[0x30, 0x85, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00]).next(), None);
+
+            let mut der = Der::new(&[0x02, 0x81, 0x01, 0x2a, 0x05, 0x00]);
+            assert_eq!(der.next(), Some((0x02, &[0x2a][..])));
+            assert_eq!(der.next(), Some((0x05, &[][..])));
+            assert!(der.is_empty());
+        }
+
+        #[test]
+        fn oids_with_multi_byte_arcs() {
+            assert_eq!(format_oid(&[0x55, 0x04, 0x03]), "2.5.4.3");
+            assert_eq!(format_oid(&[0x2b, 0x06, 0x01, 0x04, 0x01, 0x82, 0x37, 0x3c, 0x02, 0x01, 0x03]), "1.3.6.1.4.1.311.60.2.1.3");
+            assert_eq!(format_oid(&[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x09, 0x01]), "1.2.840.113549.1.9.1");
+            // Past 2 the first byte only holds the second arc
+            assert_eq!(format_oid(&[0x88, 0x37, 0x03]), "2.999.3");
+        }
+
+        #[test]
+        fn subject_alt_names_skip_unknown_kinds() {
+            let names = [
+                0x82, 0x01, b'a',
+                0xa4, 0x02, 0x30, 0x00, // Directory name
+                0x87, 0x03, 0x01, 0x02, 0x03, // Neither IPv4 nor IPv6
+                0x87, 0x04, 0x7f, 0x00, 0x00, 0x01
+            ];
+            assert_eq!(parse_subject_alt_names(Der::new(&names)), Some(vec![
+                SubjectAltName::Dns(String::from("a")),
+                SubjectAltName::Ip(IpAddr::from([127, 0, 0, 1]))
+            ]));
+            assert_eq!(parse_subject_alt_names(Der::new(&[0x82, 0x05, b'a'])), None);
+        }
+
+        #[test]
+        fn values_are_escaped() {
+            assert_eq!(escape_value("a,b+c"), "a\\,b\\+c");
+            assert_eq!(escape_value("#a b "), "\\#a b\\ ");
+            assert_eq!(escape_value(" "), "\\ ");
+        }
+    }
+}
@@ -0,0 +1,44 @@
+/// Who a strategy found the request to come from, handed to the handler through `Request::get_auth`
+#[derive(Debug, Clone, Default, Eq, PartialEq)]
+pub struct Credentials {
+    strategy: String,
+    identity: String,
+    claims: Vec<(String, String)>
+}
+
+impl Credentials {
+    pub fn new(identity: String) -> Self {
+        Self {
+            identity,
+            ..Self::default()
+        }
+    }
+
+    /// Something else the strategy learned about the client, a name may be claimed more than once
+    pub fn with_claim(mut self, name: &str, value: &str) -> Self {
+        self.claims.push((String::from(name), String::from(value)));
+        self
+    }
+
+    /// The name of the strategy that authenticated the request
+    pub fn get_strategy(&self) -> String {
+        self.strategy.clone()
+    }
+
+    pub fn get_identity(&self) -> String {
+        self.identity.clone()
+    }
+
+    /// The first value claimed under `name`
+    pub fn get_claim(&self, name: &str) -> Option<String> {
+        self.claims.iter().find(|(claim, _)| claim == name).map(|(_, value)| value.clone())
+    }
+
+    pub fn get_claims(&self) -> Vec<(String, String)> {
+        self.claims.clone()
+    }
+
+    pub(crate) fn set_strategy(&mut self, strategy: String) {
+        self.strategy = strategy;
+    }
+}
@@ -0,0 +1,282 @@
+#[cfg(feature = "tls")]
+use super::certificate::ClientCertificate;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, SystemTime};
+
+/// Who sent the request, where and when, like hapi's `request.info`
+#[derive(Debug, Clone)]
+pub struct RequestInfo {
+    id: String,
+    remote_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    remote_address: Option<IpAddr>,
+    remote_port: Option<u16>,
+    received: SystemTime,
+    scheme: String,
+    host: String,
+    hostname: String,
+    port: Option<u16>,
+    #[cfg(feature = "tls")]
+    client_certificate: Option<ClientCertificate>,
+    // Shared by the request's clones so the time is visible wherever the request went
+    responded: Arc<OnceLock<SystemTime>>
+}
+
+impl RequestInfo {
+    /// `client` is where the request came from as resolved through the trusted proxies, the local address stands in for a missing host
+    pub fn new(id: String, remote_addr: Option<SocketAddr>, local_addr: Option<SocketAddr>, received: SystemTime, client: Client) -> Self {
+        let host = match client.get_host().filter(|host| !host.trim().is_empty()) {
+            Some(host) => String::from(host.trim()),
+            None => local_addr.map(|addr| addr.to_string()).unwrap_or_default()
+        };
+        let (hostname, port) = split_host(&host);
+
+        Self {
+            id,
+            remote_addr,
+            local_addr,
+            remote_address: client.get_address(),
+            remote_port: client.get_port(),
+            received,
+            scheme: client.get_scheme(),
+            hostname,
+            // A host without a port asks for the scheme's default
+            port: port.or_else(|| match client.get_scheme().as_str() {
+                "https" => Some(443),
+                "http" => Some(80),
+                _ => local_addr.map(|addr| addr.port())
+            }),
+            host,
+            #[cfg(feature = "tls")]
+            client_certificate: None,
+            responded: Arc::new(OnceLock::new())
+        }
+    }
+
+    /**
+     * Getters
+    */
+
+    /// Unique for the life of the server unless taken from the client's request id header
+    pub fn get_id(&self) -> String {
+        self.id.clone()
+    }
+
+    /// The other end of the connection, the closest proxy when behind one
+    pub fn get_remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn get_local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The client's address, taken from the forwarding headers of trusted proxies
+    pub fn get_remote_address(&self) -> Option<IpAddr> {
+        self.remote_address
+    }
+
+    /// The client's port, `None` when a proxy didn't pass it on
+    pub fn get_remote_port(&self) -> Option<u16> {
+        self.remote_port
+    }
+
+    /// `http` or `https`, as the client sent the request
+    pub fn get_scheme(&self) -> String {
+        self.scheme.clone()
+    }
+
+    /// When the connection carrying the request was accepted
+    pub fn get_received(&self) -> SystemTime {
+        self.received
+    }
+
+    /// The host the client asked for, such as `example.com:8080`
+    pub fn get_host(&self) -> String {
+        self.host.clone()
+    }
+
+    /// The host without its port
+    pub fn get_hostname(&self) -> String {
+        self.hostname.clone()
+    }
+
+    /// The port of the host, the scheme's default port if the host has none
+    pub fn get_port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// The certificate the client authenticated with over mutual TLS
+    #[cfg(feature = "tls")]
+    pub fn get_client_certificate(&self) -> Option<ClientCertificate> {
+        self.client_certificate.clone()
+    }
+
+    /// When the response finished writing, `None` until then
+    pub fn get_responded(&self) -> Option<SystemTime> {
+        self.responded.get().copied()
+    }
+
+    /// Time from receiving the request to finishing its response
+    pub fn get_response_time(&self) -> Option<Duration> {
+        self.get_responded().map(|responded| responded.duration_since(self.received).unwrap_or_default())
+    }
+
+    pub(crate) fn set_responded(&self) {
+        self.responded.set(SystemTime::now()).unwrap_or_default();
+    }
+
+    #[cfg(feature = "tls")]
+    pub(crate) fn set_client_certificate(&mut self, certificate: Option<ClientCertificate>) {
+        self.client_certificate = certificate;
+    }
+}
+
+impl Default for RequestInfo {
+    fn default() -> Self {
+        Self::new(String::new(), None, None, SystemTime::now(), Client::new(None, false, None))
+    }
+}
+
+/// Where a request came from once the hops through trusted proxies are accounted for
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct Client {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    scheme: String,
+    host: Option<String>
+}
+
+impl Client {
+    /// The peer of the connection, before any forwarding headers are believed
+    pub(crate) fn new(peer: Option<SocketAddr>, secure: bool, host: Option<String>) -> Self {
+        Self {
+            address: peer.map(|addr| addr.ip()),
+            port: peer.map(|addr| addr.port()),
+            scheme: String::from(if secure { "https" } else { "http" }),
+            host
+        }
+    }
+
+    /// `None` if a proxy hid the client behind an obfuscated or `unknown` identifier
+    pub fn get_address(&self) -> Option<IpAddr> {
+        self.address
+    }
+
+    /// `None` if the proxies didn't pass the client's port on
+    pub fn get_port(&self) -> Option<u16> {
+        self.port
+    }
+
+    pub fn get_scheme(&self) -> String {
+        self.scheme.clone()
+    }
+
+    /// The host the client asked for
+    pub fn get_host(&self) -> Option<String> {
+        self.host.clone()
+    }
+
+    /// Take the place of the client a trusted proxy forwarded the request for
+    pub(crate) fn forwarded(&mut self, address: Option<IpAddr>, port: Option<u16>, proto: Option<String>, host: Option<String>) {
+        self.address = address;
+        self.port = port;
+        if let Some(proto) = proto {
+            self.scheme = proto.to_ascii_lowercase();
+        }
+        if let Some(host) = host {
+            self.host = Some(host);
+        }
+    }
+}
+
+/// `example.com:8080` and `[::1]:8080` into their hostname and port
+fn split_host(host: &str) -> (String, Option<u16>) {
+    if host.starts_with('[') {
+        return match host.find(']') {
+            None => (String::from(host), None),
+            Some(end) => (String::from(&host[..=end]), host[end + 1..].strip_prefix(':').and_then(|port| port.parse().ok()))
+        };
+    }
+
+    match host.rsplit_once(':') {
+        Some((hostname, port)) if !hostname.contains(':') => (String::from(hostname), port.parse().ok()),
+        _ => (String::from(host), None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Client, RequestInfo, split_host};
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+    use std::time::{Duration, SystemTime};
+
+    fn info(secure: bool, host: Option<&str>) -> RequestInfo {
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let local: SocketAddr = "127.0.0.1:3000".parse().unwrap();
+        RequestInfo::new(String::from("1"), Some(peer), Some(local), SystemTime::now(), Client::new(Some(peer), secure, host.map(String::from)))
+    }
+
+    #[test]
+    fn hosts_split_into_hostname_and_port() {
+        assert_eq!(split_host("example.com:8080"), (String::from("example.com"), Some(8080)));
+        assert_eq!(split_host("example.com"), (String::from("example.com"), None));
+        assert_eq!(split_host("[::1]:8080"), (String::from("[::1]"), Some(8080)));
+        assert_eq!(split_host("[::1]"), (String::from("[::1]"), None));
+        // A bare IPv6 address has no port to split off
+        assert_eq!(split_host("::1"), (String::from("::1"), None));
+        assert_eq!(split_host("example.com:http"), (String::from("example.com"), None));
+    }
+
+    #[test]
+    fn ports_default_to_the_scheme() {
+        let http = info(false, Some("example.com"));
+        assert_eq!((http.get_hostname(), http.get_port()), (String::from("example.com"), Some(80)));
+
+        let https = info(true, Some(" example.com "));
+        assert_eq!((https.get_host(), https.get_scheme(), https.get_port()), (String::from("example.com"), String::from("https"), Some(443)));
+
+        let explicit = info(false, Some("example.com:8080"));
+        assert_eq!(explicit.get_port(), Some(8080));
+    }
+
+    #[test]
+    fn the_local_address_stands_in_for_a_missing_host() {
+        for host in [None, Some(""), Some("  ")] {
+            let info = info(false, host);
+            assert_eq!(info.get_host(), "127.0.0.1:3000");
+            assert_eq!((info.get_hostname(), info.get_port()), (String::from("127.0.0.1"), Some(3000)));
+        }
+    }
+
+    #[test]
+    fn addresses_come_from_the_client() {
+        let peer: SocketAddr = "10.0.0.1:5000".parse().unwrap();
+        let mut client = Client::new(Some(peer), false, None);
+        client.forwarded(Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))), None, Some(String::from("HTTPS")), Some(String::from("example.com")));
+        let info = RequestInfo::new(String::from("1"), Some(peer), None, SystemTime::now(), client);
+
+        assert_eq!(info.get_remote_addr(), Some(peer));
+        assert_eq!(info.get_remote_address(), Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7))));
+        assert_eq!(info.get_remote_port(), None);
+        assert_eq!(info.get_scheme(), "https");
+        assert_eq!((info.get_host(), info.get_port()), (String::from("example.com"), Some(443)));
+    }
+
+    #[test]
+    fn response_time_is_measured_once_across_clones() {
+        let received = SystemTime::now() - Duration::from_millis(50);
+        let info = RequestInfo::new(String::from("7"), None, None, received, Client::new(None, false, None));
+        let clone = info.clone();
+        assert_eq!(info.get_responded(), None);
+        assert_eq!(info.get_response_time(), None);
+
+        clone.set_responded();
+        let responded = info.get_responded().unwrap();
+        info.set_responded();
+        assert_eq!(info.get_responded(), Some(responded));
+        assert!(info.get_response_time().unwrap() >= Duration::from_millis(50));
+        assert_eq!(info.get_id(), "7");
+    }
+}
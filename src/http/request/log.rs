@@ -0,0 +1,42 @@
+use std::fmt::{self, Display};
+
+/// How severe a log event is, events below the logger's level are dropped
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub enum LogLevel {
+    Trace,
+    Debug,
+    Info,
+    Warn,
+    Error
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let level = match self {
+            LogLevel::Trace => "trace",
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error"
+        };
+        write!(f, "{}", level)
+    }
+}
+
+/// Where a request event came from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RequestChannel {
+    /// Logged by the application through `Request::log`
+    App,
+    /// The request failed with a server error
+    Error,
+    /// Logged by the server while handling the request
+    Internal
+}
+
+/// Receives the log events of a request, the server hands each request its `Logger`
+pub trait RequestLogger: Send + Sync {
+    fn is_enabled(&self, level: LogLevel) -> bool;
+
+    fn log_request(&self, id: String, channel: RequestChannel, level: LogLevel, tags: &[&str], data: String);
+}
@@ -1,14 +1,39 @@
+#[cfg(feature = "tls")]
+pub mod certificate;
+pub mod credentials;
+pub mod info;
+pub mod log;
+pub mod trace;
+
+use self::credentials::Credentials;
+use self::info::RequestInfo;
+use self::log::{LogLevel, RequestChannel, RequestLogger};
+use self::trace::{Span, SpanKind, TraceContext};
 use super::{HTTPMethod, HTTPVersion};
 use super::header::Header;
+use std::fmt::Display;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant, SystemTime};
 use std::vec::Vec;
 //use serde
 
+#[derive(Clone)]
 pub struct Request {
     method: HTTPMethod,
     path: String,
     version: HTTPVersion,
     headers: Header,
-    payload: String
+    payload: String,
+    info: RequestInfo,
+    logger: Option<Arc<dyn RequestLogger>>,
+    received: Instant,
+    route: Option<String>,
+    auth: Option<Credentials>,
+    trace: TraceContext,
+    // Protocol the client asked to switch to with `Connection: Upgrade`
+    upgrade: Option<String>,
+    // Phases recorded while handling the request, shared by its clones, `None` unless spans are exported
+    spans: Option<Arc<Mutex<Vec<Span>>>>
 }
 
 impl Request {
@@ -18,7 +43,15 @@ impl Request {
             path,
             version,
             headers,
-            payload
+            payload,
+            info: RequestInfo::default(),
+            logger: None,
+            received: Instant::now(),
+            route: None,
+            auth: None,
+            trace: TraceContext::new(),
+            upgrade: None,
+            spans: None
         }
     }
 
@@ -46,6 +79,114 @@ impl Request {
         self.payload.clone()
     }
 
+    /// Identifies the request in log events and its response's request id header
+    pub fn get_id(&self) -> String {
+        self.info.get_id()
+    }
+
+    /// Addresses, host and timing of the request
+    pub fn get_info(&self) -> RequestInfo {
+        self.info.clone()
+    }
+
+    /// When the connection carrying the request was accepted
+    pub fn get_received_at(&self) -> SystemTime {
+        self.info.get_received()
+    }
+
+    /// Time since the request was received
+    pub fn get_elapsed(&self) -> Duration {
+        self.received.elapsed()
+    }
+
+    /// Path template of the route that matched, such as `/users/{id}`
+    pub fn get_route(&self) -> Option<String> {
+        self.route.clone()
+    }
+
+    /// Who the route's auth strategy found the request to come from, `None` on routes without one
+    pub fn get_auth(&self) -> Option<Credentials> {
+        self.auth.clone()
+    }
+
+    /// The W3C trace the request belongs to, send `get_outgoing_headers` along with calls to other services
+    pub fn get_trace(&self) -> TraceContext {
+        self.trace.clone()
+    }
+
+    /// The `Upgrade` header of a request whose `Connection` header asks for it, such as `h2c`
+    pub fn get_upgrade(&self) -> Option<String> {
+        self.upgrade.clone()
+    }
+
+    /**
+     * Tracing
+    */
+
+    /// Time `f` as a phase of the request, such as `auth`, exported along with the request's span
+    pub fn span<T, F>(&self, name: &str, f: F) -> T
+        where F: FnOnce() -> T
+    {
+        let start = SystemTime::now();
+        let result = f();
+        self.record_phase(name, start);
+        result
+    }
+
+    /// Record a phase that began at `start` and just ended
+    pub(crate) fn record_phase(&self, name: &str, start: SystemTime) {
+        if let Some(ref spans) = self.spans {
+            let span = Span::new(name, SpanKind::Internal, &self.trace, start, SystemTime::now());
+            spans.lock().unwrap_or_else(PoisonError::into_inner).push(span);
+        }
+    }
+
+    pub(crate) fn take_phases(&self) -> Vec<Span> {
+        match self.spans {
+            None => vec![],
+            Some(ref spans) => spans.lock().unwrap_or_else(PoisonError::into_inner).drain(..).collect()
+        }
+    }
+
+    pub(crate) fn set_trace(&mut self, trace: TraceContext, record_spans: bool) {
+        self.trace = trace;
+        self.spans = match record_spans {
+            true => Some(Arc::new(Mutex::new(vec![]))),
+            false => None
+        };
+    }
+
+    /**
+     * Logging
+    */
+
+    /// Log an event tagged with this request's id, listeners receive it on the `App` channel
+    /// Events are dropped until the server hands the request its logger
+    pub fn log<D: Display>(&self, level: LogLevel, tags: &[&str], data: D) {
+        self.log_on(RequestChannel::App, level, tags, data);
+    }
+
+    pub(crate) fn log_on<D: Display>(&self, channel: RequestChannel, level: LogLevel, tags: &[&str], data: D) {
+        match self.logger {
+            Some(ref logger) if logger.is_enabled(level) => logger.log_request(self.info.get_id(), channel, level, tags, data.to_string()),
+            _ => ()
+        }
+    }
+
+    pub(crate) fn set_route(&mut self, route: String) {
+        self.route = Some(route);
+    }
+
+    pub(crate) fn set_auth(&mut self, credentials: Credentials) {
+        self.auth = Some(credentials);
+    }
+
+    pub(crate) fn set_context(&mut self, info: RequestInfo, logger: Arc<dyn RequestLogger>, received: Instant) {
+        self.info = info;
+        self.logger = Some(logger);
+        self.received = received;
+    }
+
     /**
      * Request Parsing
     */
@@ -84,13 +225,13 @@ impl Request {
         let headers = headers_opt.unwrap();
 
         // Connection Upgrade
-        match headers.get_header(String::from("Connection")) {
-            None => (),
-            Some(connection_header) => match connection_header.eq(&String::from("Upgrade")) {
-                true => (), // TODO: implement upgrade
-                false => ()
+        let upgrade = match headers.find("Connection") {
+            None => None,
+            Some(connection_header) => match connection_header.split(',').any(|option| option.trim().eq_ignore_ascii_case("Upgrade")) {
+                true => headers.find("Upgrade").cloned(),
+                false => None
             }
-        }
+        };
 
         // Grab Payload
         loop {
@@ -101,15 +242,10 @@ impl Request {
         }
         let payload = payload_vec.join("\r\n");
 
-        Some(
-            Self {
-                method,
-                path,
-                version,
-                headers,
-                payload
-            }
-        )
+        Some(Self {
+            upgrade,
+            ..Self::new(method, path, version, headers, payload)
+        })
     }
 
     fn parse_request_line(buffer: &str) -> Option<(HTTPMethod, String, HTTPVersion)> {
@@ -155,11 +291,14 @@ impl Request {
         loop {
             let (key, value) = match split_buffer.next() {
                 None => break,
+                // No headers at all, as HTTP/1.0 clients may send
+                Some("") => continue,
                 Some(line) => {
-                    let mut split_line = line.split(": ");
-                    let temp_key = split_line.next().unwrap();
-                    let temp_value = split_line.next().unwrap();
-                    (temp_key, temp_value)
+                    let mut split_line = line.splitn(2, ": ");
+                    match (split_line.next(), split_line.next()) {
+                        (Some(temp_key), Some(temp_value)) => (temp_key, temp_value),
+                        _ => return None
+                    }
                 }
             };
 
@@ -179,7 +318,37 @@ impl Default for Request {
             path: String::from(""),
             version: HTTPVersion::HTTP11,
             headers: Header::new(),
-            payload: String::from("")
+            payload: String::from(""),
+            info: RequestInfo::default(),
+            logger: None,
+            received: Instant::now(),
+            route: None,
+            auth: None,
+            trace: TraceContext::new(),
+            upgrade: None,
+            spans: None
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Request;
+    use super::super::{HTTPMethod, HTTPVersion};
+
+    #[test]
+    fn http10_requests_without_headers_parse() {
+        let request = Request::parse_request(b"GET /index.html HTTP/1.0\r\n\r\n").unwrap();
+        assert_eq!(request.get_method(), HTTPMethod::GET);
+        assert_eq!(request.get_path(), "/index.html");
+        assert_eq!(request.get_version(), HTTPVersion::HTTP10);
+        assert_eq!(request.get_headers().iter().count(), 0);
+        assert_eq!(request.get_upgrade(), None);
+    }
+
+    #[test]
+    fn unknown_versions_and_broken_headers_are_rejected() {
+        assert!(Request::parse_request(b"GET / HTTP/0.9\r\n\r\n").is_none());
+        assert!(Request::parse_request(b"GET / HTTP/1.0\r\nBroken\r\n\r\n").is_none());
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// W3C Trace Context of a request, continuing the caller's trace or starting a new one
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct TraceContext {
+    trace_id: String,
+    span_id: String,
+    parent_id: Option<String>,
+    flags: u8,
+    tracestate: Option<String>
+}
+
+impl TraceContext {
+    /// Start a new sampled trace
+    pub fn new() -> Self {
+        Self::sampled(1.0)
+    }
+
+    /// Start a new trace, sampled with a probability of `ratio` between 0 and 1
+    pub fn sampled(ratio: f64) -> Self {
+        let (high, low) = (random_u64(), random_u64());
+        // Decided from the trace id so every service using the same ratio makes the same decision
+        let sampled = ratio >= 1.0 || (low as f64) < ratio * u64::MAX as f64;
+
+        Self {
+            trace_id: format!("{:016x}{:016x}", high, low),
+            span_id: new_span_id(),
+            parent_id: None,
+            flags: match sampled {
+                true => 1,
+                false => 0
+            },
+            tracestate: None
+        }
+    }
+
+    /// Continue the trace of a `traceparent` header, `None` if the header is invalid
+    pub fn parse(traceparent: &str, tracestate: Option<&str>) -> Option<Self> {
+        let fields: Vec<&str> = traceparent.trim().split('-').collect();
+        if fields.len() < 4 {
+            return None;
+        }
+
+        let (version, trace_id, parent_id, flags) = (fields[0], fields[1], fields[2], fields[3]);
+        let valid = is_hex(version, 2) && version != "ff"
+            && is_hex(trace_id, 32) && trace_id.bytes().any(|b| b != b'0')
+            && is_hex(parent_id, 16) && parent_id.bytes().any(|b| b != b'0')
+            && is_hex(flags, 2)
+            // Version 00 has exactly four fields, later versions may add more
+            && (version != "00" || fields.len() == 4);
+        if !valid {
+            return None;
+        }
+
+        Some(Self {
+            trace_id: String::from(trace_id),
+            span_id: new_span_id(),
+            parent_id: Some(String::from(parent_id)),
+            flags: u8::from_str_radix(flags, 16).ok()?,
+            tracestate: tracestate.map(|state| String::from(state.trim())).filter(|state| !state.is_empty())
+        })
+    }
+
+    pub fn get_trace_id(&self) -> String {
+        self.trace_id.clone()
+    }
+
+    /// The server's span for the request, the parent of calls made while handling it
+    pub fn get_span_id(&self) -> String {
+        self.span_id.clone()
+    }
+
+    /// The caller's span, `None` if the request started the trace
+    pub fn get_parent_id(&self) -> Option<String> {
+        self.parent_id.clone()
+    }
+
+    pub fn get_tracestate(&self) -> Option<String> {
+        self.tracestate.clone()
+    }
+
+    pub fn is_sampled(&self) -> bool {
+        self.flags & 1 == 1
+    }
+
+    /// `traceparent` value for outgoing calls, making the server's span their parent
+    pub fn to_traceparent(&self) -> String {
+        format!("00-{}-{}-{:02x}", self.trace_id, self.span_id, self.flags)
+    }
+
+    /// `traceparent` and `tracestate` headers to send with outgoing calls
+    pub fn get_outgoing_headers(&self) -> Vec<(String, String)> {
+        let mut headers = vec![(String::from("traceparent"), self.to_traceparent())];
+        if let Some(ref state) = self.tracestate {
+            headers.push((String::from("tracestate"), state.clone()));
+        }
+        headers
+    }
+}
+
+impl Default for TraceContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SpanKind {
+    /// The request as a whole
+    Server,
+    /// A phase of handling the request
+    Internal
+}
+
+/// A finished, timed piece of work within a trace
+#[derive(Debug, Clone)]
+pub struct Span {
+    name: String,
+    kind: SpanKind,
+    trace_id: String,
+    span_id: String,
+    parent_id: Option<String>,
+    start: SystemTime,
+    end: SystemTime,
+    attributes: Vec<(String, String)>
+}
+
+impl Span {
+    pub fn new(name: &str, kind: SpanKind, trace: &TraceContext, start: SystemTime, end: SystemTime) -> Self {
+        let (span_id, parent_id) = match kind {
+            SpanKind::Server => (trace.get_span_id(), trace.get_parent_id()),
+            SpanKind::Internal => (new_span_id(), Some(trace.get_span_id()))
+        };
+
+        Self {
+            name: String::from(name),
+            kind,
+            trace_id: trace.get_trace_id(),
+            span_id,
+            parent_id,
+            start,
+            end,
+            attributes: vec![]
+        }
+    }
+
+    pub fn attribute(mut self, key: &str, value: &str) -> Self {
+        self.attributes.push((String::from(key), String::from(value)));
+        self
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_kind(&self) -> SpanKind {
+        self.kind
+    }
+
+    pub fn get_trace_id(&self) -> String {
+        self.trace_id.clone()
+    }
+
+    pub fn get_span_id(&self) -> String {
+        self.span_id.clone()
+    }
+
+    pub fn get_parent_id(&self) -> Option<String> {
+        self.parent_id.clone()
+    }
+
+    pub fn get_start(&self) -> SystemTime {
+        self.start
+    }
+
+    pub fn get_duration(&self) -> Duration {
+        self.end.duration_since(self.start).unwrap_or_default()
+    }
+
+    pub fn get_attributes(&self) -> Vec<(String, String)> {
+        self.attributes.clone()
+    }
+
+    /// The span in the OTLP JSON encoding
+    pub fn to_otlp(&self) -> serde_json::Value {
+        let attributes: Vec<serde_json::Value> = self.attributes.iter()
+            .map(|(key, value)| serde_json::json!({ "key": key, "value": { "stringValue": value } }))
+            .collect();
+
+        serde_json::json!({
+            "traceId": self.trace_id,
+            "spanId": self.span_id,
+            "parentSpanId": self.parent_id.clone().unwrap_or_default(),
+            "name": self.name,
+            "kind": match self.kind {
+                SpanKind::Internal => 1,
+                SpanKind::Server => 2
+            },
+            "startTimeUnixNano": unix_nanos(self.start).to_string(),
+            "endTimeUnixNano": unix_nanos(self.end).to_string(),
+            "attributes": attributes
+        })
+    }
+}
+
+fn is_hex(value: &str, length: usize) -> bool {
+    value.len() == length && value.bytes().all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+}
+
+fn new_span_id() -> String {
+    format!("{:016x}", random_u64())
+}
+
+/// Random enough for trace ids, not for anything secret
+fn random_u64() -> u64 {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(COUNTER.fetch_add(1, Ordering::Relaxed));
+    hasher.write_u128(unix_nanos(SystemTime::now()));
+    match hasher.finish() {
+        0 => 1,
+        random => random
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Span, SpanKind, TraceContext};
+    use std::time::{Duration, UNIX_EPOCH};
+
+    const TRACE_ID: &str = "4bf92f3577b34da6a3ce929d0e0e4736";
+    const PARENT_ID: &str = "00f067aa0ba902b7";
+
+    fn traceparent(version: &str, trace_id: &str, parent_id: &str, flags: &str) -> String {
+        format!("{}-{}-{}-{}", version, trace_id, parent_id, flags)
+    }
+
+    #[test]
+    fn valid_headers_continue_the_trace() {
+        let trace = TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "01"), Some(" vendor=a ")).unwrap();
+        assert_eq!(trace.get_trace_id(), TRACE_ID);
+        assert_eq!(trace.get_parent_id(), Some(String::from(PARENT_ID)));
+        assert_ne!(trace.get_span_id(), PARENT_ID);
+        assert_eq!(trace.get_tracestate(), Some(String::from("vendor=a")));
+        assert!(trace.is_sampled());
+
+        let unsampled = TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "00"), Some("")).unwrap();
+        assert!(!unsampled.is_sampled());
+        assert_eq!(unsampled.get_tracestate(), None);
+    }
+
+    #[test]
+    fn bad_versions_are_rejected() {
+        assert!(TraceContext::parse(&traceparent("ff", TRACE_ID, PARENT_ID, "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("0", TRACE_ID, PARENT_ID, "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("zz", TRACE_ID, PARENT_ID, "01"), None).is_none());
+        // Version 00 has exactly four fields, later versions may carry more
+        assert!(TraceContext::parse(&format!("{}-extra", traceparent("00", TRACE_ID, PARENT_ID, "01")), None).is_none());
+        assert!(TraceContext::parse(&format!("{}-extra", traceparent("01", TRACE_ID, PARENT_ID, "01")), None).is_some());
+    }
+
+    #[test]
+    fn all_zero_ids_are_rejected() {
+        assert!(TraceContext::parse(&traceparent("00", &"0".repeat(32), PARENT_ID, "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("00", TRACE_ID, &"0".repeat(16), "01"), None).is_none());
+    }
+
+    #[test]
+    fn wrong_lengths_are_rejected() {
+        assert!(TraceContext::parse(&traceparent("00", &TRACE_ID[1..], PARENT_ID, "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("00", TRACE_ID, &format!("{}0", PARENT_ID), "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "1"), None).is_none());
+        assert!(TraceContext::parse(&format!("00-{}-{}", TRACE_ID, PARENT_ID), None).is_none());
+        assert!(TraceContext::parse("", None).is_none());
+    }
+
+    #[test]
+    fn uppercase_hex_is_rejected() {
+        assert!(TraceContext::parse(&traceparent("00", &TRACE_ID.to_uppercase(), PARENT_ID, "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("00", TRACE_ID, &PARENT_ID.to_uppercase(), "01"), None).is_none());
+        assert!(TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "0A"), None).is_none());
+    }
+
+    #[test]
+    fn outgoing_headers_make_the_server_span_the_parent() {
+        let trace = TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "01"), Some("vendor=a")).unwrap();
+        assert_eq!(trace.get_outgoing_headers(), vec![
+            (String::from("traceparent"), format!("00-{}-{}-01", TRACE_ID, trace.get_span_id())),
+            (String::from("tracestate"), String::from("vendor=a"))
+        ]);
+
+        let started = TraceContext::sampled(0.0);
+        assert_eq!(started.get_parent_id(), None);
+        assert!(!started.is_sampled());
+        assert_eq!(started.get_outgoing_headers(), vec![(String::from("traceparent"), started.to_traceparent())]);
+        assert!(started.to_traceparent().ends_with("-00"));
+
+        // Our own ids always parse back
+        let parsed = TraceContext::parse(&TraceContext::new().to_traceparent(), None).unwrap();
+        assert!(parsed.is_sampled());
+    }
+
+    #[test]
+    fn spans_encode_as_otlp() {
+        let trace = TraceContext::parse(&traceparent("00", TRACE_ID, PARENT_ID, "01"), None).unwrap();
+        let (start, end) = (UNIX_EPOCH + Duration::from_secs(1), UNIX_EPOCH + Duration::from_millis(1500));
+
+        let server = Span::new("GET /", SpanKind::Server, &trace, start, end).attribute("http.status_code", "200");
+        assert_eq!(server.get_duration(), Duration::from_millis(500));
+        assert_eq!(server.to_otlp(), serde_json::json!({
+            "traceId": TRACE_ID,
+            "spanId": trace.get_span_id(),
+            "parentSpanId": PARENT_ID,
+            "name": "GET /",
+            "kind": 2,
+            "startTimeUnixNano": "1000000000",
+            "endTimeUnixNano": "1500000000",
+            "attributes": [{ "key": "http.status_code", "value": { "stringValue": "200" } }]
+        }));
+
+        // Phases are children of the server span
+        let phase = Span::new("handler", SpanKind::Internal, &trace, start, end);
+        let otlp = phase.to_otlp();
+        assert_eq!(otlp["kind"], 1);
+        assert_eq!(otlp["parentSpanId"], serde_json::Value::from(trace.get_span_id()));
+        assert_ne!(otlp["spanId"], serde_json::Value::from(trace.get_span_id()));
+
+        // Root spans have an empty parent
+        let root = TraceContext::new();
+        assert_eq!(Span::new("GET /", SpanKind::Server, &root, start, end).to_otlp()["parentSpanId"], "");
+    }
+}
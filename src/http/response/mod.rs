@@ -1,42 +1,31 @@
+pub(crate) mod toolkit;
+
 use super::{HTTPVersion, header::Header, HTTPStatusCodes};
-use std::net::{TcpStream, Shutdown};
-use std::io::Write;
+use std::io::{self, Write};
 
 pub struct Response {
     version: HTTPVersion,
     code: u32,
     reason: String,
     headers: Header,
-    body: String,
-    stream: Option<TcpStream>,
-    written: bool,
-    dirty: bool
+    body: Vec<u8>,
+    // Paths to push to HTTP/2 clients along with the response, and the headers of their requests
+    pushes: Vec<(String, Vec<(String, String)>)>
 }
 
 impl Response {
-    pub fn new(version: HTTPVersion, code: u32, reason: String, headers: Header, body: String, stream: TcpStream) -> Self {
+    pub fn new(body: Vec<u8>) -> Self {
         Self {
-            version,
-            code,
-            reason,
-            headers,
             body,
-            stream: Some(stream),
-            written: false,
-            dirty: false
+            ..Self::default()
         }
     }
 
-    pub fn from_stream(stream: TcpStream) -> Self {
+    pub fn from_code(code: HTTPStatusCodes) -> Self {
         Self {
-            version: HTTPVersion::HTTP20,
-            code: HTTPStatusCodes::to_int(&HTTPStatusCodes::Ok200),
-            reason: HTTPStatusCodes::get_generic_reason(&HTTPStatusCodes::Ok200),
-            headers: Header::new(),
-            body: String::from(""),
-            stream: Some(stream),
-            written: false,
-            dirty: false
+            code: code.to_int(),
+            reason: code.get_generic_reason(),
+            ..Self::default()
         }
     }
 
@@ -60,30 +49,42 @@ impl Response {
         self.headers.clone()
     }
 
-    pub fn get_body(&self) -> String {
+    pub fn get_body(&self) -> Vec<u8> {
         self.body.clone()
     }
 
-    fn get_response(&self) -> String {
+    pub fn get_pushes(&self) -> Vec<(String, Vec<(String, String)>)> {
+        self.pushes.clone()
+    }
+
+    /// HTTP standard dictates that response codes of 1XX, 204, and 304 are not allowed bodies
+    pub(crate) fn has_body(&self) -> bool {
         let no_body_codes = [HTTPStatusCodes::Continue100, HTTPStatusCodes::SwitchingProtocols101, HTTPStatusCodes::NoContent204, HTTPStatusCodes::NotModified304];
+        !no_body_codes.iter().any(|x| self.code == x.to_int())
+    }
 
-        // HTTP standard dictates that response codes of 1XX, 204, and 304 are not allowed bodies
-        let body = match no_body_codes.iter().find(|ref x| self.code == HTTPStatusCodes::to_int(&x)) {
-            None => self.get_body(),
-            Some(_) => String::from("")
-        };
-
-        match self.written {
-            false => format!(
-                "{} {} {}\r\n{}\r\n{}",
-                self.version.to_string(),
-                self.code,
-                self.reason,
-                self.headers.get_headers_formatted(),
-                body
-            ),
-            true => format!("{}", body)
+    fn get_response(&self) -> Vec<u8> {
+        let has_body = self.has_body();
+
+        let mut head = format!("{} {} {}\r\n", self.version.to_string(), self.code, self.reason);
+        for (key, value) in self.headers.iter() {
+            // HTTP/1.0 knows neither chunked bodies nor persistent connections, the body goes with its length and the connection closes
+            if self.version == HTTPVersion::HTTP10 && (key.eq_ignore_ascii_case("Transfer-Encoding") || key.eq_ignore_ascii_case("Connection")) {
+                continue;
+            }
+            head.push_str(&format!("{}: {}\r\n", key, value));
+        }
+        if has_body && !self.headers.contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
         }
+        head.push_str("\r\n");
+
+        let mut response = head.into_bytes();
+        if has_body {
+            response.extend_from_slice(&self.body);
+        }
+
+        response
     }
 
     /**
@@ -111,37 +112,67 @@ impl Response {
         self.headers.insert(key, value);
     }
 
-    pub fn set_body(&mut self, body: String) {
+    pub fn set_body(&mut self, body: Vec<u8>) {
         self.body = body;
-        self.dirty = true;
     }
 
     /**
-     * Actions
+     * Builder
     */
 
-    /// Write and flush the response to the stream
-    pub fn write(&mut self, flush: bool) {
-        if cfg!(debug_assertions) {
-            println!("Response:\n{}", self.get_response());
-        }
-
-        let response = self.get_response();
-        match self.stream {
-            Some(ref mut tcp) => {
-                if flush {
-                    tcp.set_nodelay(true).unwrap();
-                } else {
-                    tcp.set_nodelay(false).unwrap();
-                }
-
-                tcp.write(response.as_ref()).unwrap();
-                self.written = true;
+    /// Set the status code, using the generic reason phrase when the code is known and none otherwise
+    pub fn code(mut self, code: u32) -> Self {
+        match HTTPStatusCodes::from_int(code) {
+            None => {
+                self.code = code;
+                self.reason = String::new();
             },
-            None => ()
+            Some(known) => self.set_default_code_and_reason(known)
         }
+        self
+    }
+
+    pub fn header(mut self, key: &str, value: &str) -> Self {
+        self.set_header(String::from(key), String::from(value));
+        self
+    }
+
+    /// Set the `Content-Type` header
+    pub fn type_(self, mime: &str) -> Self {
+        self.header("Content-Type", mime)
+    }
+
+    /// Mark the response as `201 Created` with the `Location` of the new resource
+    pub fn created(self, location: &str) -> Self {
+        self.code(HTTPStatusCodes::Created201.to_int()).header("Location", location)
+    }
+
+    /// Set a strong `ETag` for the response
+    pub fn etag(self, tag: &str) -> Self {
+        self.header("ETag", &format!("\"{}\"", tag))
+    }
+
+    /// Push `path` to HTTP/2 clients along with the response, such as the stylesheet of a page
+    /// The pushed `GET` request carries `headers` and goes through the router, clients refusing pushes and HTTP/1.1 ones never see it
+    pub fn push(mut self, path: &str, headers: &[(&str, &str)]) -> Self {
+        let headers = headers.iter().map(|&(name, value)| (String::from(name), String::from(value))).collect();
+        self.pushes.push((String::from(path), headers));
+        self
+    }
+
+    /**
+     * Actions
+    */
+
+    /// Serialize the response and write it to the stream, returning the size of the body sent
+    pub(crate) fn write_to<W: Write>(&self, stream: &mut W) -> io::Result<usize> {
+        stream.write_all(&self.get_response())?;
+        stream.flush()?;
 
-        self.dirty = false;
+        match self.has_body() {
+            true => Ok(self.body.len()),
+            false => Ok(0)
+        }
     }
 }
 
@@ -149,29 +180,60 @@ impl Default for Response {
     fn default() -> Self {
         Self {
             version: HTTPVersion::HTTP20,
-            code: HTTPStatusCodes::to_int(&HTTPStatusCodes::Ok200),
-            reason: HTTPStatusCodes::get_generic_reason(&HTTPStatusCodes::Ok200),
+            code: HTTPStatusCodes::Ok200.to_int(),
+            reason: HTTPStatusCodes::Ok200.get_generic_reason(),
             headers: Header::new(),
-            body: String::from(""),
-            stream: None,
-            written: false,
-            dirty: false
+            body: vec![],
+            pushes: vec![]
         }
     }
 }
 
-impl Drop for Response {
-    fn drop(&mut self) {
-        if self.dirty {
-            self.write(true);
-        }
+#[cfg(test)]
+mod tests {
+    use super::Response;
+    use super::super::HTTPVersion;
 
-        match self.stream {
-            None => return,
-            Some(ref mut tcp) => {
-                tcp.shutdown(Shutdown::Both).unwrap();
-                return;
-            }
-        }
+    fn written(response: &Response) -> String {
+        let mut buffer = vec![];
+        response.write_to(&mut buffer).unwrap();
+        String::from_utf8(buffer).unwrap()
+    }
+
+    #[test]
+    fn known_codes_get_their_reason() {
+        let response = Response::new(vec![]).code(404);
+        assert_eq!((response.get_code(), response.get_reason()), (404, String::from("Not Found")));
+    }
+
+    #[test]
+    fn unknown_codes_drop_the_old_reason() {
+        let mut response = Response::new(vec![]).code(299);
+        assert_eq!((response.get_code(), response.get_reason()), (299, String::new()));
+
+        response.set_version(HTTPVersion::HTTP11);
+        assert!(written(&response).starts_with("HTTP/1.1 299 \r\n"));
+    }
+
+    fn with_version(version: HTTPVersion) -> Response {
+        let mut response = Response::new(b"hello".to_vec())
+            .header("Connection", "keep-alive")
+            .header("Transfer-Encoding", "identity")
+            .header("X-Kept", "yes");
+        response.set_version(version);
+        response
+    }
+
+    #[test]
+    fn http10_responses_drop_connection_and_transfer_encoding() {
+        assert_eq!(written(&with_version(HTTPVersion::HTTP10)), "HTTP/1.0 200 OK\r\nX-Kept: yes\r\nContent-Length: 5\r\n\r\nhello");
+    }
+
+    #[test]
+    fn http11_responses_keep_every_header() {
+        let response = written(&with_version(HTTPVersion::HTTP11));
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains("Connection: keep-alive\r\n"), "{}", response);
+        assert!(response.contains("Transfer-Encoding: identity\r\n"), "{}", response);
     }
 }
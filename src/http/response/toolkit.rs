@@ -0,0 +1,165 @@
+use super::Response;
+use super::super::{HTTPError, HTTPStatusCodes};
+use serde::Serialize;
+
+/// Anything a route handler may return in place of a `Response`
+pub trait IntoResponse {
+    fn into_response(self) -> Response;
+
+    /// Like `into_response`, but a failed conversion is an error the server logs and answers like a handler error
+    fn try_into_response(self) -> Result<Response, HTTPError> where Self: Sized {
+        Ok(self.into_response())
+    }
+}
+
+impl IntoResponse for Response {
+    fn into_response(self) -> Response {
+        self
+    }
+}
+
+impl IntoResponse for () {
+    fn into_response(self) -> Response {
+        Response::from_code(HTTPStatusCodes::NoContent204)
+    }
+}
+
+impl IntoResponse for String {
+    fn into_response(self) -> Response {
+        Response::new(self.into_bytes()).type_("text/plain; charset=utf-8")
+    }
+}
+
+impl IntoResponse for &'static str {
+    fn into_response(self) -> Response {
+        String::from(self).into_response()
+    }
+}
+
+impl IntoResponse for Vec<u8> {
+    fn into_response(self) -> Response {
+        Response::new(self).type_("application/octet-stream")
+    }
+}
+
+/// Serializes the wrapped value as an `application/json` body
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        self.try_into_response().unwrap_or_else(|e| e.into_response())
+    }
+
+    fn try_into_response(self) -> Result<Response, HTTPError> {
+        match serde_json::to_vec(&self.0) {
+            Ok(body) => Ok(Response::new(body).type_("application/json; charset=utf-8")),
+            Err(e) => Err(HTTPError::internal_server_error(&format!("Failed to serialize JSON response: {}", e)))
+        }
+    }
+}
+
+/// Redirects the client to another location, `302 Found` unless changed
+pub struct Redirect {
+    location: String,
+    permanent: bool,
+    rewritable: bool
+}
+
+impl Redirect {
+    pub fn to(location: &str) -> Self {
+        Self {
+            location: String::from(location),
+            permanent: false,
+            rewritable: true
+        }
+    }
+
+    /// Use `301`/`308` instead of `302`/`307`
+    pub fn permanent(mut self) -> Self {
+        self.permanent = true;
+        self
+    }
+
+    /// Whether the client may change the method to GET when following the redirect, `307`/`308` if not
+    pub fn rewritable(mut self, rewritable: bool) -> Self {
+        self.rewritable = rewritable;
+        self
+    }
+}
+
+impl IntoResponse for Redirect {
+    fn into_response(self) -> Response {
+        let code = match (self.permanent, self.rewritable) {
+            (false, true) => HTTPStatusCodes::Found302,
+            (true, true) => HTTPStatusCodes::MovedPermanently301,
+            (false, false) => HTTPStatusCodes::TemporaryRedirect307,
+            (true, false) => HTTPStatusCodes::PermanentRedirect308
+        };
+
+        Response::from_code(code).header("Location", &self.location)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{IntoResponse, Json, Redirect};
+    use super::super::Response;
+    use std::collections::HashMap;
+
+    fn content_type(response: &Response) -> Option<String> {
+        response.get_headers().find("Content-Type").cloned()
+    }
+
+    #[test]
+    fn plain_values_become_responses() {
+        let empty = ().into_response();
+        assert_eq!((empty.get_code(), empty.get_reason()), (204, String::from("No Content")));
+
+        let text = "hello".into_response();
+        assert_eq!(text.get_code(), 200);
+        assert_eq!(text.get_body(), b"hello".to_vec());
+        assert_eq!(content_type(&text), Some(String::from("text/plain; charset=utf-8")));
+        assert_eq!(content_type(&String::from("hello").into_response()), Some(String::from("text/plain; charset=utf-8")));
+
+        let bytes = vec![0u8, 1, 2].into_response();
+        assert_eq!(bytes.get_body(), vec![0, 1, 2]);
+        assert_eq!(content_type(&bytes), Some(String::from("application/octet-stream")));
+
+        let response = Response::new(b"as is".to_vec()).code(202).into_response();
+        assert_eq!((response.get_code(), response.get_body()), (202, b"as is".to_vec()));
+    }
+
+    #[test]
+    fn json_bodies_are_serialized() {
+        let response = Json(vec![1, 2, 3]).into_response();
+        assert_eq!(response.get_code(), 200);
+        assert_eq!(response.get_body(), b"[1,2,3]".to_vec());
+        assert_eq!(content_type(&response), Some(String::from("application/json; charset=utf-8")));
+    }
+
+    #[test]
+    fn unserializable_json_is_a_server_error() {
+        // JSON object keys have to be strings
+        let mut map = HashMap::new();
+        map.insert((1, 2), 3);
+
+        assert_eq!(Json(map.clone()).try_into_response().map_err(|e| e.get_code().to_int()).err(), Some(500));
+        assert_eq!(Json(map).into_response().get_code(), 500);
+    }
+
+    #[test]
+    fn redirects_pick_their_code() {
+        let cases = [
+            (Redirect::to("/a"), 302),
+            (Redirect::to("/a").permanent(), 301),
+            (Redirect::to("/a").rewritable(false), 307),
+            (Redirect::to("/a").permanent().rewritable(false), 308)
+        ];
+        for (redirect, code) in cases {
+            let response = redirect.into_response();
+            assert_eq!(response.get_code(), code);
+            assert_eq!(response.get_headers().find("Location"), Some(&String::from("/a")));
+            assert!(response.get_body().is_empty());
+        }
+    }
+}
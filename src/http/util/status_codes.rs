@@ -1,4 +1,5 @@
 #[allow(dead_code)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum HTTPStatusCodes {
     // 1xx Informational Response
     Continue100,
@@ -215,4 +216,77 @@ impl HTTPStatusCodes {
             HTTPStatusCodes::NetworkAuthenticationRequired511 => 511
         }
     }
+
+    pub fn from_int(code: u32) -> Option<Self> {
+        match code {
+            100 => Some(HTTPStatusCodes::Continue100),
+            101 => Some(HTTPStatusCodes::SwitchingProtocols101),
+            102 => Some(HTTPStatusCodes::Processing102),
+            103 => Some(HTTPStatusCodes::EarlyHints103),
+
+            200 => Some(HTTPStatusCodes::Ok200),
+            201 => Some(HTTPStatusCodes::Created201),
+            202 => Some(HTTPStatusCodes::Accepted202),
+            203 => Some(HTTPStatusCodes::NonAuthoritativeInformation203),
+            204 => Some(HTTPStatusCodes::NoContent204),
+            205 => Some(HTTPStatusCodes::ResetContent205),
+            206 => Some(HTTPStatusCodes::PartialContent206),
+            207 => Some(HTTPStatusCodes::MultiStatus207),
+            208 => Some(HTTPStatusCodes::AlreadyReported208),
+            226 => Some(HTTPStatusCodes::IMUsed226),
+
+            300 => Some(HTTPStatusCodes::MultipleChoices300),
+            301 => Some(HTTPStatusCodes::MovedPermanently301),
+            302 => Some(HTTPStatusCodes::Found302),
+            303 => Some(HTTPStatusCodes::SeeOther303),
+            304 => Some(HTTPStatusCodes::NotModified304),
+            305 => Some(HTTPStatusCodes::UseProxy305),
+            306 => Some(HTTPStatusCodes::SwitchProxy306),
+            307 => Some(HTTPStatusCodes::TemporaryRedirect307),
+            308 => Some(HTTPStatusCodes::PermanentRedirect308),
+
+            400 => Some(HTTPStatusCodes::BadRequest400),
+            401 => Some(HTTPStatusCodes::Unauthorized401),
+            402 => Some(HTTPStatusCodes::PaymentRequired402),
+            403 => Some(HTTPStatusCodes::Forbidden403),
+            404 => Some(HTTPStatusCodes::NotFound404),
+            405 => Some(HTTPStatusCodes::MethodNotAllowed405),
+            406 => Some(HTTPStatusCodes::NotAcceptable406),
+            407 => Some(HTTPStatusCodes::ProxyAuthenticationRequired407),
+            408 => Some(HTTPStatusCodes::RequestTimeout408),
+            409 => Some(HTTPStatusCodes::Conflict409),
+            410 => Some(HTTPStatusCodes::Gone410),
+            411 => Some(HTTPStatusCodes::LengthRequired411),
+            412 => Some(HTTPStatusCodes::PreconditionFailed412),
+            413 => Some(HTTPStatusCodes::PayloadTooLarge413),
+            414 => Some(HTTPStatusCodes::URITooLong414),
+            415 => Some(HTTPStatusCodes::UnsupportedMediaType415),
+            416 => Some(HTTPStatusCodes::RangeNotSatisfiable416),
+            417 => Some(HTTPStatusCodes::ExpectationFailed417),
+            418 => Some(HTTPStatusCodes::ImATeapot418),
+            421 => Some(HTTPStatusCodes::MisdirectedRequest421),
+            422 => Some(HTTPStatusCodes::UnprocessableEntity422),
+            423 => Some(HTTPStatusCodes::Locked423),
+            424 => Some(HTTPStatusCodes::FailedDependency424),
+            426 => Some(HTTPStatusCodes::UpgradeRequired426),
+            428 => Some(HTTPStatusCodes::PreconditionRequired428),
+            429 => Some(HTTPStatusCodes::TooManyRequests429),
+            431 => Some(HTTPStatusCodes::RequestHeaderFieldsTooLarge431),
+            451 => Some(HTTPStatusCodes::UnavailableForLegalReasons451),
+
+            500 => Some(HTTPStatusCodes::InternalServerError500),
+            501 => Some(HTTPStatusCodes::NotImplemented501),
+            502 => Some(HTTPStatusCodes::BadGateway502),
+            503 => Some(HTTPStatusCodes::ServiceUnavailable503),
+            504 => Some(HTTPStatusCodes::GatewayTimeout504),
+            505 => Some(HTTPStatusCodes::HTTPVersionNotSupported505),
+            506 => Some(HTTPStatusCodes::VariantAlsoNegotiates506),
+            507 => Some(HTTPStatusCodes::InsufficientStorage507),
+            508 => Some(HTTPStatusCodes::LoopDetected508),
+            510 => Some(HTTPStatusCodes::NotExtended510),
+            511 => Some(HTTPStatusCodes::NetworkAuthenticationRequired511),
+
+            _ => None
+        }
+    }
 }
\ No newline at end of file
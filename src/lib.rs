@@ -1,3 +1,4 @@
 extern crate serde;
+extern crate serde_json;
 pub mod server;
 pub mod http;
@@ -0,0 +1,380 @@
+use super::log::civil_from_days;
+use super::super::super::http::request::Request;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Layout of the access log lines
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum AccessLogFormat {
+    /// Apache Common Log Format
+    Common,
+    /// Apache Combined Log Format, the Common format plus referer and user agent
+    #[default]
+    Combined,
+    /// One JSON object per line
+    Json
+}
+
+/// One served request
+pub struct AccessLogEntry {
+    remote_addr: Option<IpAddr>,
+    time: SystemTime,
+    request_line: Option<String>,
+    method: Option<String>,
+    path: Option<String>,
+    status: u32,
+    bytes: usize,
+    referer: Option<String>,
+    user_agent: Option<String>,
+    duration: Option<Duration>
+}
+
+impl AccessLogEntry {
+    /// Describe the response to the request, if it could be parsed, sent to the client at `remote_addr`
+    pub fn new(request: Option<&Request>, remote_addr: Option<IpAddr>, status: u32, bytes: usize) -> Self {
+        Self {
+            remote_addr,
+            time: request.map(Request::get_received_at).unwrap_or_else(SystemTime::now),
+            request_line: request.map(|req| format!("{} {} {}", req.get_method().to_string(), req.get_path(), req.get_version().to_string())),
+            method: request.map(|req| req.get_method().to_string()),
+            path: request.map(Request::get_path),
+            status,
+            bytes,
+            referer: request.and_then(|req| find_header(req, "Referer")),
+            user_agent: request.and_then(|req| find_header(req, "User-Agent")),
+            duration: request.map(Request::get_elapsed)
+        }
+    }
+
+    /// `127.0.0.1 - - [19/Oct/2026:07:14:53 +0000] "GET / HTTP/1.1" 200 2`
+    pub fn to_common(&self) -> String {
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.remote_addr.map(|addr| addr.to_string()).unwrap_or_else(|| String::from("-")),
+            format_clf_time(self.time),
+            self.request_line.as_ref().map(|line| escape(line)).unwrap_or_else(|| String::from("-")),
+            self.status,
+            match self.bytes {
+                0 => String::from("-"),
+                bytes => bytes.to_string()
+            }
+        )
+    }
+
+    pub fn to_combined(&self) -> String {
+        format!(
+            "{} \"{}\" \"{}\"",
+            self.to_common(),
+            self.referer.as_ref().map(|referer| escape(referer)).unwrap_or_else(|| String::from("-")),
+            self.user_agent.as_ref().map(|agent| escape(agent)).unwrap_or_else(|| String::from("-"))
+        )
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "remoteAddr": self.remote_addr.map(|addr| addr.to_string()),
+            "time": format_clf_time(self.time),
+            "method": self.method,
+            "path": self.path,
+            "status": self.status,
+            "bytes": self.bytes,
+            "referer": self.referer,
+            "userAgent": self.user_agent,
+            "durationMs": self.duration.map(|duration| duration.as_secs_f64() * 1000.0)
+        }).to_string()
+    }
+}
+
+/// Writes access log lines to a file, rotating it once it grows past a size
+pub struct AccessLog {
+    format: AccessLogFormat,
+    file: Mutex<RotatingFile>
+}
+
+impl AccessLog {
+    pub fn new(path: PathBuf, format: AccessLogFormat, max_size: Option<u64>, max_files: usize) -> Self {
+        Self {
+            format,
+            file: Mutex::new(RotatingFile {
+                path,
+                max_size,
+                max_files,
+                file: None,
+                size: 0
+            })
+        }
+    }
+
+    pub fn write(&self, entry: &AccessLogEntry) -> io::Result<()> {
+        let line = match self.format {
+            AccessLogFormat::Common => entry.to_common(),
+            AccessLogFormat::Combined => entry.to_combined(),
+            AccessLogFormat::Json => entry.to_json()
+        };
+
+        self.file.lock().unwrap_or_else(PoisonError::into_inner).write_line(&line)
+    }
+}
+
+struct RotatingFile {
+    path: PathBuf,
+    max_size: Option<u64>,
+    max_files: usize,
+    // Opened on the first write and again after every rotation
+    file: Option<File>,
+    size: u64
+}
+
+impl RotatingFile {
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        let length = line.len() as u64 + 1;
+
+        self.open()?;
+        if let Some(max_size) = self.max_size {
+            if self.size > 0 && self.size + length > max_size {
+                self.rotate()?;
+                self.open()?;
+            }
+        }
+
+        if let Some(ref mut file) = self.file {
+            writeln!(file, "{}", line)?;
+            self.size += length;
+        }
+        Ok(())
+    }
+
+    fn open(&mut self) -> io::Result<()> {
+        if self.file.is_none() {
+            let file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.size = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        Ok(())
+    }
+
+    /// Shift `access.log` to `access.log.1`, `access.log.1` to `access.log.2` and so on, dropping the oldest
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        self.size = 0;
+
+        if self.max_files == 0 {
+            return fs::remove_file(&self.path);
+        }
+
+        let oldest = numbered(&self.path, self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for n in (1..self.max_files).rev() {
+            let from = numbered(&self.path, n);
+            if from.exists() {
+                fs::rename(&from, numbered(&self.path, n + 1))?;
+            }
+        }
+
+        fs::rename(&self.path, numbered(&self.path, 1))
+    }
+}
+
+fn numbered(path: &Path, n: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+fn find_header(request: &Request, name: &str) -> Option<String> {
+    request.get_headers().find(name).cloned()
+}
+
+/// Keep quoted fields on one line and their quotes balanced
+fn escape(value: &str) -> String {
+    value.escape_debug().to_string()
+}
+
+/// `19/Oct/2026:07:14:53 +0000`
+fn format_clf_time(time: SystemTime) -> String {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let seconds = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{:02}/{}/{}:{:02}:{:02}:{:02} +0000",
+        day, MONTHS[month as usize - 1], year,
+        seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AccessLog, AccessLogEntry, AccessLogFormat, format_clf_time, numbered};
+    use super::super::super::super::http::{HTTPMethod, HTTPVersion, Header, request::Request};
+    use std::fs;
+    use std::net::IpAddr;
+    use std::path::PathBuf;
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn entry(bytes: usize, user_agent: Option<&str>) -> AccessLogEntry {
+        AccessLogEntry {
+            remote_addr: Some(IpAddr::from([127, 0, 0, 1])),
+            time: UNIX_EPOCH + Duration::from_secs(1_792_395_293),
+            request_line: Some(String::from("GET /a\"b HTTP/1.1")),
+            method: Some(String::from("GET")),
+            path: Some(String::from("/a\"b")),
+            status: 200,
+            bytes,
+            referer: None,
+            user_agent: user_agent.map(String::from),
+            duration: Some(Duration::from_micros(1500))
+        }
+    }
+
+    /// An empty directory of its own for each test
+    fn directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("hapi-access-log-{}-{}", std::process::id(), name));
+        fs::remove_dir_all(&directory).unwrap_or_default();
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn lines(path: &PathBuf) -> Vec<String> {
+        fs::read_to_string(path).map(|content| content.lines().map(String::from).collect()).unwrap_or_default()
+    }
+
+    #[test]
+    fn common_lines() {
+        assert_eq!(entry(2, None).to_common(), "127.0.0.1 - - [19/Oct/2026:07:34:53 +0000] \"GET /a\\\"b HTTP/1.1\" 200 2");
+        assert_eq!(entry(0, None).to_common(), "127.0.0.1 - - [19/Oct/2026:07:34:53 +0000] \"GET /a\\\"b HTTP/1.1\" 200 -");
+
+        let unparsed = AccessLogEntry {
+            remote_addr: None,
+            request_line: None,
+            ..entry(0, None)
+        };
+        assert_eq!(unparsed.to_common(), "- - - [19/Oct/2026:07:34:53 +0000] \"-\" 200 -");
+    }
+
+    #[test]
+    fn combined_lines() {
+        assert_eq!(entry(2, None).to_combined(), format!("{} \"-\" \"-\"", entry(2, None).to_common()));
+        assert_eq!(
+            entry(2, Some("curl/8 \"x\"\nforged")).to_combined(),
+            format!("{} \"-\" \"curl/8 \\\"x\\\"\\nforged\"", entry(2, None).to_common())
+        );
+    }
+
+    #[test]
+    fn json_lines() {
+        let json: serde_json::Value = serde_json::from_str(&entry(2, Some("curl/8")).to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "remoteAddr": "127.0.0.1",
+            "time": "19/Oct/2026:07:34:53 +0000",
+            "method": "GET",
+            "path": "/a\"b",
+            "status": 200,
+            "bytes": 2,
+            "referer": null,
+            "userAgent": "curl/8",
+            "durationMs": 1.5
+        }));
+    }
+
+    #[test]
+    fn entries_from_requests() {
+        let mut headers = Header::new();
+        headers.insert(String::from("Referer"), String::from("https://example.com/"));
+        headers.insert(String::from("User-Agent"), String::from("curl/8"));
+        let request = Request::new(HTTPMethod::POST, String::from("/items"), HTTPVersion::HTTP11, headers, String::new());
+
+        let entry = AccessLogEntry::new(Some(&request), None, 201, 7);
+        assert!(entry.to_combined().ends_with("\"POST /items HTTP/1.1\" 201 7 \"https://example.com/\" \"curl/8\""));
+
+        let entry = AccessLogEntry::new(None, None, 400, 0);
+        assert!(entry.to_combined().ends_with("\"-\" 400 - \"-\" \"-\""));
+        assert!(entry.time <= SystemTime::now());
+    }
+
+    #[test]
+    fn clf_times() {
+        assert_eq!(format_clf_time(UNIX_EPOCH), "01/Jan/1970:00:00:00 +0000");
+        assert_eq!(format_clf_time(UNIX_EPOCH + Duration::from_secs(951_868_799)), "29/Feb/2000:23:59:59 +0000");
+        assert_eq!(format_clf_time(UNIX_EPOCH + Duration::from_secs(1_798_761_599)), "31/Dec/2026:23:59:59 +0000");
+    }
+
+    #[test]
+    fn numbered_paths() {
+        assert_eq!(numbered(&PathBuf::from("/var/log/access.log"), 2), PathBuf::from("/var/log/access.log.2"));
+    }
+
+    #[test]
+    fn lines_are_appended_in_the_chosen_format() {
+        let path = directory("append").join("access.log");
+        fs::write(&path, "earlier\n").unwrap();
+
+        let log = AccessLog::new(path.clone(), AccessLogFormat::Common, None, 3);
+        log.write(&entry(2, None)).unwrap();
+        log.write(&entry(3, None)).unwrap();
+
+        assert_eq!(lines(&path), vec![String::from("earlier"), entry(2, None).to_common(), entry(3, None).to_common()]);
+        assert!(!numbered(&path, 1).exists());
+    }
+
+    #[test]
+    fn files_rotate_past_the_size_and_drop_the_oldest() {
+        let path = directory("rotate").join("access.log");
+        let line = entry(2, None).to_common();
+        // Two lines fit, the third rotates
+        let log = AccessLog::new(path.clone(), AccessLogFormat::Common, Some(2 * (line.len() as u64 + 1)), 2);
+
+        for bytes in 1..=7 {
+            log.write(&entry(bytes, None)).unwrap();
+        }
+
+        let statuses = |path: &PathBuf| -> Vec<String> {
+            lines(path).iter().map(|line| line.rsplit(' ').next().unwrap().to_string()).collect()
+        };
+        assert_eq!(statuses(&path), vec!["7"]);
+        assert_eq!(statuses(&numbered(&path, 1)), vec!["5", "6"]);
+        assert_eq!(statuses(&numbered(&path, 2)), vec!["3", "4"]);
+        assert!(!numbered(&path, 3).exists());
+    }
+
+    #[test]
+    fn lines_larger_than_the_limit_are_still_written() {
+        let path = directory("oversized").join("access.log");
+        let log = AccessLog::new(path.clone(), AccessLogFormat::Json, Some(10), 1);
+
+        log.write(&entry(2, None)).unwrap();
+        assert_eq!(lines(&path).len(), 1);
+        assert!(!numbered(&path, 1).exists());
+
+        log.write(&entry(3, None)).unwrap();
+        assert_eq!(lines(&path), vec![entry(3, None).to_json()]);
+        assert_eq!(lines(&numbered(&path, 1)), vec![entry(2, None).to_json()]);
+    }
+
+    #[test]
+    fn no_kept_files_truncates_instead() {
+        let path = directory("truncate").join("access.log");
+        let log = AccessLog::new(path.clone(), AccessLogFormat::Combined, Some(1), 0);
+
+        log.write(&entry(2, None)).unwrap();
+        log.write(&entry(3, None)).unwrap();
+
+        assert_eq!(lines(&path), vec![entry(3, None).to_combined()]);
+        assert!(!numbered(&path, 1).exists());
+    }
+
+    #[test]
+    fn unwritable_paths_fail() {
+        let path = directory("unwritable").join("missing").join("access.log");
+        let log = AccessLog::new(path, AccessLogFormat::Common, None, 1);
+        assert!(log.write(&entry(2, None)).is_err());
+    }
+}
@@ -0,0 +1,86 @@
+use super::super::super::http::HTTPError;
+use super::super::super::http::request::Request;
+use super::super::super::http::request::credentials::Credentials;
+#[cfg(feature = "tls")]
+use super::super::super::http::request::certificate::SubjectAltName;
+
+/// Authenticates requests to the routes that name it, like a hapi auth strategy
+/// Errors are answered as the route's, usually a `401` or `403`
+pub trait AuthStrategy: Send + Sync {
+    fn authenticate(&self, request: &Request) -> Result<Credentials, HTTPError>;
+}
+
+impl<F> AuthStrategy for F where F: Fn(&Request) -> Result<Credentials, HTTPError> + Send + Sync {
+    fn authenticate(&self, request: &Request) -> Result<Credentials, HTTPError> {
+        self(request)
+    }
+}
+
+/// Authenticates clients by the certificate they presented during the TLS handshake
+/// Without allow lists any certificate the CA bundle verified is accepted, otherwise it has to match one entry
+#[cfg(feature = "tls")]
+#[derive(Debug, Clone, Default)]
+pub struct ClientCertificateAuth {
+    subjects: Vec<String>,
+    alt_names: Vec<SubjectAltName>,
+    fingerprints: Vec<String>
+}
+
+#[cfg(feature = "tls")]
+impl ClientCertificateAuth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Accept certificates with this subject, `CN=billing,O=Example`
+    pub fn allow_subject(mut self, subject: &str) -> Self {
+        self.subjects.push(String::from(subject));
+        self
+    }
+
+    /// Accept certificates issued for this name, such as a SPIFFE id
+    pub fn allow_alt_name(mut self, alt_name: SubjectAltName) -> Self {
+        self.alt_names.push(alt_name);
+        self
+    }
+
+    /// Accept the certificate with this SHA-256 fingerprint, hex with or without colons
+    pub fn allow_fingerprint(mut self, fingerprint: &str) -> Self {
+        self.fingerprints.push(fingerprint.replace(':', "").to_ascii_lowercase());
+        self
+    }
+}
+
+#[cfg(feature = "tls")]
+impl AuthStrategy for ClientCertificateAuth {
+    fn authenticate(&self, request: &Request) -> Result<Credentials, HTTPError> {
+        let certificate = match request.get_info().get_client_certificate() {
+            Some(certificate) => certificate,
+            None => return Err(HTTPError::unauthorized("Client certificate required"))
+        };
+
+        let alt_names = certificate.get_subject_alt_names();
+        let restricted = !self.subjects.is_empty() || !self.alt_names.is_empty() || !self.fingerprints.is_empty();
+        let allowed = self.subjects.contains(&certificate.get_subject())
+            || self.alt_names.iter().any(|alt_name| alt_names.contains(alt_name))
+            || self.fingerprints.contains(&certificate.get_fingerprint());
+        if restricted && !allowed {
+            return Err(HTTPError::forbidden("Client certificate not allowed"));
+        }
+
+        // A SPIFFE id names a service better than its subject
+        let identity = alt_names.iter()
+            .find_map(|alt_name| match alt_name {
+                SubjectAltName::Uri(uri) => Some(uri.clone()),
+                _ => None
+            })
+            .unwrap_or_else(|| certificate.get_subject());
+
+        let credentials = Credentials::new(identity)
+            .with_claim("subject", &certificate.get_subject())
+            .with_claim("issuer", &certificate.get_issuer())
+            .with_claim("serial", &certificate.get_serial())
+            .with_claim("fingerprint", &certificate.get_fingerprint());
+        Ok(alt_names.iter().fold(credentials, |credentials, alt_name| credentials.with_claim("alt_name", &alt_name.to_string())))
+    }
+}
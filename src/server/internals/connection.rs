@@ -0,0 +1,251 @@
+use super::http2::stream::StreamSink;
+use super::super::super::http::Response;
+use std::io::{self, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::time::Duration;
+#[cfg(feature = "tls")]
+use std::sync::{Arc, Mutex, PoisonError};
+
+/// A client's connection, plain TCP or TLS over it
+pub struct Connection {
+    tcp: TcpStream,
+    // Read off the socket ahead of time, such as what followed a PROXY protocol header
+    pending: Vec<u8>,
+    // Shared with the clones answering server timeouts
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<Mutex<rustls::ServerConnection>>>
+}
+
+impl Connection {
+    pub fn new(tcp: TcpStream) -> Self {
+        Self {
+            tcp,
+            pending: vec![],
+            #[cfg(feature = "tls")]
+            tls: None
+        }
+    }
+
+    /// Speak TLS from now on, the handshake happens on the first read
+    #[cfg(feature = "tls")]
+    pub fn start_tls(&mut self, session: rustls::ServerConnection) {
+        self.tls = Some(Arc::new(Mutex::new(session)));
+    }
+
+    pub fn is_secure(&self) -> bool {
+        #[cfg(feature = "tls")]
+        let secure = self.tls.is_some();
+        #[cfg(not(feature = "tls"))]
+        let secure = false;
+        secure
+    }
+
+    /// Whether the client chose HTTP/2 through ALPN, once the handshake is done
+    pub fn is_http2(&self) -> bool {
+        #[cfg(feature = "tls")]
+        let http2 = self.tls.as_ref()
+            .is_some_and(|tls| tls.lock().unwrap_or_else(PoisonError::into_inner).alpn_protocol() == Some(&b"h2"[..]));
+        #[cfg(not(feature = "tls"))]
+        let http2 = false;
+        http2
+    }
+
+    /// The certificate chain the client presented, once the handshake is done
+    #[cfg(feature = "tls")]
+    pub fn peer_certificates(&self) -> Option<Vec<rustls::pki_types::CertificateDer<'static>>> {
+        let tls = self.tls.as_ref()?.lock().unwrap_or_else(PoisonError::into_inner);
+        tls.peer_certificates().map(<[_]>::to_vec)
+    }
+
+    /// Hand bytes back to be read again before anything else on the socket
+    pub fn unread(&mut self, bytes: &[u8]) {
+        self.pending.splice(0..0, bytes.iter().copied());
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.peer_addr()
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.tcp.local_addr()
+    }
+
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_read_timeout(timeout)
+    }
+
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.tcp.set_write_timeout(timeout)
+    }
+
+    pub fn set_nodelay(&self, nodelay: bool) -> io::Result<()> {
+        self.tcp.set_nodelay(nodelay)
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            tcp: self.tcp.try_clone()?,
+            pending: vec![],
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone()
+        })
+    }
+
+    /// Decrypt what the client sent, the session is only locked in between reads of the socket
+    /// so HTTP/2 streams can write while the connection waits for the client
+    #[cfg(feature = "tls")]
+    fn read_tls(&mut self, tls: &Mutex<rustls::ServerConnection>, buf: &mut [u8]) -> io::Result<usize> {
+        let mut received = [0; 16 * 1024];
+        loop {
+            {
+                let mut tls = tls.lock().unwrap_or_else(PoisonError::into_inner);
+                // Handshake messages and alerts go out before waiting for more
+                while tls.wants_write() {
+                    tls.write_tls(&mut &self.tcp)?;
+                }
+                match tls.reader().read(buf) {
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    result => return result
+                }
+            }
+
+            let size = Socket { tcp: &self.tcp, pending: &mut self.pending }.read(&mut received)?;
+            let mut tls = tls.lock().unwrap_or_else(PoisonError::into_inner);
+            let mut records = &received[..size];
+            // Reading nothing tells the session the client closed the connection
+            tls.read_tls(&mut records)?;
+            // Whatever the session had no room for is read again next time
+            self.pending.splice(0..0, records.iter().copied());
+
+            if let Err(e) = tls.process_new_packets() {
+                while tls.wants_write() {
+                    if tls.write_tls(&mut &self.tcp).is_err() {
+                        break;
+                    }
+                }
+                return Err(io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+        }
+    }
+
+    /// Close the connection, telling TLS clients first
+    pub fn shutdown(&mut self) {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            let mut tls = tls.lock().unwrap_or_else(PoisonError::into_inner);
+            tls.send_close_notify();
+            while tls.wants_write() {
+                if tls.write_tls(&mut &self.tcp).is_err() {
+                    break;
+                }
+            }
+        }
+
+        self.tcp.shutdown(Shutdown::Both).unwrap_or_default();
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls.clone() {
+            return self.read_tls(&tls, buf);
+        }
+
+        Socket { tcp: &self.tcp, pending: &mut self.pending }.read(buf)
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            let mut tls = tls.lock().unwrap_or_else(PoisonError::into_inner);
+            let size = tls.writer().write(buf)?;
+            while tls.wants_write() {
+                tls.write_tls(&mut &self.tcp)?;
+            }
+            return Ok(size);
+        }
+
+        (&self.tcp).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        #[cfg(feature = "tls")]
+        if let Some(ref tls) = self.tls {
+            let mut tls = tls.lock().unwrap_or_else(PoisonError::into_inner);
+            tls.writer().flush()?;
+            while tls.wants_write() {
+                tls.write_tls(&mut &self.tcp)?;
+            }
+        }
+
+        (&self.tcp).flush()
+    }
+}
+
+/// Where a response goes, the whole connection on HTTP/1 or one stream of an HTTP/2 connection
+pub enum Sink {
+    Connection(Connection),
+    Stream(StreamSink)
+}
+
+impl Sink {
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        match self {
+            Sink::Connection(connection) => connection.peer_addr(),
+            Sink::Stream(stream) => stream.peer_addr()
+        }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Sink::Connection(connection) => connection.try_clone().map(Sink::Connection),
+            Sink::Stream(stream) => Ok(Sink::Stream(stream.clone()))
+        }
+    }
+
+    /// Write the response and close the connection or end the stream, returning the size of the body sent
+    pub fn send(self, response: &Response) -> io::Result<usize> {
+        match self {
+            Sink::Connection(mut connection) => {
+                let sent = response.write_to(&mut connection);
+                connection.shutdown();
+                sent
+            },
+            Sink::Stream(stream) => stream.send(response)
+        }
+    }
+}
+
+impl From<Connection> for Sink {
+    fn from(connection: Connection) -> Self {
+        Sink::Connection(connection)
+    }
+}
+
+impl From<StreamSink> for Sink {
+    fn from(stream: StreamSink) -> Self {
+        Sink::Stream(stream)
+    }
+}
+
+/// The raw socket, serving the pending bytes first
+struct Socket<'a> {
+    tcp: &'a TcpStream,
+    pending: &'a mut Vec<u8>
+}
+
+impl<'a> Read for Socket<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pending.is_empty() {
+            return self.tcp.read(buf);
+        }
+
+        let size = buf.len().min(self.pending.len());
+        buf[..size].copy_from_slice(&self.pending[..size]);
+        self.pending.drain(..size);
+        Ok(size)
+    }
+}
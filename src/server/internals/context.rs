@@ -0,0 +1,537 @@
+use super::access_log::{AccessLog, AccessLogEntry};
+use super::auth::AuthStrategy;
+#[cfg(feature = "tls")]
+use super::super::super::http::request::certificate::ClientCertificate;
+use super::connection::{Connection, Sink};
+use super::events::{EventName, Events, ResponseEvent, ServerEvent};
+use super::executor::{Executor, ThreadExecutor};
+use super::fallback::{FallbackHandler, Fallbacks};
+use super::http2::{self, ConnectionTracker};
+use super::load::Load;
+use super::log::Logger;
+use super::options::ServerOptions;
+use super::prometheus::HttpMetrics;
+use super::proxy::TrustedProxies;
+use super::proxy_protocol::ProxyHeader;
+use super::reader::ReadTimeouts;
+use super::route::RouteHandler;
+use super::thread_pool::job::panic_message;
+use super::timer::Timer;
+#[cfg(feature = "tls")]
+use super::tls::Tls;
+use super::tracing::SpanExporter;
+use super::super::super::http::h2::settings::Settings;
+use super::super::super::http::{
+    ErrorFormat,
+    HTTPError,
+    HTTPMethod,
+    HTTPVersion,
+    request::Request,
+    request::log::{LogLevel, RequestChannel},
+    request::trace::{Span, SpanKind, TraceContext},
+    request::info::RequestInfo,
+    response::Response
+};
+use std::panic::{self, AssertUnwindSafe};
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Server wide settings shared by every connection
+pub struct ServerContext {
+    error_format: ErrorFormat,
+    expose_server_errors: bool,
+    fallbacks: Fallbacks,
+    load: Load,
+    read_timeouts: ReadTimeouts,
+    timer: Timer,
+    executor: OnceLock<Arc<dyn Executor>>,
+    executor_threads: usize,
+    logger: Logger,
+    events: Arc<Events>,
+    writer: ResponseWriter,
+    metrics: Option<Arc<HttpMetrics>>,
+    next_request_id: AtomicU64,
+    honor_request_id: bool,
+    trace_sampling_ratio: f64,
+    trusted_proxies: TrustedProxies,
+    auth_strategies: HashMap<String, Arc<dyn AuthStrategy>>,
+    http2_settings: Settings,
+    http2_connections: Arc<ConnectionTracker>,
+    #[cfg(feature = "tls")]
+    tls: Option<Tls>,
+    stopping: AtomicBool
+}
+
+impl ServerContext {
+    pub fn new(options: &ServerOptions) -> Self {
+        let events = Arc::new(Events::default());
+        let metrics = options.get_metrics_route().map(|_| Arc::new(HttpMetrics::default()));
+        let logger = Logger::new(options.get_log_level(), options.get_log_sink()).with_events(Arc::clone(&events));
+        let access_log = options.get_access_log().map(|path| {
+            Arc::new(AccessLog::new(path, options.get_access_log_format(), options.get_access_log_max_size(), options.get_access_log_max_files()))
+        });
+        #[cfg(feature = "tls")]
+        let tls = match options.get_tls_certificates().is_empty() {
+            true => None,
+            false => Some(
+                Tls::new(options, logger.clone())
+                    .unwrap_or_else(|e| panic!("Invalid TLS configuration: {}", e))
+            )
+        };
+
+        Self {
+            error_format: options.get_error_format(),
+            expose_server_errors: options.get_expose_server_errors(),
+            fallbacks: Fallbacks::default(),
+            load: Load::new(options),
+            read_timeouts: ReadTimeouts::new(options),
+            timer: Timer::new(logger.clone()),
+            executor: OnceLock::new(),
+            executor_threads: options.get_worker_threads(),
+            writer: ResponseWriter {
+                logger: logger.clone(),
+                events: Arc::clone(&events),
+                metrics: metrics.clone(),
+                exporter: options.get_span_exporter(),
+                request_id_header: options.get_request_id_header(),
+                access_log,
+                default_http_version: options.get_default_http_version()
+            },
+            metrics,
+            events,
+            logger,
+            next_request_id: AtomicU64::new(1),
+            honor_request_id: options.get_honor_request_id(),
+            trace_sampling_ratio: options.get_trace_sampling_ratio(),
+            trusted_proxies: TrustedProxies::new(options.get_trusted_proxies()),
+            auth_strategies: HashMap::new(),
+            http2_settings: http2::settings_from(options),
+            http2_connections: Arc::new(ConnectionTracker::default()),
+            #[cfg(feature = "tls")]
+            tls,
+            stopping: AtomicBool::new(false)
+        }
+    }
+
+    /// The executor for async handlers, a `ThreadExecutor` is started the first time one is needed unless another was set
+    pub fn get_executor(&self) -> Arc<dyn Executor> {
+        let executor = self.executor.get_or_init(|| {
+            Arc::new(ThreadExecutor::new(self.executor_threads).logger(self.logger.clone()))
+        });
+        Arc::clone(executor)
+    }
+
+    pub fn set_executor(&mut self, executor: Arc<dyn Executor>) {
+        self.executor = OnceLock::from(executor);
+    }
+
+    pub fn get_logger(&self) -> &Logger {
+        &self.logger
+    }
+
+    pub fn get_events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Request metrics, collected when the options set a metrics route
+    pub fn get_metrics(&self) -> Option<Arc<HttpMetrics>> {
+        self.metrics.clone()
+    }
+
+    /// Starts TLS on every connection when the options have a certificate
+    #[cfg(feature = "tls")]
+    pub fn get_tls(&self) -> Option<&Tls> {
+        self.tls.as_ref()
+    }
+
+    /// `https` when connections use TLS
+    pub fn get_scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        let scheme = match self.tls {
+            Some(_) => "https",
+            None => "http"
+        };
+        #[cfg(not(feature = "tls"))]
+        let scheme = "http";
+        scheme
+    }
+
+    /// What the server announces to HTTP/2 clients in its SETTINGS
+    pub fn get_http2_settings(&self) -> Settings {
+        self.http2_settings
+    }
+
+    /// The HTTP/2 connections being served, which a stopping server waits for
+    pub fn get_http2_connections(&self) -> &Arc<ConnectionTracker> {
+        &self.http2_connections
+    }
+
+    /// Whether the server threads should stop accepting connections
+    pub fn is_stopping(&self) -> bool {
+        self.stopping.load(Ordering::SeqCst)
+    }
+
+    pub fn set_stopping(&self) {
+        self.stopping.store(true, Ordering::SeqCst);
+    }
+
+    /// Give a freshly parsed request its id and info, a handle to the logger and its trace
+    /// The addresses of a PROXY protocol header stand in for the connection's own
+    pub fn track_request(&self, request: &mut Request, received: Instant, stream: &Connection, proxy: Option<ProxyHeader>) {
+        let headers = request.get_headers();
+        let id = match self.honor_request_id {
+            true => headers.find(&self.writer.request_id_header).filter(|id| is_valid_request_id(id)).cloned(),
+            false => None
+        };
+        let id = id.unwrap_or_else(|| self.next_request_id.fetch_add(1, Ordering::Relaxed).to_string());
+
+        let peer = proxy.and_then(|proxy| proxy.get_source()).or_else(|| stream.peer_addr().ok());
+        let local = proxy.and_then(|proxy| proxy.get_destination()).or_else(|| stream.local_addr().ok());
+        let info = RequestInfo::new(
+            id,
+            peer,
+            local,
+            SystemTime::now() - received.elapsed(),
+            self.trusted_proxies.resolve(peer, &headers, stream.is_secure())
+        );
+        #[cfg(feature = "tls")]
+        let info = {
+            let mut info = info;
+            info.set_client_certificate(stream.peer_certificates().and_then(|chain| chain.first().and_then(|der| ClientCertificate::parse(der))));
+            info
+        };
+        request.set_context(info, Arc::new(self.logger.clone()), received);
+
+        let trace = headers.find("traceparent")
+            .and_then(|traceparent| TraceContext::parse(traceparent, headers.find("tracestate").map(String::as_str)))
+            .unwrap_or_else(|| TraceContext::sampled(self.trace_sampling_ratio));
+        let record_spans = self.writer.exporter.is_some() && trace.is_sampled();
+        request.set_trace(trace, record_spans);
+        request.record_phase("parse", request.get_received_at());
+
+        request.log_on(RequestChannel::Internal, LogLevel::Debug, &["request"], format!("{} {} {}", request.get_method().to_string(), request.get_path(), request.get_version().to_string()));
+        request.log_on(RequestChannel::Internal, LogLevel::Trace, &["request", "headers"], request.get_headers().get_headers_formatted());
+        request.log_on(RequestChannel::Internal, LogLevel::Trace, &["request", "payload"], request.get_payload());
+
+        if let Some(ref metrics) = self.metrics {
+            metrics.request_started();
+        }
+    }
+
+    pub fn get_read_timeouts(&self) -> &ReadTimeouts {
+        &self.read_timeouts
+    }
+
+    pub fn get_load(&self) -> &Load {
+        &self.load
+    }
+
+    pub fn get_fallbacks_mut(&mut self) -> &mut Fallbacks {
+        &mut self.fallbacks
+    }
+
+    pub fn add_auth_strategy(&mut self, name: String, strategy: Arc<dyn AuthStrategy>) {
+        self.auth_strategies.insert(name, strategy);
+    }
+
+    pub fn has_auth_strategy(&self, name: &str) -> bool {
+        self.auth_strategies.contains_key(name)
+    }
+
+    /// Run the route's auth strategy, its credentials reach the handler through the request
+    pub fn authenticate(&self, strategy: Option<&str>, request: &mut Request) -> Result<(), HTTPError> {
+        let (name, strategy) = match strategy.and_then(|name| self.auth_strategies.get_key_value(name)) {
+            None => return Ok(()),
+            Some(found) => found
+        };
+
+        let started = SystemTime::now();
+        let result = match panic::catch_unwind(AssertUnwindSafe(|| strategy.authenticate(request))) {
+            Ok(result) => result,
+            Err(panic) => Err(self.handler_panicked(request, &*panic))
+        };
+        request.record_phase("auth", started);
+
+        let mut credentials = result?;
+        credentials.set_strategy(name.clone());
+        request.set_auth(credentials);
+        Ok(())
+    }
+
+    /// Authenticate the request, run the route handler and answer the client with its response or error, a panicking handler is answered with a 500
+    /// and one still running after the `server_timeout` with a 503
+    pub fn handle_route(&self, handler: Arc<RouteHandler>, server_timeout: Option<Duration>, auth: Option<String>, mut request: Request, stream: Sink) {
+        let responded = self.begin_route(server_timeout, &request, &stream);
+
+        let result = self.authenticate(auth.as_deref(), &mut request).and_then(|_| {
+            let started = SystemTime::now();
+            let result = match panic::catch_unwind(AssertUnwindSafe(|| handler(&request))) {
+                Ok(result) => result,
+                Err(panic) => Err(self.handler_panicked(&request, &*panic))
+            };
+            request.record_phase("handler", started);
+            result
+        });
+
+        self.finish_route(result, responded, request, stream);
+    }
+
+    /// Start the server timeout of a route, the returned flag is set once the client has been answered
+    pub fn begin_route(&self, server_timeout: Option<Duration>, request: &Request, stream: &Sink) -> Arc<AtomicBool> {
+        let responded = Arc::new(AtomicBool::new(false));
+        if let Some(timeout) = server_timeout {
+            self.schedule_timeout(timeout, Arc::clone(&responded), request, stream);
+        }
+
+        responded
+    }
+
+    pub fn handler_panicked(&self, request: &Request, panic: &(dyn Any + Send)) -> HTTPError {
+        let message = panic_message(panic);
+        request.log_on(RequestChannel::Error, LogLevel::Error, &["handler", "panic"], format!("{} {} handler panicked: {}", request.get_method().to_string(), request.get_path(), message));
+        HTTPError::internal_server_error(&message)
+    }
+
+    /// Answer the client with the handler's response or error unless the server timeout beat it to it
+    pub fn finish_route(&self, result: Result<Response, HTTPError>, responded: Arc<AtomicBool>, request: Request, stream: Sink) {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                let (channel, level) = match e.is_server_error() {
+                    true => (RequestChannel::Error, LogLevel::Error),
+                    false => (RequestChannel::Internal, LogLevel::Debug)
+                };
+                request.log_on(channel, level, &["handler", "error"], format!("{} {}: {}", request.get_method().to_string(), request.get_path(), e));
+                self.fallback(self.fallbacks.get_handler_error(), e, Some(&request))
+            }
+        };
+
+        // The client already got a 503
+        if responded.swap(true, Ordering::SeqCst) {
+            request.log_on(RequestChannel::Internal, LogLevel::Warn, &["handler", "timeout"], format!("{} {} handler finished after its server timeout", request.get_method().to_string(), request.get_path()));
+            return;
+        }
+
+        self.send(response, Some(&request), stream);
+    }
+
+    /// Answer with a 503 once the timeout passes unless the handler responded first
+    fn schedule_timeout(&self, timeout: Duration, responded: Arc<AtomicBool>, request: &Request, stream: &Sink) {
+        let timeout_stream = match stream.try_clone() {
+            Ok(t) => t,
+            Err(e) => return request.log_on(RequestChannel::Internal, LogLevel::Error, &["handler", "timeout", "error"], format!("Failed to clone stream for server timeout: {}", e))
+        };
+
+        let response = self.render_error(HTTPError::service_unavailable("Server timeout"), Some(request));
+
+        let (writer, request) = (self.writer.clone(), request.clone());
+        self.timer.schedule(timeout, move || {
+            if responded.swap(true, Ordering::SeqCst) {
+                return;
+            }
+
+            // Writing blocks on the client, the workers may all be busy with slow handlers like this one
+            let logger = writer.logger.clone();
+            let spawned = thread::Builder::new().name(String::from("server-timeout")).spawn(move || {
+                writer.write(response, Some(&request), timeout_stream);
+            });
+            if let Err(e) = spawned {
+                logger.error(&["handler", "timeout", "error"], format!("Failed to start server timeout thread: {}", e));
+            }
+        });
+    }
+
+    pub fn not_found(&self, request: Request, stream: Sink) {
+        let handler = self.fallbacks.get_not_found(&request.get_path());
+        let response = self.fallback(handler, HTTPError::not_found("Not Found"), Some(&request));
+        self.send(response, Some(&request), stream);
+    }
+
+    /// The path exists but none of its routes accept the method, `allowed` lists the ones that do
+    pub fn method_not_allowed(&self, request: Request, allowed: Vec<HTTPMethod>, stream: Sink) {
+        let allow = allowed.iter().map(|method| method.to_string()).collect::<Vec<String>>().join(", ");
+        let error = HTTPError::method_not_allowed("Method Not Allowed").with_header("Allow", &allow);
+        let response = self.fallback(self.fallbacks.get_method_not_allowed(), error, Some(&request));
+        self.send(response, Some(&request), stream);
+    }
+
+    pub fn bad_request(&self, stream: Sink) {
+        self.logger.debug(&["request", "error"], "Failed to parse request");
+        let response = self.fallback(self.fallbacks.get_bad_request(), HTTPError::bad_request("Invalid request"), None);
+        self.send(response, None, stream);
+    }
+
+    /// Answer with an error that no fallback handler may replace
+    pub fn reject(&self, error: HTTPError, request: Option<&Request>, stream: Sink) {
+        let response = self.render_error(error, request);
+        self.send(response, request, stream);
+    }
+
+    /// Turn the request away because the server is under too much load
+    /// Answer a request the worker queue had no room for
+    pub fn queue_full(&self, request: Option<&Request>, stream: Sink) {
+        self.load.reject_queue_full();
+        self.overloaded(request, stream);
+    }
+
+    pub fn overloaded(&self, request: Option<&Request>, stream: Sink) {
+        let error = HTTPError::service_unavailable("Server under heavy load")
+            .with_header("Retry-After", &self.load.get_retry_after().to_string());
+        let response = self.render_error(error, request);
+        self.send(response, request, stream);
+    }
+
+    /// Let the user handler answer the error, rendering it generically if there is none or it fails too
+    fn fallback(&self, handler: Option<Arc<FallbackHandler>>, error: HTTPError, request: Option<&Request>) -> Response {
+        match handler {
+            None => self.render_error(error, request),
+            Some(handler) => {
+                let fallback_error = error.clone();
+                match panic::catch_unwind(AssertUnwindSafe(|| handler(request, error))) {
+                    Ok(Ok(response)) => response,
+                    Ok(Err(e)) => self.render_error(e, request),
+                    Err(panic) => {
+                        self.logger.log_for(request.map(|req| (req.get_id(), RequestChannel::Error)), LogLevel::Error, &["fallback", "panic"], format!("Fallback handler panicked: {}", panic_message(&*panic)));
+                        self.render_error(fallback_error, request)
+                    }
+                }
+            }
+        }
+    }
+
+    fn render_error(&self, error: HTTPError, request: Option<&Request>) -> Response {
+        let accept = request.and_then(|req| req.get_headers().find("Accept").cloned());
+        let instance = request.map(|req| req.get_path());
+
+        self.error_format.render(error, accept, instance, self.expose_server_errors)
+    }
+
+    fn send(&self, response: Response, request: Option<&Request>, stream: Sink) {
+        self.writer.write(response, request, stream);
+    }
+}
+
+/// Writes responses to clients and records them in the access log
+#[derive(Clone)]
+struct ResponseWriter {
+    logger: Logger,
+    events: Arc<Events>,
+    metrics: Option<Arc<HttpMetrics>>,
+    exporter: Option<Arc<dyn SpanExporter>>,
+    request_id_header: String,
+    access_log: Option<Arc<AccessLog>>,
+    // Status line version of responses to requests that didn't parse
+    default_http_version: HTTPVersion
+}
+
+impl ResponseWriter {
+    fn write(&self, mut response: Response, request: Option<&Request>, stream: Sink) {
+        if let Some(request) = request {
+            response.set_header(self.request_id_header.clone(), request.get_id());
+        }
+        response.set_version(self.response_version(request, &stream));
+        self.logger.log_for(request.map(|req| (req.get_id(), RequestChannel::Internal)), LogLevel::Debug, &["response"], format!("{} {}", response.get_code(), response.get_reason()));
+
+        let remote_addr = stream.peer_addr().ok();
+        let started = SystemTime::now();
+        let bytes = match stream.send(&response) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                self.logger.error(&["response", "error"], format!("Failed to write response: {}", e));
+                0
+            }
+        };
+        if let Some(request) = request {
+            request.get_info().set_responded();
+        }
+
+        if let (Some(ref exporter), Some(request)) = (&self.exporter, request) {
+            request.record_phase("write", started);
+            self.export_spans(exporter.as_ref(), request, &response);
+        }
+
+        if let Some(ref access_log) = self.access_log {
+            let client = request.and_then(|req| req.get_info().get_remote_address());
+            let entry = AccessLogEntry::new(request, client.or(remote_addr.map(|addr| addr.ip())), response.get_code(), bytes);
+            if let Err(e) = access_log.write(&entry) {
+                self.logger.error(&["access-log", "error"], format!("Failed to write access log: {}", e));
+            }
+        }
+
+        if let Some(ref metrics) = self.metrics {
+            metrics.request_finished(request, response.get_code(), request.map(Request::get_elapsed));
+        }
+
+        if self.events.has_listeners(EventName::Response) {
+            self.events.emit(ServerEvent::Response(ResponseEvent::new(
+                request.map(Request::get_id),
+                remote_addr,
+                request.map(Request::get_method),
+                request.map(Request::get_path),
+                response.get_code(),
+                bytes,
+                request.map(Request::get_elapsed)
+            )));
+        }
+    }
+
+    /// The request's own protocol, HTTP/1 connections never see `HTTP/2.0` in a status line
+    fn response_version(&self, request: Option<&Request>, stream: &Sink) -> HTTPVersion {
+        match (stream, request.map(Request::get_version).unwrap_or(self.default_http_version)) {
+            (Sink::Stream(_), _) => HTTPVersion::HTTP20,
+            (Sink::Connection(_), HTTPVersion::HTTP10) => HTTPVersion::HTTP10,
+            (Sink::Connection(_), _) => HTTPVersion::HTTP11
+        }
+    }
+
+    /// Hand the request's span and its phases to the exporter
+    fn export_spans(&self, exporter: &dyn SpanExporter, request: &Request, response: &Response) {
+        let trace = request.get_trace();
+        let mut spans = request.take_phases();
+        if !trace.is_sampled() || spans.is_empty() {
+            return;
+        }
+
+        let route = request.get_route().unwrap_or_else(|| request.get_path());
+        let root = Span::new(&format!("{} {}", request.get_method().to_string(), route), SpanKind::Server, &trace, request.get_received_at(), SystemTime::now())
+            .attribute("http.method", &request.get_method().to_string())
+            .attribute("http.target", &request.get_path())
+            .attribute("http.route", &request.get_route().unwrap_or_default())
+            .attribute("http.status_code", &response.get_code().to_string())
+            .attribute("request.id", &request.get_id());
+
+        spans.insert(0, root);
+        exporter.export(spans);
+    }
+}
+
+impl Default for ServerContext {
+    fn default() -> Self {
+        Self::new(&ServerOptions::default())
+    }
+}
+
+/// Ids taken from clients end up in logs and headers, keep them short and printable
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 128 && id.bytes().all(|b| b.is_ascii_graphic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_valid_request_id;
+
+    #[test]
+    fn client_request_ids_must_be_short_and_printable() {
+        assert!(is_valid_request_id("abc-123"));
+        assert!(is_valid_request_id(&"a".repeat(128)));
+        assert!(!is_valid_request_id(""));
+        assert!(!is_valid_request_id(&"a".repeat(129)));
+        assert!(!is_valid_request_id("has space"));
+        assert!(!is_valid_request_id("line\nbreak"));
+        assert!(!is_valid_request_id("caf\u{e9}"));
+    }
+}
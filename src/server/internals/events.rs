@@ -0,0 +1,274 @@
+use super::log::LogEvent;
+use super::super::super::http::HTTPMethod;
+use super::super::super::http::request::log::RequestChannel;
+use std::net::SocketAddr;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, PoisonError, RwLock};
+use std::time::Duration;
+
+pub type Listener = dyn Fn(&ServerEvent) + Send + Sync;
+
+/// The kinds of events the server emits
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum EventName {
+    Start,
+    Stop,
+    Route,
+    Request,
+    Response,
+    Log
+}
+
+/// A request the server finished answering
+#[derive(Debug, Clone)]
+pub struct ResponseEvent {
+    request_id: Option<String>,
+    remote_addr: Option<SocketAddr>,
+    method: Option<HTTPMethod>,
+    path: Option<String>,
+    status: u32,
+    bytes: usize,
+    duration: Option<Duration>
+}
+
+impl ResponseEvent {
+    pub fn new(request_id: Option<String>, remote_addr: Option<SocketAddr>, method: Option<HTTPMethod>, path: Option<String>, status: u32, bytes: usize, duration: Option<Duration>) -> Self {
+        Self {
+            request_id,
+            remote_addr,
+            method,
+            path,
+            status,
+            bytes,
+            duration
+        }
+    }
+
+    /// `None` if the request couldn't be parsed
+    pub fn get_request_id(&self) -> Option<String> {
+        self.request_id.clone()
+    }
+
+    pub fn get_remote_addr(&self) -> Option<SocketAddr> {
+        self.remote_addr
+    }
+
+    pub fn get_method(&self) -> Option<HTTPMethod> {
+        self.method
+    }
+
+    pub fn get_path(&self) -> Option<String> {
+        self.path.clone()
+    }
+
+    pub fn get_status(&self) -> u32 {
+        self.status
+    }
+
+    /// Size of the body sent
+    pub fn get_bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Time from accepting the connection to finishing the response
+    pub fn get_duration(&self) -> Option<Duration> {
+        self.duration
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ServerEvent {
+    /// The server is listening at `uri`
+    Start { uri: String },
+    Stop,
+    /// A route was added
+    Route { method: Vec<HTTPMethod>, path: String },
+    /// A log event tied to a request
+    Request { channel: RequestChannel, event: LogEvent },
+    Response(ResponseEvent),
+    /// A server log event
+    Log(LogEvent)
+}
+
+impl ServerEvent {
+    pub fn get_name(&self) -> EventName {
+        match self {
+            ServerEvent::Start { .. } => EventName::Start,
+            ServerEvent::Stop => EventName::Stop,
+            ServerEvent::Route { .. } => EventName::Route,
+            ServerEvent::Request { .. } => EventName::Request,
+            ServerEvent::Response(_) => EventName::Response,
+            ServerEvent::Log(_) => EventName::Log
+        }
+    }
+
+    /// Tags of log and request events, other events have none
+    pub fn get_tags(&self) -> Vec<String> {
+        match self {
+            ServerEvent::Request { event, .. } | ServerEvent::Log(event) => event.get_tags(),
+            _ => vec![]
+        }
+    }
+}
+
+/// Which events a listener receives
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    name: EventName,
+    channels: Vec<RequestChannel>,
+    tags: Vec<String>
+}
+
+impl EventFilter {
+    pub fn new(name: EventName) -> Self {
+        Self {
+            name,
+            channels: vec![],
+            tags: vec![]
+        }
+    }
+
+    /// Only request events from these channels, all channels if empty
+    pub fn channels(mut self, channels: Vec<RequestChannel>) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Only events carrying at least one of these tags, any event if empty
+    pub fn tags(mut self, tags: Vec<&str>) -> Self {
+        self.tags = tags.iter().map(|tag| tag.to_string()).collect();
+        self
+    }
+
+    pub fn matches(&self, event: &ServerEvent) -> bool {
+        if event.get_name() != self.name {
+            return false;
+        }
+
+        if let ServerEvent::Request { channel, .. } = event {
+            if !self.channels.is_empty() && !self.channels.contains(channel) {
+                return false;
+            }
+        }
+
+        self.tags.is_empty() || event.get_tags().iter().any(|tag| self.tags.contains(tag))
+    }
+}
+
+impl From<EventName> for EventFilter {
+    fn from(name: EventName) -> Self {
+        Self::new(name)
+    }
+}
+
+/// Hands server events to the listeners subscribed to them
+#[derive(Default)]
+pub struct Events {
+    listeners: RwLock<Vec<(EventFilter, Arc<Listener>)>>
+}
+
+impl Events {
+    pub fn on<F>(&self, filter: EventFilter, listener: F)
+        where F: Fn(&ServerEvent) + Send + Sync + 'static
+    {
+        self.listeners.write().unwrap_or_else(PoisonError::into_inner).push((filter, Arc::new(listener)));
+    }
+
+    /// Whether anyone listens to events of this kind, to skip building events nobody receives
+    pub fn has_listeners(&self, name: EventName) -> bool {
+        self.listeners.read().unwrap_or_else(PoisonError::into_inner).iter().any(|(filter, _)| filter.name == name)
+    }
+
+    pub fn emit(&self, event: ServerEvent) {
+        let listeners: Vec<Arc<Listener>> = self.listeners.read().unwrap_or_else(PoisonError::into_inner).iter()
+            .filter(|(filter, _)| filter.matches(&event))
+            .map(|(_, listener)| Arc::clone(listener))
+            .collect();
+
+        for listener in listeners {
+            // Listeners run on server threads, don't let one take them down
+            // The logger may be what emitted the event so report straight to stderr
+            if panic::catch_unwind(AssertUnwindSafe(|| listener(&event))).is_err() {
+                eprintln!("{:?} event listener panicked", event.get_name());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventFilter, EventName, Events, ServerEvent};
+    use super::super::log::{LogEvent, LogSink, Logger};
+    use super::super::super::super::http::request::log::{LogLevel, RequestChannel};
+    use std::sync::{Arc, Mutex};
+
+    fn log_event(tags: &[&str]) -> LogEvent {
+        let logged = Arc::new(Mutex::new(None));
+        let sink_logged = Arc::clone(&logged);
+        Logger::new(LogLevel::Trace, LogSink::callback(move |event: &LogEvent| *sink_logged.lock().unwrap() = Some(event.clone())))
+            .info(tags, "data");
+        let event = logged.lock().unwrap().take().unwrap();
+        event
+    }
+
+    fn request(channel: RequestChannel, tags: &[&str]) -> ServerEvent {
+        ServerEvent::Request { channel, event: log_event(tags) }
+    }
+
+    #[test]
+    fn filters_match_by_name() {
+        let filter = EventFilter::from(EventName::Stop);
+        assert!(filter.matches(&ServerEvent::Stop));
+        assert!(!filter.matches(&ServerEvent::Start { uri: String::from("http://localhost") }));
+        assert!(!filter.matches(&ServerEvent::Log(log_event(&["server"]))));
+    }
+
+    #[test]
+    fn filters_match_by_channel() {
+        let any = EventFilter::new(EventName::Request);
+        let errors = EventFilter::new(EventName::Request).channels(vec![RequestChannel::Error, RequestChannel::Internal]);
+
+        assert!(any.matches(&request(RequestChannel::App, &["app"])));
+        assert!(errors.matches(&request(RequestChannel::Error, &["app"])));
+        assert!(errors.matches(&request(RequestChannel::Internal, &["app"])));
+        assert!(!errors.matches(&request(RequestChannel::App, &["app"])));
+    }
+
+    #[test]
+    fn filters_match_any_of_their_tags() {
+        let filter = EventFilter::new(EventName::Log).tags(vec!["tls", "timeout"]);
+        assert!(filter.matches(&ServerEvent::Log(log_event(&["server", "timeout"]))));
+        assert!(filter.matches(&ServerEvent::Log(log_event(&["tls"]))));
+        assert!(!filter.matches(&ServerEvent::Log(log_event(&["server"]))));
+        assert!(!filter.matches(&ServerEvent::Log(log_event(&[]))));
+        assert!(EventFilter::new(EventName::Log).matches(&ServerEvent::Log(log_event(&[]))));
+
+        // Tags and channels both have to match
+        let both = EventFilter::new(EventName::Request).channels(vec![RequestChannel::App]).tags(vec!["db"]);
+        assert!(both.matches(&request(RequestChannel::App, &["db"])));
+        assert!(!both.matches(&request(RequestChannel::App, &["cache"])));
+        assert!(!both.matches(&request(RequestChannel::Error, &["db"])));
+
+        // Events without tags never match a tag filter
+        assert!(!EventFilter::new(EventName::Stop).tags(vec!["server"]).matches(&ServerEvent::Stop));
+    }
+
+    #[test]
+    fn listeners_get_matching_events_despite_panicking_neighbours() {
+        let events = Events::default();
+        let received = Arc::new(Mutex::new(vec![]));
+        let listener_received = Arc::clone(&received);
+        events.on(EventFilter::new(EventName::Log), |_event: &ServerEvent| panic!("listener"));
+        events.on(EventFilter::new(EventName::Log).tags(vec!["kept"]), move |event: &ServerEvent| {
+            listener_received.lock().unwrap().push(event.get_tags());
+        });
+
+        assert!(events.has_listeners(EventName::Log));
+        assert!(!events.has_listeners(EventName::Stop));
+
+        events.emit(ServerEvent::Log(log_event(&["kept"])));
+        events.emit(ServerEvent::Log(log_event(&["dropped"])));
+        events.emit(ServerEvent::Stop);
+        assert_eq!(*received.lock().unwrap(), vec![vec![String::from("kept")]]);
+    }
+}
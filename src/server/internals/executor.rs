@@ -0,0 +1,217 @@
+use super::log::Logger;
+use super::thread_pool::job::panic_message;
+use std::any::Any;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, PoisonError, Weak, mpsc};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread;
+
+pub type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send + 'static>>;
+
+/// Drives the futures of async route handlers
+pub trait Executor: Send + Sync {
+    fn spawn(&self, future: BoxFuture<()>);
+}
+
+#[cfg(feature = "tokio")]
+impl Executor for tokio::runtime::Handle {
+    fn spawn(&self, future: BoxFuture<()>) {
+        tokio::runtime::Handle::spawn(self, future);
+    }
+}
+
+/// A minimal executor polling futures on a few dedicated threads, used unless another one is set
+/// Its threads exit once it is dropped and the queued tasks are polled, futures still pending then are dropped
+pub struct ThreadExecutor {
+    sender: Arc<mpsc::Sender<Arc<Task>>>,
+    logger: Logger
+}
+
+struct Task {
+    // Locked while polling so a wake during the poll waits for it rather than getting lost
+    future: Mutex<Option<BoxFuture<()>>>,
+    // Weak so tasks don't keep the queue, and with it the executor threads, alive
+    sender: Weak<mpsc::Sender<Arc<Task>>>
+}
+
+impl Wake for Task {
+    fn wake(self: Arc<Self>) {
+        if let Some(sender) = self.sender.upgrade() {
+            sender.send(self).unwrap_or_default();
+        }
+    }
+}
+
+impl ThreadExecutor {
+    pub fn new(size: usize) -> Self {
+        assert!(size > 0);
+
+        let (sender, receiver) = mpsc::channel::<Arc<Task>>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for _ in 0..size {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || {
+                loop {
+                    let task = match receiver.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                        Ok(task) => task,
+                        Err(_) => break
+                    };
+
+                    let waker = Waker::from(Arc::clone(&task));
+                    let mut context = Context::from_waker(&waker);
+                    let mut slot = task.future.lock().unwrap_or_else(PoisonError::into_inner);
+
+                    if let Some(mut future) = slot.take() {
+                        if future.as_mut().poll(&mut context).is_pending() {
+                            *slot = Some(future);
+                        }
+                    }
+                }
+            });
+        }
+
+        Self {
+            sender: Arc::new(sender),
+            logger: Logger::default()
+        }
+    }
+
+    /// Log panicking tasks here instead of the default stderr logger
+    pub fn logger(mut self, logger: Logger) -> Self {
+        self.logger = logger;
+        self
+    }
+}
+
+impl Executor for ThreadExecutor {
+    fn spawn(&self, future: BoxFuture<()>) {
+        // Futures may panic on any executor, never let one take a thread down
+        let logger = self.logger.clone();
+        let future: BoxFuture<()> = Box::pin(async move {
+            if let Err(panic) = CatchUnwind(future).await {
+                logger.error(&["executor", "panic"], format!("Async task panicked: {}", panic_message(&*panic)));
+            }
+        });
+
+        let task = Arc::new(Task {
+            future: Mutex::new(Some(future)),
+            sender: Arc::downgrade(&self.sender)
+        });
+
+        self.sender.send(task).unwrap_or_default();
+    }
+}
+
+/// Resolves to `Err` with the panic payload if polling the future panics
+pub struct CatchUnwind<T>(pub BoxFuture<T>);
+
+impl<T> Future for CatchUnwind<T> {
+    type Output = Result<T, Box<dyn Any + Send>>;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Self::Output> {
+        match panic::catch_unwind(AssertUnwindSafe(|| self.0.as_mut().poll(cx))) {
+            Ok(Poll::Pending) => Poll::Pending,
+            Ok(Poll::Ready(value)) => Poll::Ready(Ok(value)),
+            Err(panic) => Poll::Ready(Err(panic))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BoxFuture, CatchUnwind, Executor, ThreadExecutor};
+    use super::super::log::{LogEvent, LogSink, Logger};
+    use super::super::super::super::http::request::log::LogLevel;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::{Arc, Mutex, mpsc};
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Pending until another thread wakes it a little later
+    struct WokenLater {
+        woken: bool
+    }
+
+    impl Future for WokenLater {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+            match self.woken {
+                true => Poll::Ready(()),
+                false => {
+                    self.woken = true;
+                    let waker = cx.waker().clone();
+                    thread::spawn(move || {
+                        thread::sleep(Duration::from_millis(10));
+                        waker.wake();
+                    });
+                    Poll::Pending
+                }
+            }
+        }
+    }
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    fn poll_once<T>(future: &mut CatchUnwind<T>) -> Poll<<CatchUnwind<T> as Future>::Output> {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        Pin::new(future).poll(&mut Context::from_waker(&waker))
+    }
+
+    #[test]
+    fn futures_run_to_completion_after_waking() {
+        let executor = ThreadExecutor::new(2);
+        let (sender, receiver) = mpsc::channel();
+        for i in 0..4 {
+            let sender = sender.clone();
+            executor.spawn(Box::pin(async move {
+                WokenLater { woken: false }.await;
+                sender.send(i).unwrap_or_default();
+            }));
+        }
+
+        let mut done: Vec<i32> = (0..4).map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap()).collect();
+        done.sort();
+        assert_eq!(done, vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn panicking_futures_are_logged_and_spare_the_thread() {
+        let logged = Arc::new(Mutex::new(vec![]));
+        let sink_logged = Arc::clone(&logged);
+        let logger = Logger::new(LogLevel::Info, LogSink::callback(move |event: &LogEvent| sink_logged.lock().unwrap().push(event.get_data())));
+        let executor = ThreadExecutor::new(1).logger(logger);
+
+        let (sender, receiver) = mpsc::channel();
+        executor.spawn(Box::pin(async { panic!("task failed") }));
+        executor.spawn(Box::pin(async move { sender.send(()).unwrap_or_default() }));
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        assert_eq!(*logged.lock().unwrap(), vec![String::from("Async task panicked: task failed")]);
+    }
+
+    #[test]
+    fn catch_unwind_passes_values_and_panics_on() {
+        let ready: BoxFuture<u8> = Box::pin(async { 7 });
+        assert!(matches!(poll_once(&mut CatchUnwind(ready)), Poll::Ready(Ok(7))));
+
+        let panicking: BoxFuture<u8> = Box::pin(async { panic!("polled") });
+        match poll_once(&mut CatchUnwind(panicking)) {
+            Poll::Ready(Err(panic)) => assert_eq!(panic.downcast_ref::<&str>(), Some(&"polled")),
+            _ => panic!("expected the panic")
+        }
+
+        let mut pending = CatchUnwind(Box::pin(WokenLater { woken: false }) as BoxFuture<()>);
+        assert!(poll_once(&mut pending).is_pending());
+        assert!(matches!(poll_once(&mut pending), Poll::Ready(Ok(()))));
+    }
+}
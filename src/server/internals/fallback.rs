@@ -0,0 +1,152 @@
+use std::sync::Arc;
+use std::vec::Vec;
+
+use super::super::super::http::{
+    HTTPError,
+    IntoResponse,
+    request::Request,
+    response::Response
+};
+
+/// Answers a request the router couldn't, receiving the error the server would send otherwise
+pub type FallbackHandler = Box<dyn Fn(Option<&Request>, HTTPError) -> Result<Response, HTTPError> + Send + Sync + 'static>;
+
+pub(crate) fn to_fallback_handler<F, R, E>(handler: F) -> Arc<FallbackHandler>
+    where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+          R: IntoResponse,
+          E: Into<HTTPError>
+{
+    Arc::new(Box::new(move |req: Option<&Request>, error: HTTPError| handler(req, error).map(IntoResponse::into_response).map_err(Into::into)))
+}
+
+/// User supplied handlers replacing the generic error responses
+#[derive(Clone, Default)]
+pub struct Fallbacks {
+    not_found: Option<Arc<FallbackHandler>>,
+    not_found_prefixes: Vec<(String, Arc<FallbackHandler>)>,
+    method_not_allowed: Option<Arc<FallbackHandler>>,
+    bad_request: Option<Arc<FallbackHandler>>,
+    handler_error: Option<Arc<FallbackHandler>>
+}
+
+impl Fallbacks {
+    /**
+     * Getters
+    */
+
+    /// The not found handler registered for the longest prefix of the path, the global one otherwise
+    pub fn get_not_found(&self, path: &str) -> Option<Arc<FallbackHandler>> {
+        self.not_found_prefixes.iter()
+            .filter(|(prefix, _)| is_under_prefix(path, prefix))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, handler)| Arc::clone(handler))
+            .or_else(|| self.not_found.clone())
+    }
+
+    pub fn get_method_not_allowed(&self) -> Option<Arc<FallbackHandler>> {
+        self.method_not_allowed.clone()
+    }
+
+    pub fn get_bad_request(&self) -> Option<Arc<FallbackHandler>> {
+        self.bad_request.clone()
+    }
+
+    pub fn get_handler_error(&self) -> Option<Arc<FallbackHandler>> {
+        self.handler_error.clone()
+    }
+
+    /**
+     * Setters
+    */
+
+    pub fn set_not_found(&mut self, handler: Arc<FallbackHandler>) {
+        self.not_found = Some(handler);
+    }
+
+    /// Accepts either a plain prefix like `/api` or a catch-all path like `/api/{p*}`
+    pub fn add_not_found_prefix(&mut self, prefix: String, handler: Arc<FallbackHandler>) {
+        let prefix = match prefix.rfind("/{") {
+            Some(i) if prefix.ends_with("*}") => String::from(&prefix[..i]),
+            _ => prefix
+        };
+
+        self.not_found_prefixes.retain(|(existing, _)| *existing != prefix);
+        self.not_found_prefixes.push((prefix, handler));
+    }
+
+    pub fn set_method_not_allowed(&mut self, handler: Arc<FallbackHandler>) {
+        self.method_not_allowed = Some(handler);
+    }
+
+    pub fn set_bad_request(&mut self, handler: Arc<FallbackHandler>) {
+        self.bad_request = Some(handler);
+    }
+
+    pub fn set_handler_error(&mut self, handler: Arc<FallbackHandler>) {
+        self.handler_error = Some(handler);
+    }
+}
+
+/// Prefixes only match on whole path segments, `/api` covers `/api` and `/api/users` but not `/apis`
+fn is_under_prefix(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+
+    path.starts_with(prefix) && matches!(path[prefix.len()..].chars().next(), None | Some('/') | Some('?'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_under_prefix, to_fallback_handler, FallbackHandler, Fallbacks};
+    use super::super::super::super::http::{HTTPError, request::Request};
+    use std::sync::Arc;
+
+    /// A handler answering with its name
+    fn named(name: &'static str) -> Arc<FallbackHandler> {
+        to_fallback_handler(move |_req: Option<&Request>, _error: HTTPError| -> Result<&'static str, HTTPError> { Ok(name) })
+    }
+
+    /// The name of the handler answering a not found `path`, if any
+    fn not_found_by(fallbacks: &Fallbacks, path: &str) -> Option<String> {
+        fallbacks.get_not_found(path)
+            .map(|handler| String::from_utf8(handler(None, HTTPError::not_found("Not Found")).unwrap_or_default().get_body()).unwrap())
+    }
+
+    #[test]
+    fn prefixes_match_whole_segments() {
+        assert!(is_under_prefix("/api", "/api"));
+        assert!(is_under_prefix("/api/users", "/api"));
+        assert!(is_under_prefix("/api?page=2", "/api"));
+        assert!(is_under_prefix("/api/users", "/api/"));
+        assert!(!is_under_prefix("/apis", "/api"));
+        assert!(!is_under_prefix("/ap", "/api"));
+        assert!(!is_under_prefix("/v1/api", "/api"));
+        // The root covers everything
+        assert!(is_under_prefix("/anything", "/"));
+    }
+
+    #[test]
+    fn the_longest_prefix_wins() {
+        let mut fallbacks = Fallbacks::default();
+        fallbacks.set_not_found(named("global"));
+        fallbacks.add_not_found_prefix(String::from("/api"), named("api"));
+        fallbacks.add_not_found_prefix(String::from("/api/admin"), named("admin"));
+
+        assert_eq!(not_found_by(&fallbacks, "/api/users"), Some(String::from("api")));
+        assert_eq!(not_found_by(&fallbacks, "/api/admin/users"), Some(String::from("admin")));
+        assert_eq!(not_found_by(&fallbacks, "/api/administrators"), Some(String::from("api")));
+        assert_eq!(not_found_by(&fallbacks, "/apis"), Some(String::from("global")));
+        assert_eq!(not_found_by(&Fallbacks::default(), "/apis"), None);
+    }
+
+    #[test]
+    fn catch_all_paths_act_as_prefixes() {
+        let mut fallbacks = Fallbacks::default();
+        fallbacks.add_not_found_prefix(String::from("/static/{path*}"), named("static"));
+        assert_eq!(not_found_by(&fallbacks, "/static/css/site.css"), Some(String::from("static")));
+        assert_eq!(not_found_by(&fallbacks, "/statics"), None);
+
+        // Adding the same prefix again replaces its handler
+        fallbacks.add_not_found_prefix(String::from("/static"), named("replaced"));
+        assert_eq!(not_found_by(&fallbacks, "/static/css/site.css"), Some(String::from("replaced")));
+    }
+}
@@ -0,0 +1,153 @@
+use super::super::super::super::http::{Header, HTTPMethod, HTTPVersion, Request, Response};
+use super::super::super::super::http::h2::{ErrorCode, H2Error};
+use std::time::Instant;
+
+// Meaningless on HTTP/2, a request carrying one is malformed and a response drops them
+const CONNECTION_HEADERS: [&str; 5] = ["connection", "keep-alive", "proxy-connection", "transfer-encoding", "upgrade"];
+
+/// A request whose header block arrived, gathering its body until the client ends the stream
+pub struct Incoming {
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    received: Instant
+}
+
+impl Incoming {
+    /// Check the header block against RFC 7540 section 8.1.2, malformed requests reset their stream
+    pub fn new(stream_id: u32, headers: Vec<(String, String)>) -> Result<Self, H2Error> {
+        let malformed = |message: &str| Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, message));
+
+        let mut regular = false;
+        let mut seen = vec![];
+        for (name, value) in headers.iter() {
+            match name.strip_prefix(':') {
+                Some(pseudo) => {
+                    if regular {
+                        return malformed("Pseudo-header after a regular header");
+                    }
+                    if !matches!(pseudo, "method" | "scheme" | "authority" | "path") || seen.contains(&pseudo) {
+                        return malformed("Unknown or repeated pseudo-header");
+                    }
+                    seen.push(pseudo);
+                },
+                None => {
+                    regular = true;
+                    if name.bytes().any(|b| b.is_ascii_uppercase()) {
+                        return malformed("Uppercase header name");
+                    }
+                    if CONNECTION_HEADERS.contains(&name.as_str()) || (name == "te" && value != "trailers") {
+                        return malformed("Connection-specific header");
+                    }
+                }
+            }
+        }
+
+        let connect = headers.iter().any(|(name, value)| name == ":method" && value == "CONNECT");
+        let complete = match connect {
+            true => seen.contains(&"authority") && !seen.contains(&"scheme") && !seen.contains(&"path"),
+            false => seen.contains(&"method") && seen.contains(&"scheme") && headers.iter().any(|(name, value)| name == ":path" && !value.is_empty())
+        };
+        if !complete {
+            return malformed("Missing pseudo-headers");
+        }
+
+        Ok(Self {
+            headers,
+            body: vec![],
+            received: Instant::now()
+        })
+    }
+
+    /// When the request's header block arrived
+    pub fn get_received(&self) -> Instant {
+        self.received
+    }
+
+    /// The `:authority` the request was sent to, or its `host` header
+    pub fn get_authority(&self) -> String {
+        self.headers.iter()
+            .find(|(name, _)| name == ":authority")
+            .or_else(|| self.headers.iter().find(|(name, _)| name == "host"))
+            .map(|(_, value)| value.clone())
+            .unwrap_or_default()
+    }
+
+    pub fn extend_body(&mut self, data: &[u8]) {
+        self.body.extend_from_slice(data);
+    }
+
+    /// The finished request, `None` for methods this server doesn't know or bodies that aren't UTF-8, which are answered with a 400
+    pub fn into_request(self, stream_id: u32) -> Result<Option<Request>, H2Error> {
+        let content_length = self.headers.iter().find(|(name, _)| name == "content-length").map(|(_, value)| value.trim().parse::<usize>());
+        match content_length {
+            Some(Ok(length)) if length == self.body.len() => (),
+            None => (),
+            Some(_) => return Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, "Body doesn't match its content-length"))
+        }
+
+        let pseudo = |key: &str| self.headers.iter().find(|(name, _)| name == key).map(|(_, value)| value.clone());
+        let method = HTTPMethod::from_str(&pseudo(":method").unwrap_or_default());
+        let path = pseudo(":path").or_else(|| pseudo(":authority")).unwrap_or_default();
+
+        let mut header = Header::new();
+        for (name, value) in self.headers.iter().filter(|(name, _)| !name.starts_with(':')) {
+            let name = title_case(name);
+            // Header fields split across several lines go back together, cookies with their own separator
+            let value = match (header.get_header(name.clone()), name.as_str()) {
+                (None, _) => value.clone(),
+                (Some(previous), "Cookie") => format!("{}; {}", previous, value),
+                (Some(previous), _) => format!("{}, {}", previous, value)
+            };
+            header.insert(name, value);
+        }
+        // :authority stands in for the Host header
+        if let (Some(authority), None) = (pseudo(":authority"), header.find("Host")) {
+            header.insert(String::from("Host"), authority);
+        }
+
+        let payload = match String::from_utf8(self.body) {
+            Err(_) => return Ok(None),
+            Ok(payload) => payload
+        };
+        match method {
+            HTTPMethod::ERR => Ok(None),
+            method => Ok(Some(Request::new(method, path, HTTPVersion::HTTP20, header, payload)))
+        }
+    }
+}
+
+/// The response's status and headers as an HTTP/2 header list, and the body to send after them
+pub fn response_head(response: &Response) -> (Vec<(String, String)>, Vec<u8>) {
+    let mut headers = vec![(String::from(":status"), response.get_code().to_string())];
+    let response_headers = response.get_headers();
+    for (name, value) in response_headers.iter() {
+        let name = name.to_ascii_lowercase();
+        if !CONNECTION_HEADERS.contains(&name.as_str()) {
+            headers.push((name, value.clone()));
+        }
+    }
+
+    let body = match response.has_body() {
+        true => response.get_body(),
+        false => vec![]
+    };
+    if response.has_body() && response_headers.find("Content-Length").is_none() {
+        headers.push((String::from("content-length"), body.len().to_string()));
+    }
+
+    (headers, body)
+}
+
+/// `content-type` as `Content-Type`, the way handlers look headers up on HTTP/1.1
+fn title_case(name: &str) -> String {
+    name.split('-')
+        .map(|word| {
+            let mut chars = word.chars();
+            match chars.next() {
+                None => String::new(),
+                Some(first) => first.to_ascii_uppercase().to_string() + chars.as_str()
+            }
+        })
+        .collect::<Vec<String>>()
+        .join("-")
+}
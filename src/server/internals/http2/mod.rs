@@ -0,0 +1,900 @@
+pub mod message;
+pub mod priority;
+pub mod stream;
+
+use self::message::Incoming;
+use self::stream::{grow_window, Shared, State, Stream, StreamSink};
+use super::connection::Connection;
+use super::context::ServerContext;
+use super::options::ServerOptions;
+use super::proxy_protocol::ProxyHeader;
+use super::super::super::http::{HTTPStatusCodes, HTTPVersion, Request, Response};
+use super::super::super::http::h2::{ErrorCode, H2Error, PREFACE};
+use super::super::super::http::h2::frame::{Frame, Priority, FRAME_HEADER_SIZE};
+use super::super::super::http::h2::hpack::Decoder;
+use super::super::super::http::h2::settings::{self, Settings, DEFAULT_WINDOW_SIZE};
+use std::collections::VecDeque;
+use std::io::{self, ErrorKind, Read};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// How often an idle connection checks whether the server is stopping
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// How soon requests the workers had no room for are offered again
+const RETRY_INTERVAL: Duration = Duration::from_millis(10);
+// Header blocks larger than this end the connection, they can't be skipped without decoding them
+const MAX_HEADER_BLOCK_SIZE: usize = 64 * 1024;
+// PRIORITY frames may describe streams that never open, only this many of those are remembered
+const MAX_IDLE_PRIORITIES: usize = 256;
+
+/// A request the workers had no room for yet, handed back with its stream
+pub type Rejected = Box<(Option<Request>, StreamSink)>;
+
+/// The server's side of an HTTP/2 connection, reading frames and handing each finished request to the router
+pub struct Http2Connection {
+    connection: Connection,
+    shared: Arc<Shared>,
+    context: Arc<ServerContext>,
+    proxy: Option<ProxyHeader>,
+    settings: Settings,
+    decoder: Decoder,
+    // Highest stream the client opened, streams below it that aren't open are closed
+    last_stream_id: u32,
+    // HEADERS waiting for the CONTINUATION frames completing their block
+    continuation: Option<(u32, Vec<u8>, bool, Option<Priority>)>,
+    receive_window: i64,
+    unacknowledged: u32,
+    going_away: bool,
+    last_active: Instant,
+    // Requests the client finished sending, dispatched outside of the state lock and in order once the workers have room
+    ready: VecDeque<(Option<Request>, StreamSink)>
+}
+
+impl Http2Connection {
+    pub fn new(connection: Connection, context: &Arc<ServerContext>, proxy: Option<ProxyHeader>) -> io::Result<Self> {
+        // Frames are small and flow control waits on the client, Nagle's algorithm would hold the last of each window back
+        connection.set_nodelay(true)?;
+        let shared = Arc::new(Shared::new(connection.try_clone()?, context.get_read_timeouts().get_socket()));
+        let settings = context.get_http2_settings();
+        let mut decoder = Decoder::new();
+        decoder.set_max_header_list_size(settings.get_max_header_list_size().map(|size| size as usize));
+        Ok(Self {
+            connection,
+            shared,
+            context: Arc::clone(context),
+            proxy,
+            settings,
+            decoder,
+            last_stream_id: 0,
+            continuation: None,
+            receive_window: DEFAULT_WINDOW_SIZE as i64,
+            unacknowledged: 0,
+            going_away: false,
+            last_active: Instant::now(),
+            ready: VecDeque::new()
+        })
+    }
+
+    /// Serve streams until the client or the server ends the connection
+    /// `received` holds what was already read off the connection, starting with the preface
+    pub fn serve<F>(mut self, received: &[u8], dispatch: F)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected> + Send + Sync + 'static
+    {
+        let dispatch = Arc::new(dispatch);
+        self.enable_push(&dispatch);
+        match self.open() {
+            true => self.run(received.to_vec(), &*dispatch),
+            false => self.shared.close()
+        }
+    }
+
+    /// Switch an HTTP/1.1 connection whose request asked for h2c, RFC 7540 section 3.2
+    /// The request is answered on stream 1, `settings` are the client's from its `HTTP2-Settings` header
+    pub fn serve_upgrade<F>(mut self, request: Request, settings: &[(u16, u32)], received: &[u8], dispatch: F)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected> + Send + Sync + 'static
+    {
+        let dispatch = Arc::new(dispatch);
+        self.enable_push(&dispatch);
+
+        let mut switching = Response::from_code(HTTPStatusCodes::SwitchingProtocols101)
+            .header("Connection", "Upgrade")
+            .header("Upgrade", "h2c");
+        switching.set_version(HTTPVersion::HTTP11);
+        if switching.write_to(&mut self.connection).is_err() {
+            return self.shared.close();
+        }
+
+        // The 101 acknowledges the client's settings, no SETTINGS frame needed
+        let mut state = self.shared.lock();
+        state.peer.apply(settings).unwrap_or_default();
+        let table_size = state.peer.get_header_table_size();
+        drop(state);
+        self.shared.set_encoder_table_size(table_size);
+
+        if !self.open() {
+            return self.shared.close();
+        }
+
+        // Stream 1 is half closed, the client sent all of its request over HTTP/1.1
+        let mut state = self.shared.lock();
+        state.priorities.insert(1, None);
+        let send_window = state.peer.get_initial_window_size() as i64;
+        state.streams.insert(1, Stream {
+            send_window,
+            receive_window: self.settings.get_initial_window_size() as i64,
+            unacknowledged: 0,
+            request: None,
+            authority: request.get_headers().find("Host").cloned().unwrap_or_default()
+        });
+        drop(state);
+        self.last_stream_id = 1;
+        self.ready.push_back((Some(request), StreamSink::new(Arc::clone(&self.shared), 1)));
+        self.dispatch_ready(&*dispatch);
+
+        self.run(received.to_vec(), &*dispatch);
+    }
+
+    /// Route the requests workers push like the ones the client sends
+    fn enable_push<F>(&self, dispatch: &Arc<F>)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected> + Send + Sync + 'static
+    {
+        let connection = match self.connection.try_clone() {
+            Err(_) => return,
+            Ok(connection) => connection
+        };
+        let (dispatch, context, proxy) = (Arc::clone(dispatch), Arc::clone(&self.context), self.proxy);
+        self.shared.enable_push(Box::new(move |incoming, stream| {
+            let received = incoming.get_received();
+            let request = incoming.into_request(stream.get_stream_id()).unwrap_or_default().map(|mut request| {
+                context.track_request(&mut request, received, &connection, proxy);
+                request
+            });
+            // Pushes are only an optimisation, the client asks for the resource itself if it needs it
+            if let Err(rejected) = dispatch(request, stream) {
+                let (request, stream) = *rejected;
+                context.queue_full(request.as_ref(), stream.into());
+            }
+        }));
+    }
+
+    /// Send the server's preface, its SETTINGS and the connection's window if larger than the default
+    fn open(&mut self) -> bool {
+        self.context.get_logger().debug(&["request", "http2"], "HTTP/2 connection started");
+
+        let mut opening = vec![self.settings.to_frame()];
+        // The connection's window can only grow through WINDOW_UPDATE
+        if self.settings.get_initial_window_size() > DEFAULT_WINDOW_SIZE {
+            let increment = self.settings.get_initial_window_size() - DEFAULT_WINDOW_SIZE;
+            self.receive_window += increment as i64;
+            opening.push(Frame::WindowUpdate { stream_id: 0, increment });
+        }
+        opening.iter().all(|frame| self.shared.write_frame(frame).is_ok())
+    }
+
+    /// Read the client's preface, then frames until either end is done with the connection
+    fn run<F>(mut self, mut buffer: Vec<u8>, dispatch: &F)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected>
+    {
+        let logger = self.context.get_logger().clone();
+        let mut chunk = [0; 16 * 1024];
+
+        match self.read_preface(&mut buffer, &mut chunk) {
+            true => buffer.drain(..PREFACE.len()),
+            false => {
+                logger.debug(&["request", "http2", "error"], "Connection without a valid HTTP/2 preface");
+                return self.close();
+            }
+        };
+
+        loop {
+            // Handle every whole frame received so far
+            loop {
+                let result = match Frame::parse(&buffer, self.settings.get_max_frame_size()) {
+                    Ok(None) => break,
+                    Ok(Some((frame, size))) => {
+                        buffer.drain(..size);
+                        self.handle_frame(frame)
+                    },
+                    Err(e) => {
+                        // Skip the frame a stream error was about, connection errors end everything anyway
+                        let size = FRAME_HEADER_SIZE + ((buffer[0] as usize) << 16 | (buffer[1] as usize) << 8 | buffer[2] as usize);
+                        buffer.drain(..size.min(buffer.len()));
+                        Err(e)
+                    }
+                };
+
+                match result {
+                    Ok(()) => (),
+                    Err(H2Error::Stream(stream_id, code, message)) => {
+                        logger.debug(&["request", "http2", "error"], format!("Resetting stream {}: {:?}: {}", stream_id, code, message));
+                        self.reset(stream_id, code);
+                    },
+                    Err(H2Error::Connection(code, message)) => {
+                        logger.warn(&["request", "http2", "error"], format!("HTTP/2 connection error: {:?}: {}", code, message));
+                        self.go_away(code, &message);
+                        return self.close();
+                    }
+                }
+
+                self.dispatch_ready(dispatch);
+            }
+            self.dispatch_ready(dispatch);
+
+            let open_streams = self.shared.lock().streams.len();
+            if open_streams > 0 {
+                self.last_active = Instant::now();
+            }
+            if self.going_away && open_streams == 0 {
+                break;
+            }
+
+            // Finish the streams in flight but take no new ones
+            let idle = self.context.get_read_timeouts().get_idle().is_some_and(|timeout| self.last_active.elapsed() > timeout);
+            if !self.going_away && (self.context.is_stopping() || idle) {
+                self.go_away(ErrorCode::NoError, "");
+                continue;
+            }
+
+            let timeout = match self.ready.is_empty() {
+                true => POLL_INTERVAL,
+                false => RETRY_INTERVAL
+            };
+            self.connection.set_read_timeout(Some(timeout)).unwrap_or_default();
+            match self.connection.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => (),
+                Err(ref e) if e.kind() == ErrorKind::UnexpectedEof || e.kind() == ErrorKind::ConnectionReset => break,
+                Err(e) => {
+                    logger.error(&["request", "http2", "error"], format!("Failed to read HTTP/2 connection: {}", e));
+                    break;
+                }
+            }
+        }
+
+        logger.debug(&["request", "http2"], "HTTP/2 connection closed");
+        self.close();
+    }
+
+    /// Close the connection, requests still waiting for the workers are counted as turned away
+    fn close(&mut self) {
+        self.shared.close();
+        for (request, sink) in self.ready.drain(..) {
+            self.context.queue_full(request.as_ref(), sink.into());
+        }
+    }
+
+    /// Hand finished requests to the workers in the order they arrived, those they have no room for wait for the next try
+    fn dispatch_ready<F>(&mut self, dispatch: &F)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected>
+    {
+        while let Some((request, sink)) = self.ready.pop_front() {
+            if let Err(rejected) = dispatch(request, sink) {
+                self.ready.push_front(*rejected);
+                break;
+            }
+        }
+    }
+
+    /// Make sure the client's side starts with the preface, with prior knowledge only its first line was read along with a request head
+    fn read_preface(&mut self, buffer: &mut Vec<u8>, chunk: &mut [u8]) -> bool {
+        let deadline = Instant::now() + self.context.get_read_timeouts().get_header().unwrap_or(POLL_INTERVAL * 20);
+        while buffer.len() < PREFACE.len() {
+            let remaining = match deadline.checked_duration_since(Instant::now()) {
+                None => return false,
+                Some(remaining) => remaining
+            };
+            self.connection.set_read_timeout(Some(remaining)).unwrap_or_default();
+            match self.connection.read(chunk) {
+                Ok(0) | Err(_) => return false,
+                Ok(size) => buffer.extend_from_slice(&chunk[..size])
+            }
+        }
+
+        buffer.starts_with(PREFACE)
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<(), H2Error> {
+        // Nothing may come between HEADERS and the CONTINUATION frames of their block
+        if let Some((stream_id, ..)) = self.continuation {
+            match frame {
+                Frame::Continuation { stream_id: continued, .. } if continued == stream_id => (),
+                _ => return Err(H2Error::connection(ErrorCode::ProtocolError, "Expected a CONTINUATION frame"))
+            }
+        }
+
+        match frame {
+            Frame::Headers { stream_id, block, end_stream, end_headers, priority } => match end_headers {
+                true => self.handle_headers(stream_id, &block, end_stream, priority),
+                false => {
+                    self.continuation = Some((stream_id, block, end_stream, priority));
+                    Ok(())
+                }
+            },
+            Frame::Continuation { stream_id, block, end_headers } => {
+                let (id, mut whole, end_stream, priority) = match self.continuation.take() {
+                    None => return Err(H2Error::connection(ErrorCode::ProtocolError, "CONTINUATION frame without HEADERS")),
+                    Some(continuation) => continuation
+                };
+                whole.extend_from_slice(&block);
+                match (whole.len() > MAX_HEADER_BLOCK_SIZE, end_headers) {
+                    (true, _) => Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "Header block too large")),
+                    (false, true) => self.handle_headers(stream_id, &whole, end_stream, priority),
+                    (false, false) => {
+                        self.continuation = Some((id, whole, end_stream, priority));
+                        Ok(())
+                    }
+                }
+            },
+            Frame::Data { stream_id, data, end_stream, padding } => self.handle_data(stream_id, &data, end_stream, data.len() + padding),
+            Frame::Priority { stream_id, priority } => {
+                if priority.get_dependency() == stream_id {
+                    return Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, "Stream depends on itself"));
+                }
+                let mut state = self.shared.lock();
+                let idle = stream_id > self.last_stream_id;
+                // Closed streams are gone from the tree, idle ones only fit so many
+                if state.priorities.contains(stream_id) || (idle && state.priorities.get_size() < state.streams.len() + MAX_IDLE_PRIORITIES) {
+                    state.priorities.reprioritize(stream_id, priority);
+                }
+                Ok(())
+            },
+            Frame::RstStream { stream_id, error } => {
+                let mut state = self.shared.lock();
+                if self.is_idle(&state, stream_id) {
+                    return Err(H2Error::connection(ErrorCode::ProtocolError, "RST_STREAM on an idle stream"));
+                }
+                let open = state.remove(stream_id);
+                drop(state);
+                if open {
+                    self.context.get_logger().debug(&["request", "http2"], format!("Client reset stream {}: {:?}", stream_id, error));
+                }
+                self.shared.notify();
+                Ok(())
+            },
+            Frame::Settings { ack: true, .. } => Ok(()),
+            Frame::Settings { ack: false, settings } => {
+                let mut state = self.shared.lock();
+                let previous = state.peer.get_initial_window_size();
+                state.peer.apply(&settings)?;
+                state.resize_windows(previous)?;
+                let table_size = state.peer.get_header_table_size();
+                drop(state);
+
+                self.shared.notify();
+                self.shared.set_encoder_table_size(table_size);
+                self.write(&Settings::ack())
+            },
+            Frame::Ping { ack: false, data } => self.write(&Frame::Ping { ack: true, data }),
+            Frame::Ping { ack: true, .. } => Ok(()),
+            Frame::GoAway { error, debug, .. } => {
+                if error != ErrorCode::NoError {
+                    self.context.get_logger().debug(&["request", "http2"], format!("Client going away: {:?}: {}", error, String::from_utf8_lossy(&debug)));
+                }
+                self.going_away = true;
+                Ok(())
+            },
+            Frame::WindowUpdate { stream_id: 0, increment } => {
+                let mut state = self.shared.lock();
+                if !grow_window(&mut state.send_window, increment) {
+                    return Err(H2Error::connection(ErrorCode::FlowControlError, "Connection window above 2^31-1"));
+                }
+                drop(state);
+                self.shared.notify();
+                Ok(())
+            },
+            Frame::WindowUpdate { stream_id, increment } => {
+                let mut state = self.shared.lock();
+                if self.is_idle(&state, stream_id) {
+                    return Err(H2Error::connection(ErrorCode::ProtocolError, "WINDOW_UPDATE on an idle stream"));
+                }
+                // Streams that already closed may still be updated for a while
+                if let Some(stream) = state.streams.get_mut(&stream_id) {
+                    if !grow_window(&mut stream.send_window, increment) {
+                        return Err(H2Error::stream(stream_id, ErrorCode::FlowControlError, "Stream window above 2^31-1"));
+                    }
+                }
+                drop(state);
+                self.shared.notify();
+                Ok(())
+            },
+            Frame::PushPromise { .. } => Err(H2Error::connection(ErrorCode::ProtocolError, "Clients can't push")),
+            Frame::Unknown { .. } => Ok(())
+        }
+    }
+
+    /// A whole header block, opening a stream or ending one with trailers
+    fn handle_headers(&mut self, stream_id: u32, block: &[u8], end_stream: bool, priority: Option<Priority>) -> Result<(), H2Error> {
+        if block.len() > MAX_HEADER_BLOCK_SIZE {
+            return Err(H2Error::connection(ErrorCode::EnhanceYourCalm, "Header block too large"));
+        }
+        // Always decode, the compression state is shared by every stream
+        let headers = self.decoder.decode(block)?;
+
+        let mut state = self.shared.lock();
+        if let Some(stream) = state.streams.get_mut(&stream_id) {
+            return match (stream.request.is_some(), end_stream) {
+                // Trailers, which routes have no use for
+                (true, true) => {
+                    drop(state);
+                    self.finish_request(stream_id)
+                },
+                (true, false) => Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, "Trailers without END_STREAM")),
+                (false, _) => Err(H2Error::stream(stream_id, ErrorCode::StreamClosed, "HEADERS after END_STREAM"))
+            };
+        }
+
+        if stream_id.is_multiple_of(2) {
+            return Err(H2Error::connection(ErrorCode::ProtocolError, "Client opened an even stream"));
+        }
+        if stream_id <= self.last_stream_id {
+            return Err(H2Error::connection(ErrorCode::StreamClosed, "HEADERS on a closed stream"));
+        }
+        self.last_stream_id = stream_id;
+
+        // Streams opened after our GOAWAY are ignored, the client retries them elsewhere
+        if self.going_away {
+            return Ok(());
+        }
+        if let Some(max) = self.settings.get_max_concurrent_streams() {
+            if state.streams.len() >= max as usize {
+                return Err(H2Error::stream(stream_id, ErrorCode::RefusedStream, "Too many concurrent streams"));
+            }
+        }
+        if priority.is_some_and(|priority| priority.get_dependency() == stream_id) {
+            return Err(H2Error::stream(stream_id, ErrorCode::ProtocolError, "Stream depends on itself"));
+        }
+
+        let request = Incoming::new(stream_id, headers)?;
+        state.priorities.insert(stream_id, priority);
+        let send_window = state.peer.get_initial_window_size() as i64;
+        state.streams.insert(stream_id, Stream {
+            send_window,
+            receive_window: self.settings.get_initial_window_size() as i64,
+            unacknowledged: 0,
+            authority: request.get_authority(),
+            request: Some(request)
+        });
+        drop(state);
+
+        match end_stream {
+            true => self.finish_request(stream_id),
+            false => Ok(())
+        }
+    }
+
+    fn handle_data(&mut self, stream_id: u32, data: &[u8], end_stream: bool, size: usize) -> Result<(), H2Error> {
+        // The connection's window counts every DATA frame, even those of streams that already closed
+        self.receive_window -= size as i64;
+        if self.receive_window < 0 {
+            return Err(H2Error::connection(ErrorCode::FlowControlError, "DATA beyond the connection's window"));
+        }
+        self.unacknowledged += size as u32;
+        if self.unacknowledged >= self.settings.get_initial_window_size().max(DEFAULT_WINDOW_SIZE) / 2 {
+            self.receive_window += self.unacknowledged as i64;
+            let increment = std::mem::take(&mut self.unacknowledged);
+            self.write(&Frame::WindowUpdate { stream_id: 0, increment })?;
+        }
+
+        let mut state = self.shared.lock();
+        let idle = self.is_idle(&state, stream_id);
+        let stream = match state.streams.get_mut(&stream_id) {
+            Some(stream) if stream.request.is_some() => stream,
+            _ if idle => return Err(H2Error::connection(ErrorCode::ProtocolError, "DATA on an idle stream")),
+            // Streams opened after our GOAWAY are ignored
+            None if self.going_away => return Ok(()),
+            _ => return Err(H2Error::stream(stream_id, ErrorCode::StreamClosed, "DATA after END_STREAM"))
+        };
+
+        stream.receive_window -= size as i64;
+        if stream.receive_window < 0 {
+            return Err(H2Error::stream(stream_id, ErrorCode::FlowControlError, "DATA beyond the stream's window"));
+        }
+        if let Some(ref mut request) = stream.request {
+            request.extend_body(data);
+        }
+        if end_stream {
+            drop(state);
+            return self.finish_request(stream_id);
+        }
+
+        // Give the window back as the body arrives, the request is read whole before it is handled
+        stream.unacknowledged += size as u32;
+        if stream.unacknowledged >= self.settings.get_initial_window_size() / 2 {
+            stream.receive_window += stream.unacknowledged as i64;
+            let increment = std::mem::take(&mut stream.unacknowledged);
+            drop(state);
+            return self.write(&Frame::WindowUpdate { stream_id, increment });
+        }
+        Ok(())
+    }
+
+    /// The client ended its side of the stream, route the request
+    fn finish_request(&mut self, stream_id: u32) -> Result<(), H2Error> {
+        let incoming = match self.shared.lock().streams.get_mut(&stream_id).and_then(|stream| stream.request.take()) {
+            None => return Ok(()),
+            Some(incoming) => incoming
+        };
+
+        let received = incoming.get_received();
+        let request = incoming.into_request(stream_id)?.map(|mut request| {
+            self.context.track_request(&mut request, received, &self.connection, self.proxy);
+            request
+        });
+        self.ready.push_back((request, StreamSink::new(Arc::clone(&self.shared), stream_id)));
+        Ok(())
+    }
+
+    /// Whether neither end opened the stream yet, the client's streams are odd and the ones the server pushes even
+    fn is_idle(&self, state: &State, stream_id: u32) -> bool {
+        match stream_id.is_multiple_of(2) {
+            true => stream_id >= state.next_push_id,
+            false => stream_id > self.last_stream_id
+        }
+    }
+
+    /// End a stream on our side, its worker gives up on sending
+    fn reset(&mut self, stream_id: u32, code: ErrorCode) {
+        self.shared.lock().remove(stream_id);
+        self.shared.notify();
+        self.shared.write_frame(&Frame::RstStream { stream_id, error: code }).unwrap_or_default();
+    }
+
+    /// Tell the client which streams will still be answered, it opens new ones on another connection
+    fn go_away(&mut self, code: ErrorCode, message: &str) {
+        self.going_away = true;
+        let frame = Frame::GoAway { last_stream_id: self.last_stream_id, error: code, debug: message.as_bytes().to_vec() };
+        self.shared.write_frame(&frame).unwrap_or_default();
+    }
+
+    fn write(&self, frame: &Frame) -> Result<(), H2Error> {
+        self.shared.write_frame(frame).map_err(|e| H2Error::connection(ErrorCode::InternalError, &format!("Failed to write frame: {}", e)))
+    }
+}
+
+/// Counts the HTTP/2 connections still being served, a stopping server waits for them to finish their streams
+#[derive(Default)]
+pub struct ConnectionTracker {
+    open: Mutex<usize>,
+    closed: Condvar
+}
+
+impl ConnectionTracker {
+    pub fn open(self: &Arc<Self>) -> OpenConnection {
+        *self.open.lock().unwrap_or_else(PoisonError::into_inner) += 1;
+        OpenConnection {
+            tracker: Arc::clone(self)
+        }
+    }
+
+    pub fn wait_closed(&self) {
+        let open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        drop(self.closed.wait_while(open, |open| *open > 0).unwrap_or_else(PoisonError::into_inner));
+    }
+}
+
+/// Held for as long as an HTTP/2 connection is served
+pub struct OpenConnection {
+    tracker: Arc<ConnectionTracker>
+}
+
+impl Drop for OpenConnection {
+    fn drop(&mut self) {
+        *self.tracker.open.lock().unwrap_or_else(PoisonError::into_inner) -= 1;
+        self.tracker.closed.notify_all();
+    }
+}
+
+/// The client's settings if the request asks to upgrade to h2c, RFC 7540 section 3.2
+/// Requests with a missing or broken `HTTP2-Settings` header stay on HTTP/1.1, HTTP/1.0 ones can't upgrade at all
+pub fn upgrade_settings(request: &Request) -> Option<Vec<(u16, u32)>> {
+    if request.get_version() != HTTPVersion::HTTP11 {
+        return None;
+    }
+    let has_token = |value: &str, token: &str| value.split(',').any(|option| option.trim().eq_ignore_ascii_case(token));
+    let headers = request.get_headers();
+    match (request.get_upgrade(), headers.find("Connection")) {
+        (Some(ref upgrade), Some(connection)) if has_token(upgrade, "h2c") && has_token(connection, "HTTP2-Settings") => {
+            settings::decode_header(headers.find("HTTP2-Settings")?)
+        },
+        _ => None
+    }
+}
+
+/// The settings announced to clients, from the server options
+pub fn settings_from(options: &ServerOptions) -> Settings {
+    let mut settings = Settings::default();
+    settings.set_max_concurrent_streams(Some(options.get_http2_max_concurrent_streams()));
+    settings.set_initial_window_size(options.get_http2_initial_window_size());
+    settings.set_max_header_list_size(Some(MAX_HEADER_BLOCK_SIZE as u32));
+    settings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{upgrade_settings, Http2Connection, Rejected, StreamSink};
+    use super::super::connection::Connection;
+    use super::super::context::ServerContext;
+    use super::super::options::ServerOptions;
+    use super::super::super::super::http::{Request, Response};
+    use super::super::super::super::http::h2::{ErrorCode, PREFACE};
+    use super::super::super::super::http::h2::frame::Frame;
+    use super::super::super::super::http::h2::hpack::{Decoder, Encoder};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    /// Start serving a loopback connection, returning the client's socket past its preface and SETTINGS
+    fn serve<F>(options: &ServerOptions, dispatch: F) -> (TcpStream, Arc<ServerContext>)
+        where F: Fn(Option<Request>, StreamSink) -> Result<(), Rejected> + Send + Sync + 'static
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        let context = Arc::new(ServerContext::new(options));
+        let http2 = Http2Connection::new(Connection::new(server), &context, None).unwrap();
+        thread::spawn(move || http2.serve(&[], dispatch));
+
+        client.write_all(PREFACE).unwrap();
+        client.write_all(&Frame::Settings { ack: false, settings: vec![] }.encode()).unwrap();
+        (client, context)
+    }
+
+    /// Answer every request on a thread of its own with its path
+    fn answer(request: Option<Request>, stream: StreamSink) -> Result<(), Rejected> {
+        thread::spawn(move || stream.send(&Response::new(request.map(|request| request.get_path()).unwrap_or_default().into_bytes())));
+        Ok(())
+    }
+
+    fn get(encoder: &mut Encoder, stream_id: u32, path: &str) -> Vec<u8> {
+        let headers: Vec<(String, String)> = [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)].iter()
+            .map(|&(name, value)| (String::from(name), String::from(value)))
+            .collect();
+        Frame::Headers { stream_id, block: encoder.encode(&headers), end_stream: true, end_headers: true, priority: None }.encode()
+    }
+
+    /// The next frame that isn't about settings or windows, `None` once the server closed the connection
+    fn next_frame(client: &mut TcpStream, buffer: &mut Vec<u8>) -> Option<Frame> {
+        loop {
+            match Frame::parse(buffer, 16384).unwrap() {
+                Some((Frame::Settings { .. }, size)) | Some((Frame::WindowUpdate { .. }, size)) => {
+                    buffer.drain(..size);
+                },
+                Some((frame, size)) => {
+                    buffer.drain(..size);
+                    return Some(frame);
+                },
+                None => {
+                    let mut chunk = [0; 4096];
+                    match client.read(&mut chunk).unwrap() {
+                        0 => return None,
+                        size => buffer.extend_from_slice(&chunk[..size])
+                    }
+                }
+            }
+        }
+    }
+
+    /// The status and body of the response on `stream_id`
+    fn response(client: &mut TcpStream, buffer: &mut Vec<u8>, decoder: &mut Decoder, stream_id: u32) -> (String, String) {
+        let status = match next_frame(client, buffer) {
+            Some(Frame::Headers { stream_id: id, block, .. }) if id == stream_id => decoder.decode(&block).unwrap().remove(0).1,
+            frame => panic!("expected HEADERS on stream {}, got {:?}", stream_id, frame)
+        };
+        match next_frame(client, buffer) {
+            Some(Frame::Data { stream_id: id, data, end_stream: true, .. }) if id == stream_id => (status, String::from_utf8(data).unwrap()),
+            frame => panic!("expected DATA on stream {}, got {:?}", stream_id, frame)
+        }
+    }
+
+    #[test]
+    fn streams_are_answered() {
+        let (mut client, _context) = serve(&ServerOptions::default(), answer);
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+
+        client.write_all(&get(&mut encoder, 1, "/one")).unwrap();
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 1), (String::from("200"), String::from("/one")));
+        client.write_all(&get(&mut encoder, 3, "/two")).unwrap();
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 3), (String::from("200"), String::from("/two")));
+    }
+
+    #[test]
+    fn streams_wait_for_room_on_the_workers() {
+        // The workers take one request in three
+        let attempts = Arc::new(Mutex::new(vec![]));
+        let dispatch_attempts = Arc::clone(&attempts);
+        let (mut client, _context) = serve(&ServerOptions::default(), move |request: Option<Request>, stream: StreamSink| {
+            let mut attempts = dispatch_attempts.lock().unwrap();
+            attempts.push(stream.get_stream_id());
+            match attempts.len() % 3 {
+                0 => answer(request, stream),
+                _ => Err(Box::new((request, stream)))
+            }
+        });
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+
+        let mut requests = get(&mut encoder, 1, "/one");
+        requests.extend(get(&mut encoder, 3, "/two"));
+        client.write_all(&requests).unwrap();
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 1), (String::from("200"), String::from("/one")));
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 3), (String::from("200"), String::from("/two")));
+
+        // Offered again in order, none was turned away
+        assert_eq!(*attempts.lock().unwrap(), vec![1, 1, 1, 3, 3, 3]);
+    }
+
+    #[test]
+    fn connection_errors_go_away() {
+        let (mut client, _context) = serve(&ServerOptions::default(), answer);
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+
+        client.write_all(&get(&mut encoder, 1, "/one")).unwrap();
+        response(&mut client, &mut buffer, &mut decoder, 1);
+        // DATA on stream 0
+        client.write_all(&[0, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+
+        match next_frame(&mut client, &mut buffer) {
+            Some(Frame::GoAway { last_stream_id: 1, error: ErrorCode::ProtocolError, .. }) => (),
+            frame => panic!("expected GOAWAY, got {:?}", frame)
+        }
+        assert_eq!(next_frame(&mut client, &mut buffer), None);
+    }
+
+    #[test]
+    fn idle_and_stopping_connections_go_away() {
+        let mut options = ServerOptions::default();
+        options.set_idle_timeout(Duration::from_millis(100));
+        let (mut client, _context) = serve(&options, answer);
+        let mut buffer = vec![];
+        assert_eq!(next_frame(&mut client, &mut buffer), Some(Frame::GoAway { last_stream_id: 0, error: ErrorCode::NoError, debug: vec![] }));
+        assert_eq!(next_frame(&mut client, &mut buffer), None);
+
+        let (mut client, context) = serve(&ServerOptions::default(), answer);
+        let mut buffer = vec![];
+        client.write_all(&Frame::Ping { ack: false, data: [1; 8] }.encode()).unwrap();
+        assert_eq!(next_frame(&mut client, &mut buffer), Some(Frame::Ping { ack: true, data: [1; 8] }));
+        context.set_stopping();
+        assert!(matches!(next_frame(&mut client, &mut buffer), Some(Frame::GoAway { error: ErrorCode::NoError, .. })));
+        assert_eq!(next_frame(&mut client, &mut buffer), None);
+    }
+
+    #[test]
+    fn open_streams_are_answered_after_going_away() {
+        // Requests are held until the test lets them go
+        let held = Arc::new(Mutex::new(vec![]));
+        let dispatch_held = Arc::clone(&held);
+        let (mut client, context) = serve(&ServerOptions::default(), move |request: Option<Request>, stream: StreamSink| {
+            dispatch_held.lock().unwrap().push((request, stream));
+            Ok(())
+        });
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+
+        client.write_all(&get(&mut encoder, 1, "/slow")).unwrap();
+        while held.lock().unwrap().is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+        context.set_stopping();
+        assert!(matches!(next_frame(&mut client, &mut buffer), Some(Frame::GoAway { last_stream_id: 1, error: ErrorCode::NoError, .. })));
+
+        // Streams opened after the GOAWAY are ignored
+        client.write_all(&get(&mut encoder, 3, "/late")).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        let (request, stream) = held.lock().unwrap().remove(0);
+        answer(request, stream).unwrap_or_default();
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 1), (String::from("200"), String::from("/slow")));
+        assert_eq!(next_frame(&mut client, &mut buffer), None);
+        assert!(held.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn streams_over_the_limit_are_refused() {
+        let mut options = ServerOptions::default();
+        options.set_http2_max_concurrent_streams(1);
+        let held = Arc::new(Mutex::new(vec![]));
+        let dispatch_held = Arc::clone(&held);
+        let (mut client, _context) = serve(&options, move |request: Option<Request>, stream: StreamSink| {
+            dispatch_held.lock().unwrap().push((request, stream));
+            Ok(())
+        });
+        let (mut buffer, mut encoder) = (vec![], Encoder::new());
+
+        let mut requests = get(&mut encoder, 1, "/one");
+        requests.extend(get(&mut encoder, 3, "/two"));
+        client.write_all(&requests).unwrap();
+        assert_eq!(next_frame(&mut client, &mut buffer), Some(Frame::RstStream { stream_id: 3, error: ErrorCode::RefusedStream }));
+        assert_eq!(held.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn only_http11_requests_upgrade() {
+        let upgrade = |version: &str| Request::parse_request(format!(
+            "GET / {}\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: AAMAAABkAAQAAP__\r\n\r\n",
+            version
+        ).as_bytes()).unwrap();
+
+        assert_eq!(upgrade_settings(&upgrade("HTTP/1.1")), Some(vec![(3, 100), (4, 65535)]));
+        assert_eq!(upgrade_settings(&upgrade("HTTP/1.0")), None);
+    }
+
+    #[test]
+    fn upgrades_need_h2c_and_valid_settings() {
+        let upgrade = |connection: &str, upgrade: &str, settings: Option<&str>| {
+            let settings = settings.map(|settings| format!("HTTP2-Settings: {}\r\n", settings)).unwrap_or_default();
+            Request::parse_request(format!(
+                "GET / HTTP/1.1\r\nHost: localhost\r\nConnection: {}\r\nUpgrade: {}\r\n{}\r\n", connection, upgrade, settings
+            ).as_bytes()).and_then(|request| upgrade_settings(&request))
+        };
+
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "h2c", Some("")), Some(vec![]));
+        assert_eq!(upgrade("upgrade,http2-settings", "H2C", Some("AAMAAABk")), Some(vec![(3, 100)]));
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "websocket, h2c", Some("AAMAAABk")), Some(vec![(3, 100)]));
+        // Not asking for h2c, or without the settings
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "websocket", Some("AAMAAABk")), None);
+        assert_eq!(upgrade("Upgrade", "h2c", Some("AAMAAABk")), None);
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "h2c", None), None);
+        assert_eq!(upgrade("keep-alive, HTTP2-Settings", "h2c", Some("AAMAAABk")), None);
+        // Settings that aren't base64url or not whole settings
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "h2c", Some("AAMA+ABk")), None);
+        assert_eq!(upgrade("Upgrade, HTTP2-Settings", "h2c", Some("AAMAAA")), None);
+    }
+
+    /// Answer with the path, pushing `/pushed` along with `/page`
+    fn pushing(request: Option<Request>, stream: StreamSink) -> Result<(), Rejected> {
+        thread::spawn(move || {
+            let path = request.map(|request| request.get_path()).unwrap_or_default();
+            let response = match path.as_str() {
+                "/page" => Response::new(path.into_bytes()).push("/pushed", &[("Accept", "text/css")]),
+                _ => Response::new(path.into_bytes())
+            };
+            stream.send(&response)
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn pushes_are_promised_to_clients_taking_them() {
+        let (mut client, _context) = serve(&ServerOptions::default(), pushing);
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+        client.write_all(&get(&mut encoder, 1, "/page")).unwrap();
+
+        let (mut promised, mut bodies) = (None, vec![]);
+        while bodies.len() < 2 {
+            match next_frame(&mut client, &mut buffer) {
+                Some(Frame::PushPromise { stream_id: 1, promised_stream_id, block, .. }) => {
+                    let headers = decoder.decode(&block).unwrap();
+                    assert!(headers.contains(&(String::from(":path"), String::from("/pushed"))), "{:?}", headers);
+                    assert!(headers.contains(&(String::from("accept"), String::from("text/css"))), "{:?}", headers);
+                    // Promised before the response that refers to it
+                    assert!(bodies.is_empty());
+                    promised = Some(promised_stream_id);
+                },
+                Some(Frame::Headers { block, .. }) => {
+                    decoder.decode(&block).unwrap();
+                },
+                Some(Frame::Data { stream_id, data, end_stream: true, .. }) => bodies.push((stream_id, String::from_utf8(data).unwrap())),
+                frame => panic!("unexpected {:?}", frame)
+            }
+        }
+
+        assert_eq!(promised, Some(2));
+        bodies.sort();
+        assert_eq!(bodies, vec![(1, String::from("/page")), (2, String::from("/pushed"))]);
+    }
+
+    #[test]
+    fn clients_disabling_push_get_none() {
+        let (mut client, _context) = serve(&ServerOptions::default(), pushing);
+        let (mut buffer, mut encoder, mut decoder) = (vec![], Encoder::new(), Decoder::new());
+        client.write_all(&Frame::Settings { ack: false, settings: vec![(2, 0)] }.encode()).unwrap();
+        client.write_all(&get(&mut encoder, 1, "/page")).unwrap();
+
+        // Straight to the response, without a PUSH_PROMISE
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 1), (String::from("200"), String::from("/page")));
+        client.write_all(&get(&mut encoder, 3, "/other")).unwrap();
+        assert_eq!(response(&mut client, &mut buffer, &mut decoder, 3), (String::from("200"), String::from("/other")));
+    }
+}
@@ -0,0 +1,222 @@
+use super::super::super::super::http::h2::frame::Priority;
+use std::collections::HashMap;
+
+/// Weight of streams that never said otherwise
+const DEFAULT_WEIGHT: u16 = 16;
+
+/// The client's stream dependency tree, deciding which of the streams ready to send goes next
+/// Streams wait for their ancestors, siblings share in proportion to their weights
+#[derive(Debug, Default)]
+pub struct PriorityTree {
+    nodes: HashMap<u32, Node>,
+    // Virtual time of the last stream served, where newly ready streams start from
+    clock: u64
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Node {
+    parent: u32,
+    weight: u16,
+    virtual_time: u64
+}
+
+impl PriorityTree {
+    /// Streams in the tree, open ones and idle ones a PRIORITY frame described
+    pub fn get_size(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn contains(&self, stream_id: u32) -> bool {
+        self.nodes.contains_key(&stream_id)
+    }
+
+    /// Add a newly opened stream, keeping what PRIORITY frames said about it while it was idle
+    pub fn insert(&mut self, stream_id: u32, priority: Option<Priority>) {
+        match priority {
+            Some(priority) => self.reprioritize(stream_id, priority),
+            None => {
+                self.nodes.entry(stream_id).or_insert(Node { parent: 0, weight: DEFAULT_WEIGHT, virtual_time: 0 });
+            }
+        }
+    }
+
+    /// Move a stream under its new parent, RFC 7540 section 5.3.3
+    /// Callers reject streams depending on themselves
+    pub fn reprioritize(&mut self, stream_id: u32, priority: Priority) {
+        let dependency = priority.get_dependency();
+        let node = *self.nodes.entry(stream_id).or_insert(Node { parent: 0, weight: DEFAULT_WEIGHT, virtual_time: 0 });
+
+        // Depending on a stream that isn't in the tree gets the default priority
+        if dependency != 0 && !self.nodes.contains_key(&dependency) {
+            self.nodes.insert(stream_id, Node { parent: 0, weight: DEFAULT_WEIGHT, ..node });
+            return;
+        }
+
+        // A stream moving under its own descendant first swaps places with it
+        if self.is_ancestor(stream_id, dependency) {
+            if let Some(descendant) = self.nodes.get_mut(&dependency) {
+                descendant.parent = node.parent;
+            }
+        }
+
+        if priority.is_exclusive() {
+            for (&id, child) in self.nodes.iter_mut() {
+                if child.parent == dependency && id != stream_id {
+                    child.parent = stream_id;
+                }
+            }
+        }
+
+        self.nodes.insert(stream_id, Node { parent: dependency, weight: priority.get_weight(), ..node });
+    }
+
+    /// Forget a closed stream, its children move up to its parent
+    pub fn remove(&mut self, stream_id: u32) {
+        let node = match self.nodes.remove(&stream_id) {
+            None => return,
+            Some(node) => node
+        };
+
+        for child in self.nodes.values_mut() {
+            if child.parent == stream_id {
+                child.parent = node.parent;
+            }
+        }
+    }
+
+    /// Of the streams ready to send, the one whose turn it is
+    /// Streams with a ready ancestor wait for it, the rest go by the virtual time their weights earned them
+    pub fn next(&self, ready: &[u32]) -> Option<u32> {
+        ready.iter()
+            .copied()
+            .filter(|&id| !ready.iter().any(|&other| other != id && self.is_ancestor(other, id)))
+            .min_by_key(|&id| (self.start_time(id), id))
+    }
+
+    /// Account for `bytes` sent on a stream, heavier streams are charged less for them
+    pub fn charge(&mut self, stream_id: u32, bytes: usize) {
+        let start = self.start_time(stream_id);
+        self.clock = start;
+        if let Some(node) = self.nodes.get_mut(&stream_id) {
+            node.virtual_time = start + bytes as u64 * 256 / node.weight as u64;
+        }
+    }
+
+    fn start_time(&self, stream_id: u32) -> u64 {
+        self.nodes.get(&stream_id).map_or(self.clock, |node| node.virtual_time.max(self.clock))
+    }
+
+    /// Whether `ancestor` is above `stream_id` in the tree
+    fn is_ancestor(&self, ancestor: u32, stream_id: u32) -> bool {
+        let mut current = stream_id;
+        // The tree never has cycles, the bound only guards against walking forever if it did
+        for _ in 0..self.nodes.len() {
+            current = match self.nodes.get(&current) {
+                None => return false,
+                Some(node) => node.parent
+            };
+            if current == ancestor {
+                return true;
+            }
+            if current == 0 {
+                return false;
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PriorityTree;
+    use super::super::super::super::super::http::h2::frame::Priority;
+
+    fn tree(streams: &[(u32, Option<Priority>)]) -> PriorityTree {
+        let mut tree = PriorityTree::default();
+        for &(stream_id, priority) in streams {
+            tree.insert(stream_id, priority);
+        }
+        tree
+    }
+
+    #[test]
+    fn streams_wait_for_their_ancestors() {
+        let tree = tree(&[(1, None), (3, Some(Priority::new(1, false, 16))), (5, Some(Priority::new(3, false, 16)))]);
+        assert_eq!(tree.next(&[1, 3, 5]), Some(1));
+        assert_eq!(tree.next(&[3, 5]), Some(3));
+        // An ancestor that isn't ready doesn't hold its descendants back
+        assert_eq!(tree.next(&[5]), Some(5));
+        assert_eq!(tree.next(&[]), None);
+    }
+
+    #[test]
+    fn exclusive_dependencies_adopt_the_siblings() {
+        let mut tree = tree(&[(1, None), (3, None)]);
+        tree.insert(5, Some(Priority::new(0, true, 16)));
+        assert_eq!(tree.next(&[1, 3, 5]), Some(5));
+        assert_eq!(tree.next(&[1, 3]), Some(1));
+    }
+
+    #[test]
+    fn moving_under_a_descendant_swaps_places_with_it() {
+        // 1 <- 3 <- 5, then 1 depends on 5
+        let mut tree = tree(&[(1, None), (3, Some(Priority::new(1, false, 16))), (5, Some(Priority::new(3, false, 16)))]);
+        tree.reprioritize(1, Priority::new(5, false, 16));
+        // 5 took 1's place at the root and 3 stays under 1, RFC 7540 section 5.3.3
+        assert_eq!(tree.next(&[1, 5]), Some(5));
+        assert_eq!(tree.next(&[1, 3]), Some(1));
+        assert_eq!(tree.next(&[3, 5]), Some(5));
+    }
+
+    #[test]
+    fn unknown_dependencies_get_the_default_priority() {
+        let mut tree = tree(&[(1, None)]);
+        tree.insert(3, Some(Priority::new(7, false, 200)));
+        // Not under 1, so both are ready at once
+        assert_eq!(tree.next(&[3, 1]), Some(1));
+        assert!(!tree.contains(7));
+    }
+
+    #[test]
+    fn removed_streams_hand_their_children_to_their_parent() {
+        let mut tree = tree(&[(1, None), (3, Some(Priority::new(1, false, 16))), (5, None)]);
+        tree.remove(1);
+        assert!(!tree.contains(1));
+        assert_eq!(tree.get_size(), 2);
+        tree.charge(5, 1000);
+        // 3 is a root now, ready alongside 5 and not charged yet
+        assert_eq!(tree.next(&[3, 5]), Some(3));
+
+        // Removing a stream twice changes nothing
+        tree.remove(1);
+        assert_eq!(tree.get_size(), 2);
+    }
+
+    #[test]
+    fn siblings_share_by_weight() {
+        let mut tree = tree(&[(1, Some(Priority::new(0, false, 192))), (3, Some(Priority::new(0, false, 64)))]);
+        let mut sent = [0, 0];
+        for _ in 0..400 {
+            let stream_id = tree.next(&[1, 3]).unwrap();
+            tree.charge(stream_id, 1024);
+            sent[(stream_id / 2) as usize] += 1;
+        }
+        assert_eq!(sent, [300, 100]);
+    }
+
+    #[test]
+    fn newly_ready_streams_start_from_the_current_time() {
+        let mut tree = tree(&[(1, None), (3, None)]);
+        for _ in 0..100 {
+            tree.charge(1, 1024);
+        }
+        // 3 doesn't get to catch up on the time it wasn't sending
+        let mut turns = vec![];
+        for _ in 0..4 {
+            let stream_id = tree.next(&[1, 3]).unwrap();
+            tree.charge(stream_id, 1024);
+            turns.push(stream_id);
+        }
+        assert_eq!(turns, vec![3, 1, 3, 1]);
+    }
+}
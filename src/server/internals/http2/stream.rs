@@ -0,0 +1,519 @@
+use super::message::{self, Incoming};
+use super::priority::PriorityTree;
+use super::super::connection::Connection;
+use super::super::super::super::http::Response;
+use super::super::super::super::http::h2::{ErrorCode, H2Error};
+use super::super::super::super::http::h2::frame::{Frame, Priority};
+use super::super::super::super::http::h2::hpack::Encoder;
+use super::super::super::super::http::h2::settings::{Settings, DEFAULT_WINDOW_SIZE, MAX_WINDOW_SIZE};
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind, Write};
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, OnceLock, PoisonError};
+use std::time::{Duration, Instant};
+
+// Stream ids are 31 bits
+const MAX_STREAM_ID: u32 = (1 << 31) - 1;
+// How long a worker waits for the client to open its window without a socket timeout, the idle timeout never fires while streams are open
+const DEFAULT_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Routes a pushed request, from the worker that promised it
+pub type PushDispatch = Box<dyn Fn(Incoming, StreamSink) + Send + Sync>;
+
+/// What the connection's reader and the workers answering its streams share
+pub struct Shared {
+    state: Mutex<State>,
+    // Signalled whenever a window grows, a stream closes or the connection ends
+    changed: Condvar,
+    // Frames are written whole and header blocks in the order they were compressed
+    writer: Mutex<Writer>,
+    peer_addr: Option<SocketAddr>,
+    secure: bool,
+    send_timeout: Option<Duration>,
+    push: OnceLock<PushDispatch>
+}
+
+/// Streams, windows and settings of one connection
+pub struct State {
+    pub peer: Settings,
+    pub send_window: i64,
+    pub streams: HashMap<u32, Stream>,
+    pub priorities: PriorityTree,
+    // Streams with a worker waiting for window to send their body
+    waiting: HashSet<u32>,
+    // Even, the server's streams are the ones it pushes
+    pub next_push_id: u32,
+    pub closed: bool
+}
+
+/// An open or half closed stream
+pub struct Stream {
+    pub send_window: i64,
+    pub receive_window: i64,
+    // Received but not yet given back with a WINDOW_UPDATE
+    pub unacknowledged: u32,
+    /// The request as it arrives, `None` once the client ended its side and it was dispatched
+    pub request: Option<Incoming>,
+    /// Where the request went, pushed requests go to the same place
+    pub authority: String
+}
+
+struct Writer {
+    connection: Connection,
+    encoder: Encoder
+}
+
+impl Shared {
+    pub fn new(connection: Connection, send_timeout: Option<Duration>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                peer: Settings::default(),
+                send_window: DEFAULT_WINDOW_SIZE as i64,
+                streams: HashMap::new(),
+                priorities: PriorityTree::default(),
+                waiting: HashSet::new(),
+                next_push_id: 2,
+                closed: false
+            }),
+            changed: Condvar::new(),
+            peer_addr: connection.peer_addr().ok(),
+            secure: connection.is_secure(),
+            writer: Mutex::new(Writer { connection, encoder: Encoder::new() }),
+            send_timeout,
+            push: OnceLock::new()
+        }
+    }
+
+    /// Let workers push, their pushed requests go through `dispatch`
+    pub fn enable_push(&self, dispatch: PushDispatch) {
+        self.push.set(dispatch).unwrap_or_default();
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wake the workers waiting for window, they check again whether they may send
+    pub fn notify(&self) {
+        self.changed.notify_all();
+    }
+
+    pub fn write_frame(&self, frame: &Frame) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.connection.write_all(&frame.encode())?;
+        writer.connection.flush()
+    }
+
+    /// Take on the client's HPACK table size, before acknowledging its SETTINGS
+    pub fn set_encoder_table_size(&self, size: u32) {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.encoder.set_max_table_size(size as usize);
+    }
+
+    /// Close the connection once the reader is done, waking every worker still waiting to send
+    pub fn close(&self) {
+        self.lock().closed = true;
+        self.notify();
+        self.writer.lock().unwrap_or_else(PoisonError::into_inner).connection.shutdown();
+    }
+
+    fn write_headers(&self, stream_id: u32, headers: &[(String, String)], end_stream: bool, max_frame_size: usize) -> io::Result<()> {
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        writer.write_block(stream_id, headers, max_frame_size, |block, end_headers| Frame::Headers { stream_id, block, end_stream, end_headers, priority: None })
+    }
+
+    /// Reserve a stream for a request the client didn't send and tell it with a PUSH_PROMISE on `stream_id`
+    /// `None` if the client refuses pushes or has as many pushed streams open as it allows
+    fn promise(&self, stream_id: u32, headers: &[(String, String)]) -> io::Result<Option<u32>> {
+        // Promised streams have to be announced in the order of their ids
+        let mut writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut state = self.lock();
+        let pushed = state.streams.keys().filter(|id| id.is_multiple_of(2)).count();
+        let full = state.peer.get_max_concurrent_streams().is_some_and(|max| pushed >= max as usize);
+        if state.closed || !state.streams.contains_key(&stream_id) || !state.peer.get_enable_push() || full || state.next_push_id > MAX_STREAM_ID {
+            return Ok(None);
+        }
+
+        let promised_stream_id = state.next_push_id;
+        state.next_push_id += 2;
+        // Pushed streams start out depending on the stream that promised them
+        state.priorities.insert(promised_stream_id, Some(Priority::new(stream_id, false, 16)));
+        let send_window = state.peer.get_initial_window_size() as i64;
+        let authority = state.streams.get(&stream_id).map(|stream| stream.authority.clone()).unwrap_or_default();
+        state.streams.insert(promised_stream_id, Stream { send_window, receive_window: 0, unacknowledged: 0, request: None, authority });
+        // The promised stream id takes 4 bytes of the first frame
+        let max_frame_size = state.peer.get_max_frame_size() as usize - 4;
+        drop(state);
+
+        writer.write_block(stream_id, headers, max_frame_size, |block, end_headers| Frame::PushPromise { stream_id, promised_stream_id, block, end_headers })?;
+        Ok(Some(promised_stream_id))
+    }
+}
+
+impl Writer {
+    /// Compress and send a header block, split across CONTINUATION frames when larger than `max_frame_size`
+    fn write_block<F>(&mut self, stream_id: u32, headers: &[(String, String)], max_frame_size: usize, first: F) -> io::Result<()>
+        where F: FnOnce(Vec<u8>, bool) -> Frame
+    {
+        let block = self.encoder.encode(headers);
+
+        let mut chunks = block.chunks(max_frame_size).peekable();
+        let first_chunk = chunks.next().unwrap_or_default().to_vec();
+        let mut frames = first(first_chunk, chunks.peek().is_none()).encode();
+        while let Some(chunk) = chunks.next() {
+            frames.extend(Frame::Continuation { stream_id, block: chunk.to_vec(), end_headers: chunks.peek().is_none() }.encode());
+        }
+
+        self.connection.write_all(&frames)?;
+        self.connection.flush()
+    }
+}
+
+impl State {
+    /// Apply a new SETTINGS_INITIAL_WINDOW_SIZE to every open stream's window
+    pub fn resize_windows(&mut self, previous: u32) -> Result<(), H2Error> {
+        let delta = self.peer.get_initial_window_size() as i64 - previous as i64;
+        for stream in self.streams.values_mut() {
+            stream.send_window += delta;
+            if stream.send_window > MAX_WINDOW_SIZE as i64 {
+                return Err(H2Error::connection(ErrorCode::FlowControlError, "SETTINGS_INITIAL_WINDOW_SIZE overflowed a stream's window"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forget a stream, the worker answering it gives up on its next write
+    pub fn remove(&mut self, stream_id: u32) -> bool {
+        self.priorities.remove(stream_id);
+        self.streams.remove(&stream_id).is_some()
+    }
+
+    /// The waiting stream whose turn it is to send, if the connection has window left
+    fn next_sender(&self) -> Option<u32> {
+        if self.send_window <= 0 {
+            return None;
+        }
+
+        let ready = self.waiting.iter()
+            .copied()
+            .filter(|id| self.streams.get(id).is_some_and(|stream| stream.send_window > 0))
+            .collect::<Vec<u32>>();
+        self.priorities.next(&ready)
+    }
+}
+
+/// Where the response of one stream goes, handed to the worker answering it
+#[derive(Clone)]
+pub struct StreamSink {
+    shared: Arc<Shared>,
+    stream_id: u32
+}
+
+impl StreamSink {
+    pub fn new(shared: Arc<Shared>, stream_id: u32) -> Self {
+        Self {
+            shared,
+            stream_id
+        }
+    }
+
+    pub fn get_stream_id(&self) -> u32 {
+        self.stream_id
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.shared.peer_addr.ok_or_else(|| io::Error::from(ErrorKind::NotConnected))
+    }
+
+    /// Send the response as HEADERS and DATA frames, waiting on flow control and the stream's priority
+    /// Returns the size of the body sent, the stream is closed either way
+    pub fn send(&self, response: &Response) -> io::Result<usize> {
+        let result = self.send_frames(response);
+        let mut state = self.shared.lock();
+        let open = state.remove(self.stream_id);
+        drop(state);
+        self.shared.notify();
+
+        // Don't leave the client waiting on a response that will never finish
+        if result.is_err() && open {
+            self.shared.write_frame(&Frame::RstStream { stream_id: self.stream_id, error: ErrorCode::InternalError }).unwrap_or_default();
+        }
+        result
+    }
+
+    fn send_frames(&self, response: &Response) -> io::Result<usize> {
+        let (headers, body) = message::response_head(response);
+        let max_frame_size = self.check_open()?.get_max_frame_size() as usize;
+        // Promised before the response refers to them, pushed streams can't push themselves
+        if !self.stream_id.is_multiple_of(2) {
+            for (path, push_headers) in response.get_pushes() {
+                self.push(&path, &push_headers)?;
+            }
+        }
+        self.shared.write_headers(self.stream_id, &headers, body.is_empty(), max_frame_size)?;
+
+        let mut sent = 0;
+        while sent < body.len() {
+            let size = self.reserve(body.len() - sent)?;
+            let data = body[sent..sent + size].to_vec();
+            sent += size;
+            self.shared.write_frame(&Frame::Data { stream_id: self.stream_id, data, end_stream: sent == body.len(), padding: 0 })?;
+        }
+
+        Ok(body.len())
+    }
+
+    /// Promise the client a `GET` of `path` and route it like a request it sent, skipped if the client won't take it
+    fn push(&self, path: &str, headers: &[(String, String)]) -> io::Result<()> {
+        let dispatch = match self.shared.push.get() {
+            None => return Ok(()),
+            Some(dispatch) => dispatch
+        };
+
+        let authority = self.shared.lock().streams.get(&self.stream_id).map(|stream| stream.authority.clone()).unwrap_or_default();
+        let scheme = match self.shared.secure {
+            true => "https",
+            false => "http"
+        };
+        let mut request_headers = vec![
+            (String::from(":method"), String::from("GET")),
+            (String::from(":scheme"), String::from(scheme)),
+            (String::from(":authority"), authority),
+            (String::from(":path"), String::from(path))
+        ];
+        request_headers.extend(headers.iter().map(|(name, value)| (name.to_ascii_lowercase(), value.clone())));
+
+        // Pushes that wouldn't make a valid request aren't promised
+        let incoming = match (path.starts_with('/'), Incoming::new(self.stream_id, request_headers.clone())) {
+            (true, Ok(incoming)) => incoming,
+            _ => return Ok(())
+        };
+        if let Some(promised_stream_id) = self.shared.promise(self.stream_id, &request_headers)? {
+            dispatch(incoming, StreamSink::new(Arc::clone(&self.shared), promised_stream_id));
+        }
+        Ok(())
+    }
+
+    /// The client's settings, if the stream is still open
+    fn check_open(&self) -> io::Result<Settings> {
+        let state = self.shared.lock();
+        match (state.closed, state.streams.contains_key(&self.stream_id)) {
+            (true, _) => Err(io::Error::new(ErrorKind::BrokenPipe, "HTTP/2 connection closed")),
+            (false, false) => Err(io::Error::new(ErrorKind::ConnectionReset, "HTTP/2 stream reset")),
+            (false, true) => Ok(state.peer)
+        }
+    }
+
+    /// Wait until the stream may send up to `wanted` bytes, returning how many it may
+    fn reserve(&self, wanted: usize) -> io::Result<usize> {
+        let deadline = Instant::now() + self.shared.send_timeout.unwrap_or(DEFAULT_SEND_TIMEOUT);
+        let mut state = self.shared.lock();
+        state.waiting.insert(self.stream_id);
+
+        let result = loop {
+            let stream_window = match (state.closed, state.streams.get(&self.stream_id)) {
+                (true, _) => break Err(io::Error::new(ErrorKind::BrokenPipe, "HTTP/2 connection closed")),
+                (false, None) => break Err(io::Error::new(ErrorKind::ConnectionReset, "HTTP/2 stream reset")),
+                (false, Some(stream)) => stream.send_window
+            };
+
+            if state.next_sender() == Some(self.stream_id) {
+                let size = (wanted as i64).min(stream_window).min(state.send_window).min(state.peer.get_max_frame_size() as i64) as usize;
+                state.send_window -= size as i64;
+                if let Some(stream) = state.streams.get_mut(&self.stream_id) {
+                    stream.send_window -= size as i64;
+                }
+                state.priorities.charge(self.stream_id, size);
+                break Ok(size);
+            }
+
+            state = match deadline.checked_duration_since(Instant::now()) {
+                None => break Err(io::Error::new(ErrorKind::TimedOut, "Timed out waiting for the client's flow control window")),
+                Some(remaining) => self.shared.changed.wait_timeout(state, remaining).unwrap_or_else(PoisonError::into_inner).0
+            };
+        };
+
+        state.waiting.remove(&self.stream_id);
+        drop(state);
+        // Whoever is next may go now
+        self.shared.notify();
+        result
+    }
+}
+
+/// Add a WINDOW_UPDATE's increment to a window, false if it would grow past 2^31-1
+pub fn grow_window(window: &mut i64, increment: u32) -> bool {
+    match *window + increment as i64 > MAX_WINDOW_SIZE as i64 {
+        true => false,
+        false => {
+            *window += increment as i64;
+            true
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grow_window, Shared, Stream, StreamSink};
+    use super::super::super::connection::Connection;
+    use super::super::super::super::super::http::Response;
+    use super::super::super::super::super::http::h2::ErrorCode;
+    use super::super::super::super::super::http::h2::frame::{Frame, Priority};
+    use super::super::super::super::super::http::h2::settings::MAX_WINDOW_SIZE;
+    use std::io::{ErrorKind, Read};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    /// The server's side of a loopback connection and the client's socket
+    fn connect(send_timeout: Option<Duration>) -> (Arc<Shared>, TcpStream) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (Arc::new(Shared::new(Connection::new(server), send_timeout)), client)
+    }
+
+    fn open(shared: &Shared, stream_id: u32, send_window: i64, priority: Option<Priority>) {
+        let mut state = shared.lock();
+        state.priorities.insert(stream_id, priority);
+        state.streams.insert(stream_id, Stream { send_window, receive_window: 0, unacknowledged: 0, request: None, authority: String::new() });
+    }
+
+    fn read_frame(client: &mut TcpStream, buffer: &mut Vec<u8>) -> Frame {
+        loop {
+            if let Some((frame, size)) = Frame::parse(buffer, 16384).unwrap() {
+                buffer.drain(..size);
+                return frame;
+            }
+            let mut chunk = [0; 4096];
+            let size = client.read(&mut chunk).unwrap();
+            assert!(size > 0, "connection closed");
+            buffer.extend_from_slice(&chunk[..size]);
+        }
+    }
+
+    fn data(frame: Frame) -> (u32, usize, bool) {
+        match frame {
+            Frame::Data { stream_id, data, end_stream, .. } => (stream_id, data.len(), end_stream),
+            frame => panic!("expected DATA, got {:?}", frame)
+        }
+    }
+
+    fn send(shared: &Arc<Shared>, stream_id: u32, body_size: usize) -> thread::JoinHandle<std::io::Result<usize>> {
+        let sink = StreamSink::new(Arc::clone(shared), stream_id);
+        thread::spawn(move || sink.send(&Response::new(vec![b'a'; body_size])))
+    }
+
+    #[test]
+    fn bodies_wait_for_the_stream_window() {
+        let (shared, mut client) = connect(None);
+        let mut buffer = vec![];
+        open(&shared, 1, 10, None);
+        let sending = send(&shared, 1, 25);
+
+        assert!(matches!(read_frame(&mut client, &mut buffer), Frame::Headers { stream_id: 1, end_stream: false, .. }));
+        assert_eq!(data(read_frame(&mut client, &mut buffer)), (1, 10, false));
+
+        grow_window(&mut shared.lock().streams.get_mut(&1).unwrap().send_window, 100);
+        shared.notify();
+        assert_eq!(data(read_frame(&mut client, &mut buffer)), (1, 15, true));
+        assert_eq!(sending.join().unwrap().unwrap(), 25);
+
+        // Both windows paid for the body and the stream is closed
+        let state = shared.lock();
+        assert_eq!(state.send_window, 65535 - 25);
+        assert!(state.streams.is_empty());
+        assert_eq!(state.priorities.get_size(), 0);
+    }
+
+    #[test]
+    fn bodies_wait_for_the_connection_window() {
+        let (shared, mut client) = connect(None);
+        let mut buffer = vec![];
+        shared.lock().send_window = 5;
+        open(&shared, 1, 65535, None);
+        let sending = send(&shared, 1, 8);
+
+        read_frame(&mut client, &mut buffer);
+        assert_eq!(data(read_frame(&mut client, &mut buffer)), (1, 5, false));
+        grow_window(&mut shared.lock().send_window, 3);
+        shared.notify();
+        assert_eq!(data(read_frame(&mut client, &mut buffer)), (1, 3, true));
+        assert_eq!(sending.join().unwrap().unwrap(), 8);
+    }
+
+    #[test]
+    fn stalled_sends_time_out_and_reset_the_stream() {
+        let (shared, mut client) = connect(Some(Duration::from_millis(50)));
+        let mut buffer = vec![];
+        open(&shared, 1, 0, None);
+
+        let error = send(&shared, 1, 10).join().unwrap().unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::TimedOut);
+        read_frame(&mut client, &mut buffer);
+        assert_eq!(read_frame(&mut client, &mut buffer), Frame::RstStream { stream_id: 1, error: ErrorCode::InternalError });
+    }
+
+    #[test]
+    fn resets_and_closing_wake_waiting_senders() {
+        let (shared, _client) = connect(None);
+        open(&shared, 1, 0, None);
+        open(&shared, 3, 0, None);
+        let (reset, closed) = (send(&shared, 1, 10), send(&shared, 3, 10));
+
+        thread::sleep(Duration::from_millis(50));
+        shared.lock().remove(1);
+        shared.notify();
+        assert_eq!(reset.join().unwrap().unwrap_err().kind(), ErrorKind::ConnectionReset);
+
+        shared.close();
+        assert_eq!(closed.join().unwrap().unwrap_err().kind(), ErrorKind::BrokenPipe);
+    }
+
+    #[test]
+    fn senders_take_turns_by_priority() {
+        let (shared, _client) = connect(None);
+        open(&shared, 1, 0, None);
+        open(&shared, 3, 100, Some(Priority::new(1, false, 16)));
+        let mut state = shared.lock();
+        state.waiting.extend([1, 3]);
+
+        // 1 has no window left, so its dependent may go
+        assert_eq!(state.next_sender(), Some(3));
+        state.streams.get_mut(&1).unwrap().send_window = 100;
+        assert_eq!(state.next_sender(), Some(1));
+        state.send_window = 0;
+        assert_eq!(state.next_sender(), None);
+    }
+
+    #[test]
+    fn initial_window_changes_apply_to_open_streams() {
+        let (shared, _client) = connect(None);
+        open(&shared, 1, 100, None);
+        let mut state = shared.lock();
+
+        state.peer.set_initial_window_size(65535 - 1000);
+        state.resize_windows(65535).unwrap();
+        // Windows may go negative, the stream waits until updates bring it back
+        assert_eq!(state.streams[&1].send_window, -900);
+
+        // A stream whose window grew through WINDOW_UPDATE can overflow
+        state.streams.get_mut(&1).unwrap().send_window = 70000;
+        state.peer.set_initial_window_size(MAX_WINDOW_SIZE);
+        assert_eq!(state.resize_windows(65535 - 1000).unwrap_err().get_code(), ErrorCode::FlowControlError);
+    }
+
+    #[test]
+    fn windows_grow_up_to_the_limit() {
+        let mut window = 0;
+        assert!(grow_window(&mut window, MAX_WINDOW_SIZE));
+        assert!(!grow_window(&mut window, 1));
+        assert_eq!(window, MAX_WINDOW_SIZE as i64);
+
+        let mut window = -10;
+        assert!(grow_window(&mut window, 15));
+        assert_eq!(window, 5);
+    }
+}
@@ -0,0 +1,148 @@
+use super::options::ServerOptions;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Counters describing how much load the server is under and how much it turned away
+#[derive(Debug, Default)]
+pub struct LoadMetrics {
+    connections: AtomicUsize,
+    queued: AtomicUsize,
+    rejected_connections: AtomicUsize,
+    rejected_queue_full: AtomicUsize,
+    rejected_queue_timeout: AtomicUsize
+}
+
+impl LoadMetrics {
+    /// Connections accepted and not yet answered
+    pub fn get_connections(&self) -> usize {
+        self.connections.load(Ordering::Relaxed)
+    }
+
+    /// Requests waiting for a free worker
+    pub fn get_queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    pub fn get_rejected_connections(&self) -> usize {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub fn get_rejected_queue_full(&self) -> usize {
+        self.rejected_queue_full.load(Ordering::Relaxed)
+    }
+
+    pub fn get_rejected_queue_timeout(&self) -> usize {
+        self.rejected_queue_timeout.load(Ordering::Relaxed)
+    }
+
+    pub fn get_rejected(&self) -> usize {
+        self.get_rejected_connections() + self.get_rejected_queue_full() + self.get_rejected_queue_timeout()
+    }
+}
+
+/// Enforces the load limits from the server options
+pub struct Load {
+    max_concurrent_connections: Option<usize>,
+    queue_wait_timeout: Option<Duration>,
+    retry_after: u64,
+    metrics: Arc<LoadMetrics>
+}
+
+impl Load {
+    pub fn new(options: &ServerOptions) -> Self {
+        Self {
+            max_concurrent_connections: options.get_max_concurrent_connections(),
+            queue_wait_timeout: options.get_queue_wait_timeout(),
+            retry_after: options.get_retry_after(),
+            metrics: Arc::new(LoadMetrics::default())
+        }
+    }
+
+    pub fn get_metrics(&self) -> Arc<LoadMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Seconds clients are told to wait before retrying a rejected request
+    pub fn get_retry_after(&self) -> u64 {
+        self.retry_after
+    }
+
+    /// Count a new connection, `None` if there are already too many
+    pub fn try_connect(&self) -> Option<ConnectionGuard> {
+        let connections = self.metrics.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        let guard = ConnectionGuard {
+            metrics: Arc::clone(&self.metrics)
+        };
+
+        match self.max_concurrent_connections {
+            Some(max) if connections > max => {
+                self.metrics.rejected_connections.fetch_add(1, Ordering::Relaxed);
+                None
+            },
+            _ => Some(guard)
+        }
+    }
+
+    /// Count a request waiting for a worker
+    pub fn enqueue(&self) -> QueueTicket {
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+
+        QueueTicket {
+            metrics: Arc::clone(&self.metrics),
+            queued_at: Instant::now(),
+            waiting: true
+        }
+    }
+
+    /// Count a request turned away because the worker queue was full
+    pub fn reject_queue_full(&self) {
+        self.metrics.rejected_queue_full.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A worker picked up the request, false if it waited longer than allowed
+    pub fn start(&self, mut ticket: QueueTicket) -> bool {
+        ticket.leave_queue();
+
+        match self.queue_wait_timeout {
+            Some(timeout) if ticket.queued_at.elapsed() > timeout => {
+                self.metrics.rejected_queue_timeout.fetch_add(1, Ordering::Relaxed);
+                false
+            },
+            _ => true
+        }
+    }
+}
+
+/// Held for as long as a connection is open
+pub struct ConnectionGuard {
+    metrics: Arc<LoadMetrics>
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.metrics.connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Held for as long as a request waits for a worker
+pub struct QueueTicket {
+    metrics: Arc<LoadMetrics>,
+    queued_at: Instant,
+    waiting: bool
+}
+
+impl QueueTicket {
+    fn leave_queue(&mut self) {
+        if self.waiting {
+            self.waiting = false;
+            self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Drop for QueueTicket {
+    fn drop(&mut self) {
+        self.leave_queue();
+    }
+}
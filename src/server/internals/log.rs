@@ -0,0 +1,317 @@
+use super::events::{Events, ServerEvent};
+use super::super::super::http::request::log::{LogLevel, RequestChannel, RequestLogger};
+use std::fmt::Display;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A single log entry
+#[derive(Debug, Clone)]
+pub struct LogEvent {
+    timestamp: SystemTime,
+    level: LogLevel,
+    tags: Vec<String>,
+    request_id: Option<String>,
+    data: String
+}
+
+impl LogEvent {
+    pub fn get_timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    pub fn get_level(&self) -> LogLevel {
+        self.level
+    }
+
+    pub fn get_tags(&self) -> Vec<String> {
+        self.tags.clone()
+    }
+
+    /// The request the event was logged for, `None` for server events
+    pub fn get_request_id(&self) -> Option<String> {
+        self.request_id.clone()
+    }
+
+    pub fn get_data(&self) -> String {
+        self.data.clone()
+    }
+
+    /// `2026-01-02T03:04:05.678Z info [server,start] data`
+    pub fn to_text(&self) -> String {
+        let request = match &self.request_id {
+            None => String::new(),
+            Some(id) => format!(" (request {})", id)
+        };
+        format!("{} {} [{}]{} {}", format_timestamp(self.timestamp), self.level, self.tags.join(","), request, self.data)
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::json!({
+            "timestamp": format_timestamp(self.timestamp),
+            "level": self.level.to_string(),
+            "tags": self.tags,
+            "requestId": self.request_id,
+            "data": self.data
+        }).to_string()
+    }
+}
+
+/// Where log events are written
+#[derive(Clone, Default)]
+pub enum LogSink {
+    /// One human readable line per event on stderr
+    #[default]
+    Stderr,
+    /// One JSON object per line on stderr
+    JsonLines,
+    Callback(Arc<dyn Fn(&LogEvent) + Send + Sync>)
+}
+
+impl LogSink {
+    pub fn callback<F>(f: F) -> Self
+        where F: Fn(&LogEvent) + Send + Sync + 'static
+    {
+        LogSink::Callback(Arc::new(f))
+    }
+}
+
+/// Sends tagged log events at or above its level to a sink and the server's event listeners, cheap to clone
+#[derive(Clone)]
+pub struct Logger {
+    level: LogLevel,
+    sink: LogSink,
+    events: Option<Arc<Events>>
+}
+
+impl Logger {
+    pub fn new(level: LogLevel, sink: LogSink) -> Self {
+        Self {
+            level,
+            sink,
+            events: None
+        }
+    }
+
+    /// Also emit every event as a `log` or `request` server event
+    pub(crate) fn with_events(mut self, events: Arc<Events>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    pub fn get_level(&self) -> LogLevel {
+        self.level
+    }
+
+    pub fn is_enabled(&self, level: LogLevel) -> bool {
+        level >= self.level
+    }
+
+    pub fn log<D: Display>(&self, level: LogLevel, tags: &[&str], data: D) {
+        self.log_for(None, level, tags, data);
+    }
+
+    /// Log on behalf of a request when given its id and channel, events carry the id
+    pub(crate) fn log_for<D: Display>(&self, request: Option<(String, RequestChannel)>, level: LogLevel, tags: &[&str], data: D) {
+        if !self.is_enabled(level) {
+            return;
+        }
+
+        let event = LogEvent {
+            timestamp: SystemTime::now(),
+            level,
+            tags: tags.iter().map(|tag| tag.to_string()).collect(),
+            request_id: request.as_ref().map(|(id, _)| id.clone()),
+            data: data.to_string()
+        };
+
+        match self.sink {
+            LogSink::Stderr => eprintln!("{}", event.to_text()),
+            LogSink::JsonLines => eprintln!("{}", event.to_json()),
+            LogSink::Callback(ref callback) => callback(&event)
+        }
+
+        if let Some(ref events) = self.events {
+            events.emit(match request {
+                None => ServerEvent::Log(event),
+                Some((_, channel)) => ServerEvent::Request { channel, event }
+            });
+        }
+    }
+
+    pub fn trace<D: Display>(&self, tags: &[&str], data: D) {
+        self.log(LogLevel::Trace, tags, data);
+    }
+
+    pub fn debug<D: Display>(&self, tags: &[&str], data: D) {
+        self.log(LogLevel::Debug, tags, data);
+    }
+
+    pub fn info<D: Display>(&self, tags: &[&str], data: D) {
+        self.log(LogLevel::Info, tags, data);
+    }
+
+    pub fn warn<D: Display>(&self, tags: &[&str], data: D) {
+        self.log(LogLevel::Warn, tags, data);
+    }
+
+    pub fn error<D: Display>(&self, tags: &[&str], data: D) {
+        self.log(LogLevel::Error, tags, data);
+    }
+}
+
+impl Default for Logger {
+    fn default() -> Self {
+        Self::new(LogLevel::Info, LogSink::default())
+    }
+}
+
+impl RequestLogger for Logger {
+    fn is_enabled(&self, level: LogLevel) -> bool {
+        Logger::is_enabled(self, level)
+    }
+
+    fn log_request(&self, id: String, channel: RequestChannel, level: LogLevel, tags: &[&str], data: String) {
+        self.log_for(Some((id, channel)), level, tags, data);
+    }
+}
+
+/// RFC 3339 UTC timestamp with milliseconds
+pub fn format_timestamp(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    let seconds_of_day = seconds % 86400;
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}Z",
+        year, month, day,
+        seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// Year, month and day of a count of days since 1970-01-01
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LogEvent, LogSink, Logger, civil_from_days, format_timestamp};
+    use super::super::events::{EventFilter, EventName, Events, ServerEvent};
+    use super::super::super::super::http::request::log::{LogLevel, RequestChannel};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    fn event(request_id: Option<&str>) -> LogEvent {
+        LogEvent {
+            timestamp: UNIX_EPOCH + Duration::from_millis(1_767_323_045_678),
+            level: LogLevel::Warn,
+            tags: vec![String::from("server"), String::from("start")],
+            request_id: request_id.map(String::from),
+            data: String::from("listening")
+        }
+    }
+
+    /// A logger at `level` and the events its sink received
+    fn capturing(level: LogLevel) -> (Logger, Arc<Mutex<Vec<LogEvent>>>) {
+        let captured = Arc::new(Mutex::new(vec![]));
+        let sink_captured = Arc::clone(&captured);
+        let logger = Logger::new(level, LogSink::callback(move |event: &LogEvent| sink_captured.lock().unwrap().push(event.clone())));
+        (logger, captured)
+    }
+
+    #[test]
+    fn text_lines() {
+        assert_eq!(event(None).to_text(), "2026-01-02T03:04:05.678Z warn [server,start] listening");
+        assert_eq!(event(Some("7")).to_text(), "2026-01-02T03:04:05.678Z warn [server,start] (request 7) listening");
+    }
+
+    #[test]
+    fn json_lines() {
+        let json: serde_json::Value = serde_json::from_str(&event(Some("7")).to_json()).unwrap();
+        assert_eq!(json, serde_json::json!({
+            "timestamp": "2026-01-02T03:04:05.678Z",
+            "level": "warn",
+            "tags": ["server", "start"],
+            "requestId": "7",
+            "data": "listening"
+        }));
+
+        let json: serde_json::Value = serde_json::from_str(&event(None).to_json()).unwrap();
+        assert_eq!(json["requestId"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn level_names() {
+        let levels = [LogLevel::Trace, LogLevel::Debug, LogLevel::Info, LogLevel::Warn, LogLevel::Error];
+        let names: Vec<String> = levels.iter().map(|level| level.to_string()).collect();
+        assert_eq!(names, vec!["trace", "debug", "info", "warn", "error"]);
+    }
+
+    #[test]
+    fn timestamps() {
+        assert_eq!(format_timestamp(UNIX_EPOCH), "1970-01-01T00:00:00.000Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_millis(951_782_399_999)), "2000-02-28T23:59:59.999Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(951_782_400)), "2000-02-29T00:00:00.000Z");
+        assert_eq!(format_timestamp(UNIX_EPOCH + Duration::from_secs(4_107_542_400)), "2100-03-01T00:00:00.000Z");
+        // Before the epoch is clamped to it
+        assert_eq!(format_timestamp(UNIX_EPOCH - Duration::from_secs(1)), "1970-01-01T00:00:00.000Z");
+    }
+
+    #[test]
+    fn civil_dates() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(11_016), (2000, 2, 29));
+        assert_eq!(civil_from_days(11_017), (2000, 3, 1));
+        assert_eq!(civil_from_days(47_540), (2100, 2, 28));
+        assert_eq!(civil_from_days(47_541), (2100, 3, 1));
+    }
+
+    #[test]
+    fn events_below_the_level_are_dropped() {
+        let (logger, captured) = capturing(LogLevel::Info);
+        logger.debug(&["dropped"], "below");
+        logger.info(&["kept"], "at");
+        logger.error(&["kept"], "above");
+
+        let captured = captured.lock().unwrap();
+        let data: Vec<String> = captured.iter().map(LogEvent::get_data).collect();
+        assert_eq!(data, vec!["at", "above"]);
+        assert!(!logger.is_enabled(LogLevel::Trace));
+        assert!(logger.is_enabled(LogLevel::Warn));
+    }
+
+    #[test]
+    fn request_events_carry_the_id_and_channel() {
+        let events = Arc::new(Events::default());
+        let received = Arc::new(Mutex::new(vec![]));
+        let listener_received = Arc::clone(&received);
+        events.on(EventFilter::new(EventName::Request).channels(vec![RequestChannel::App]), move |event: &ServerEvent| {
+            if let ServerEvent::Request { channel, event } = event {
+                listener_received.lock().unwrap().push((*channel, event.get_request_id()));
+            }
+        });
+
+        let (logger, captured) = capturing(LogLevel::Trace);
+        let logger = logger.with_events(events);
+        logger.log_for(Some((String::from("3"), RequestChannel::App)), LogLevel::Info, &["app"], "hello");
+        logger.log_for(Some((String::from("3"), RequestChannel::Internal)), LogLevel::Info, &["internal"], "filtered");
+        logger.info(&["server"], "not a request");
+
+        assert_eq!(*received.lock().unwrap(), vec![(RequestChannel::App, Some(String::from("3")))]);
+        assert_eq!(captured.lock().unwrap().len(), 3);
+        assert!(captured.lock().unwrap()[0].get_timestamp() <= SystemTime::now());
+    }
+}
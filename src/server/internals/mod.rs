@@ -1,3 +1,22 @@
+pub mod access_log;
+pub mod auth;
+pub mod connection;
+pub mod context;
+pub mod events;
+pub mod executor;
+pub mod fallback;
+pub mod http2;
+pub mod load;
+pub mod log;
 pub mod options;
+pub mod prometheus;
+pub mod proxy;
+pub mod proxy_protocol;
+pub mod reader;
 pub mod route;
-pub mod thread_pool;
\ No newline at end of file
+pub mod stop;
+pub mod thread_pool;
+pub mod timer;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod tracing;
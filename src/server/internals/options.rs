@@ -1,11 +1,58 @@
-use super::super::HTTPVersion;
+use super::access_log::AccessLogFormat;
+use super::log::LogSink;
+use super::proxy::Cidr;
+use super::proxy_protocol::ProxyProtocol;
+#[cfg(feature = "tls")]
+use super::tls::{ClientAuth, TlsCertificate};
+use super::tracing::SpanExporter;
+use super::super::super::http::{ErrorFormat, HTTPVersion};
+use super::super::super::http::request::log::LogLevel;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
 
 pub struct ServerOptions {
     host: String,
     port: u32,
     server_threads: usize,
     worker_threads: usize,
-    default_http_version: HTTPVersion
+    default_http_version: HTTPVersion,
+    error_format: ErrorFormat,
+    expose_server_errors: bool,
+    queue_capacity: usize,
+    max_concurrent_connections: Option<usize>,
+    queue_wait_timeout: Option<Duration>,
+    retry_after: u64,
+    idle_timeout: Option<Duration>,
+    header_read_timeout: Option<Duration>,
+    body_read_timeout: Option<Duration>,
+    socket_timeout: Option<Duration>,
+    max_payload_size: usize,
+    max_worker_threads: Option<usize>,
+    worker_idle_timeout: Duration,
+    log_level: LogLevel,
+    log_sink: LogSink,
+    access_log: Option<PathBuf>,
+    access_log_format: AccessLogFormat,
+    access_log_max_size: Option<u64>,
+    access_log_max_files: usize,
+    metrics_route: Option<String>,
+    span_exporter: Option<Arc<dyn SpanExporter>>,
+    trace_sampling_ratio: f64,
+    request_id_header: String,
+    honor_request_id: bool,
+    trusted_proxies: Vec<Cidr>,
+    proxy_protocol: ProxyProtocol,
+    http2_max_concurrent_streams: u32,
+    http2_initial_window_size: u32,
+    #[cfg(feature = "tls")]
+    tls_certificates: Vec<(Option<String>, TlsCertificate)>,
+    #[cfg(feature = "tls")]
+    tls_reload_interval: Duration,
+    #[cfg(feature = "tls")]
+    tls_client_auth: ClientAuth,
+    #[cfg(feature = "tls")]
+    tls_client_ca: Option<PathBuf>
 }
 
 impl ServerOptions {
@@ -15,7 +62,8 @@ impl ServerOptions {
             port,
             server_threads,
             worker_threads,
-            default_http_version
+            default_http_version,
+            ..Self::default()
         }
     }
 
@@ -35,8 +83,315 @@ impl ServerOptions {
         self.worker_threads
     }
 
+    /// Status line version of answers to requests that didn't parse, others answer in the request's own version
     pub fn get_default_http_version(&self) -> HTTPVersion {
-        self.default_http_version.clone()
+        self.default_http_version
+    }
+
+    pub fn get_error_format(&self) -> ErrorFormat {
+        self.error_format
+    }
+
+    pub fn get_expose_server_errors(&self) -> bool {
+        self.expose_server_errors
+    }
+
+    /// Upper bound of the worker pool, it never grows past `worker_threads` unless this is set
+    pub fn get_max_worker_threads(&self) -> usize {
+        self.max_worker_threads.unwrap_or(self.worker_threads).max(self.worker_threads)
+    }
+
+    pub fn get_worker_idle_timeout(&self) -> Duration {
+        self.worker_idle_timeout
+    }
+
+    pub fn get_log_level(&self) -> LogLevel {
+        self.log_level
+    }
+
+    pub fn get_log_sink(&self) -> LogSink {
+        self.log_sink.clone()
+    }
+
+    pub fn get_access_log(&self) -> Option<PathBuf> {
+        self.access_log.clone()
+    }
+
+    pub fn get_access_log_format(&self) -> AccessLogFormat {
+        self.access_log_format
+    }
+
+    pub fn get_access_log_max_size(&self) -> Option<u64> {
+        self.access_log_max_size
+    }
+
+    pub fn get_access_log_max_files(&self) -> usize {
+        self.access_log_max_files
+    }
+
+    pub fn get_metrics_route(&self) -> Option<String> {
+        self.metrics_route.clone()
+    }
+
+    pub fn get_span_exporter(&self) -> Option<Arc<dyn SpanExporter>> {
+        self.span_exporter.clone()
+    }
+
+    pub fn get_trace_sampling_ratio(&self) -> f64 {
+        self.trace_sampling_ratio
+    }
+
+    pub fn get_request_id_header(&self) -> String {
+        self.request_id_header.clone()
+    }
+
+    pub fn get_honor_request_id(&self) -> bool {
+        self.honor_request_id
+    }
+
+    pub fn get_trusted_proxies(&self) -> Vec<Cidr> {
+        self.trusted_proxies.clone()
+    }
+
+    pub fn get_proxy_protocol(&self) -> ProxyProtocol {
+        self.proxy_protocol
+    }
+
+    pub fn get_http2_max_concurrent_streams(&self) -> u32 {
+        self.http2_max_concurrent_streams
+    }
+
+    pub fn get_http2_initial_window_size(&self) -> u32 {
+        self.http2_initial_window_size
+    }
+
+    /// Certificates by the hostname they serve, `None` for the default one
+    #[cfg(feature = "tls")]
+    pub fn get_tls_certificates(&self) -> Vec<(Option<String>, TlsCertificate)> {
+        self.tls_certificates.clone()
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn get_tls_reload_interval(&self) -> Duration {
+        self.tls_reload_interval
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn get_tls_client_auth(&self) -> ClientAuth {
+        self.tls_client_auth
+    }
+
+    #[cfg(feature = "tls")]
+    pub fn get_tls_client_ca(&self) -> Option<PathBuf> {
+        self.tls_client_ca.clone()
+    }
+
+    pub fn get_queue_capacity(&self) -> usize {
+        self.queue_capacity
+    }
+
+    pub fn get_max_concurrent_connections(&self) -> Option<usize> {
+        self.max_concurrent_connections
+    }
+
+    pub fn get_queue_wait_timeout(&self) -> Option<Duration> {
+        self.queue_wait_timeout
+    }
+
+    pub fn get_retry_after(&self) -> u64 {
+        self.retry_after
+    }
+
+    pub fn get_idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout
+    }
+
+    pub fn get_header_read_timeout(&self) -> Option<Duration> {
+        self.header_read_timeout
+    }
+
+    pub fn get_body_read_timeout(&self) -> Option<Duration> {
+        self.body_read_timeout
+    }
+
+    pub fn get_socket_timeout(&self) -> Option<Duration> {
+        self.socket_timeout
+    }
+
+    pub fn get_max_payload_size(&self) -> usize {
+        self.max_payload_size
+    }
+
+    /**
+     * Setters
+    */
+
+    /// Render errors as `application/problem+json` instead of the default JSON body
+    pub fn set_error_format(&mut self, error_format: ErrorFormat) {
+        self.error_format = error_format;
+    }
+
+    /// Send the messages of `5xx` errors to the client, by default they get a generic message instead
+    pub fn set_expose_server_errors(&mut self, expose: bool) {
+        self.expose_server_errors = expose;
+    }
+
+    /// Drop log events below this level, `Info` by default
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log_level = level;
+    }
+
+    /// Where log events go, text lines on stderr by default
+    pub fn set_log_sink(&mut self, sink: LogSink) {
+        self.log_sink = sink;
+    }
+
+    /// Record every response in this file, one line per request
+    pub fn set_access_log(&mut self, path: PathBuf, format: AccessLogFormat) {
+        self.access_log = Some(path);
+        self.access_log_format = format;
+    }
+
+    /// Rotate the access log once it would grow past this many bytes
+    pub fn set_access_log_max_size(&mut self, bytes: u64) {
+        self.access_log_max_size = Some(bytes);
+    }
+
+    /// How many rotated access logs to keep, `access.log.1` being the newest, 5 by default
+    pub fn set_access_log_max_files(&mut self, max_files: usize) {
+        self.access_log_max_files = max_files;
+    }
+
+    /// Collect request and thread pool metrics and serve them to Prometheus at this path
+    pub fn set_metrics_route(&mut self, path: String) {
+        self.metrics_route = Some(path);
+    }
+
+    /// Time every sampled request and its phases and hand the spans to this exporter
+    pub fn set_span_exporter(&mut self, exporter: Arc<dyn SpanExporter>) {
+        self.span_exporter = Some(exporter);
+    }
+
+    /// Share of the traces started by the server that are sampled, between 0 and 1, all of them by default
+    /// Requests continuing a caller's trace keep the caller's decision
+    pub fn set_trace_sampling_ratio(&mut self, ratio: f64) {
+        self.trace_sampling_ratio = ratio.clamp(0.0, 1.0);
+    }
+
+    /// Header every response echoes the request's id in, `X-Request-Id` by default
+    pub fn set_request_id_header(&mut self, name: String) {
+        self.request_id_header = name;
+    }
+
+    /// Use the id a client or proxy sent in the request id header instead of generating one
+    pub fn set_honor_request_id(&mut self, honor: bool) {
+        self.honor_request_id = honor;
+    }
+
+    /// Believe the `Forwarded` and `X-Forwarded-*` headers of connections from these addresses, such as `"10.0.0.0/8".parse()`
+    pub fn set_trusted_proxies(&mut self, proxies: Vec<Cidr>) {
+        self.trusted_proxies = proxies;
+    }
+
+    /// Accept or require an HAProxy PROXY protocol v1 or v2 header at the start of every connection
+    pub fn set_proxy_protocol(&mut self, proxy_protocol: ProxyProtocol) {
+        self.proxy_protocol = proxy_protocol;
+    }
+
+    /// How many streams an HTTP/2 client may have open at once, further ones are refused, 100 by default
+    pub fn set_http2_max_concurrent_streams(&mut self, max: u32) {
+        self.http2_max_concurrent_streams = max;
+    }
+
+    /// How much of a request body HTTP/2 clients may send ahead of the server reading it, per stream and per connection, 65,535 bytes by default
+    pub fn set_http2_initial_window_size(&mut self, size: u32) {
+        self.http2_initial_window_size = size;
+    }
+
+    /// Serve HTTPS with this certificate, the one clients get unless an SNI certificate matches the hostname they ask for
+    #[cfg(feature = "tls")]
+    pub fn set_tls_certificate(&mut self, certificate: TlsCertificate) {
+        self.tls_certificates.retain(|(hostname, _)| hostname.is_some());
+        self.tls_certificates.push((None, certificate));
+    }
+
+    /// Serve this certificate to clients asking for `hostname` through SNI, such as `api.example.com` or `*.example.com`
+    #[cfg(feature = "tls")]
+    pub fn add_tls_sni_certificate(&mut self, hostname: String, certificate: TlsCertificate) {
+        self.tls_certificates.push((Some(hostname), certificate));
+    }
+
+    /// How often certificate files are checked for changes and reloaded, 30 seconds by default
+    #[cfg(feature = "tls")]
+    pub fn set_tls_reload_interval(&mut self, interval: Duration) {
+        self.tls_reload_interval = interval;
+    }
+
+    /// Ask clients for a certificate during the handshake, verified against the client CA bundle
+    #[cfg(feature = "tls")]
+    pub fn set_tls_client_auth(&mut self, client_auth: ClientAuth) {
+        self.tls_client_auth = client_auth;
+    }
+
+    /// PEM file of the CAs client certificates have to chain up to
+    #[cfg(feature = "tls")]
+    pub fn set_tls_client_ca(&mut self, ca: PathBuf) {
+        self.tls_client_ca = Some(ca);
+    }
+
+    /// Let the worker pool grow past `worker_threads` up to this many workers while they are all busy
+    pub fn set_max_worker_threads(&mut self, max: usize) {
+        self.max_worker_threads = Some(max);
+    }
+
+    /// How long workers added above `worker_threads` may sit idle before leaving the pool
+    pub fn set_worker_idle_timeout(&mut self, timeout: Duration) {
+        self.worker_idle_timeout = timeout;
+    }
+
+    /// How many requests may wait for a free worker, 1024 by default, requests arriving while the queue is full are answered with a `503`
+    pub fn set_queue_capacity(&mut self, queue_capacity: usize) {
+        self.queue_capacity = queue_capacity;
+    }
+
+    /// Answer new connections with a `503` while this many are already open
+    pub fn set_max_concurrent_connections(&mut self, max: usize) {
+        self.max_concurrent_connections = Some(max);
+    }
+
+    /// Answer requests with a `503` if they waited longer than this for a worker
+    pub fn set_queue_wait_timeout(&mut self, timeout: Duration) {
+        self.queue_wait_timeout = Some(timeout);
+    }
+
+    /// Seconds sent in the `Retry-After` header of load rejections
+    pub fn set_retry_after(&mut self, seconds: u64) {
+        self.retry_after = seconds;
+    }
+
+    /// Close connections that don't start sending a request within this time
+    pub fn set_idle_timeout(&mut self, timeout: Duration) {
+        self.idle_timeout = Some(timeout);
+    }
+
+    /// Answer with a `408` if the request headers take longer than this to arrive
+    pub fn set_header_read_timeout(&mut self, timeout: Duration) {
+        self.header_read_timeout = Some(timeout);
+    }
+
+    /// Answer with a `408` if the request body takes longer than this to arrive
+    pub fn set_body_read_timeout(&mut self, timeout: Duration) {
+        self.body_read_timeout = Some(timeout);
+    }
+
+    /// Give up on any single read or write of the socket that blocks longer than this
+    pub fn set_socket_timeout(&mut self, timeout: Duration) {
+        self.socket_timeout = Some(timeout);
+    }
+
+    /// Answer with a `413`, before reading the body, if a request's `Content-Length` is larger than this many bytes
+    pub fn set_max_payload_size(&mut self, bytes: usize) {
+        self.max_payload_size = bytes;
     }
 }
 
@@ -47,7 +402,43 @@ impl Default for ServerOptions {
             port: 3000,
             server_threads: 1,
             worker_threads: 2,
-            default_http_version: HTTPVersion::HTTP20
+            default_http_version: HTTPVersion::HTTP11,
+            error_format: ErrorFormat::default(),
+            expose_server_errors: false,
+            queue_capacity: 1024,
+            max_concurrent_connections: None,
+            queue_wait_timeout: None,
+            retry_after: 1,
+            idle_timeout: None,
+            header_read_timeout: None,
+            body_read_timeout: None,
+            socket_timeout: None,
+            max_payload_size: 1024 * 1024,
+            max_worker_threads: None,
+            worker_idle_timeout: Duration::from_secs(60),
+            log_level: LogLevel::Info,
+            log_sink: LogSink::default(),
+            access_log: None,
+            access_log_format: AccessLogFormat::default(),
+            access_log_max_size: None,
+            access_log_max_files: 5,
+            metrics_route: None,
+            span_exporter: None,
+            trace_sampling_ratio: 1.0,
+            request_id_header: String::from("X-Request-Id"),
+            honor_request_id: false,
+            trusted_proxies: vec![],
+            proxy_protocol: ProxyProtocol::Off,
+            http2_max_concurrent_streams: 100,
+            http2_initial_window_size: 65_535,
+            #[cfg(feature = "tls")]
+            tls_certificates: vec![],
+            #[cfg(feature = "tls")]
+            tls_reload_interval: Duration::from_secs(30),
+            #[cfg(feature = "tls")]
+            tls_client_auth: ClientAuth::Off,
+            #[cfg(feature = "tls")]
+            tls_client_ca: None
         }
     }
 }
@@ -0,0 +1,270 @@
+use super::load::LoadMetrics;
+use super::thread_pool::metrics::{BUCKETS, Histogram, PoolMetrics};
+use super::super::super::http::request::Request;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Route label of requests no route matched
+const UNMATCHED: &str = "unmatched";
+
+/// Request rates, errors and durations by route template, method and status
+#[derive(Debug, Default)]
+pub struct HttpMetrics {
+    requests: Mutex<BTreeMap<(String, String, u32), u64>>,
+    durations: Mutex<BTreeMap<(String, String), Arc<Histogram>>>,
+    in_flight: AtomicUsize
+}
+
+impl HttpMetrics {
+    /// A request was parsed and is being handled
+    pub fn request_started(&self) {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The client got its response, `request` is `None` if it couldn't be parsed
+    pub fn request_finished(&self, request: Option<&Request>, status: u32, duration: Option<Duration>) {
+        let route = request.and_then(Request::get_route).unwrap_or_else(|| String::from(UNMATCHED));
+        let method = request.map(|req| req.get_method().to_string()).unwrap_or_else(|| String::from("unknown"));
+
+        if request.is_some() {
+            self.in_flight.fetch_sub(1, Ordering::Relaxed);
+        }
+
+        *self.requests.lock().unwrap_or_else(PoisonError::into_inner)
+            .entry((route.clone(), method.clone(), status))
+            .or_insert(0) += 1;
+
+        if let Some(duration) = duration {
+            let histogram = Arc::clone(
+                self.durations.lock().unwrap_or_else(PoisonError::into_inner)
+                    .entry((route, method))
+                    .or_default()
+            );
+            histogram.observe(duration);
+        }
+    }
+
+    pub fn get_in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// Everything in the Prometheus text exposition format
+    pub fn render(&self, pool: &PoolMetrics, load: &LoadMetrics) -> String {
+        let mut out = String::new();
+
+        header(&mut out, "hapi_http_requests_total", "counter", "Requests answered by route template, method and status");
+        for ((route, method, status), count) in self.requests.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            let labels = format!("route=\"{}\",method=\"{}\",status=\"{}\"", escape(route), escape(method), status);
+            writeln!(out, "hapi_http_requests_total{{{}}} {}", labels, count).unwrap_or_default();
+        }
+
+        header(&mut out, "hapi_http_request_duration_seconds", "histogram", "Time from accepting the connection to answering the request");
+        for ((route, method), histogram) in self.durations.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+            let labels = format!("route=\"{}\",method=\"{}\"", escape(route), escape(method));
+            histogram_lines(&mut out, "hapi_http_request_duration_seconds", &labels, histogram);
+        }
+
+        header(&mut out, "hapi_http_requests_in_flight", "gauge", "Requests being handled");
+        writeln!(out, "hapi_http_requests_in_flight {}", self.get_in_flight()).unwrap_or_default();
+
+        header(&mut out, "hapi_open_connections", "gauge", "Connections accepted and not yet answered");
+        writeln!(out, "hapi_open_connections {}", load.get_connections()).unwrap_or_default();
+
+        header(&mut out, "hapi_load_rejected_total", "counter", "Requests turned away with a 503 because of load limits");
+        writeln!(out, "hapi_load_rejected_total {}", load.get_rejected()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_workers", "gauge", "Worker threads");
+        writeln!(out, "hapi_pool_workers {}", pool.get_workers()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_busy_workers", "gauge", "Worker threads running a job");
+        writeln!(out, "hapi_pool_busy_workers {}", pool.get_busy()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_queued_jobs", "gauge", "Jobs waiting for a worker");
+        writeln!(out, "hapi_pool_queued_jobs {}", pool.get_queued()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_jobs_total", "counter", "Jobs run by the worker threads");
+        writeln!(out, "hapi_pool_jobs_total {}", pool.get_jobs()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_panics_total", "counter", "Jobs that panicked");
+        writeln!(out, "hapi_pool_panics_total {}", pool.get_panics()).unwrap_or_default();
+
+        header(&mut out, "hapi_pool_job_wait_seconds", "histogram", "Time jobs waited for a worker");
+        histogram_lines(&mut out, "hapi_pool_job_wait_seconds", "", pool.get_wait_time());
+
+        header(&mut out, "hapi_pool_job_run_seconds", "histogram", "Time jobs ran for");
+        histogram_lines(&mut out, "hapi_pool_job_run_seconds", "", pool.get_run_time());
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap_or_default();
+    writeln!(out, "# TYPE {} {}", name, kind).unwrap_or_default();
+}
+
+/// Cumulative buckets followed by the sum and count
+fn histogram_lines(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    let separator = if labels.is_empty() { "" } else { "," };
+    let counts = histogram.get_counts();
+
+    let mut cumulative = 0;
+    for (bound, count) in BUCKETS.iter().zip(counts.iter()) {
+        cumulative += count;
+        writeln!(out, "{}_bucket{{{}{}le=\"{}\"}} {}", name, labels, separator, bound, cumulative).unwrap_or_default();
+    }
+    writeln!(out, "{}_bucket{{{}{}le=\"+Inf\"}} {}", name, labels, separator, histogram.get_count()).unwrap_or_default();
+
+    let labels = if labels.is_empty() { String::new() } else { format!("{{{}}}", labels) };
+    writeln!(out, "{}_sum{} {}", name, labels, histogram.get_sum().as_secs_f64()).unwrap_or_default();
+    writeln!(out, "{}_count{} {}", name, labels, histogram.get_count()).unwrap_or_default();
+}
+
+/// Label values escape backslashes, quotes and newlines
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{HttpMetrics, escape};
+    use super::super::load::LoadMetrics;
+    use super::super::thread_pool::metrics::{BUCKETS, PoolMetrics};
+    use super::super::super::super::http::{HTTPMethod, HTTPVersion, Header, request::Request};
+    use std::time::Duration;
+
+    fn request(method: HTTPMethod, route: Option<&str>) -> Request {
+        let mut request = Request::new(method, String::from("/items/1"), HTTPVersion::HTTP11, Header::new(), String::new());
+        if let Some(route) = route {
+            request.set_route(String::from(route));
+        }
+        request
+    }
+
+    fn render(metrics: &HttpMetrics) -> String {
+        metrics.render(&PoolMetrics::default(), &LoadMetrics::default())
+    }
+
+    /// The sample lines of `name`, without the HELP and TYPE comments
+    fn samples(out: &str, name: &str) -> Vec<String> {
+        out.lines()
+            .filter(|line| line.starts_with(name) && line[name.len()..].starts_with(['{', ' ', '_']))
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn every_family_has_help_and_type() {
+        let out = render(&HttpMetrics::default());
+        let families = [
+            ("hapi_http_requests_total", "counter"),
+            ("hapi_http_request_duration_seconds", "histogram"),
+            ("hapi_http_requests_in_flight", "gauge"),
+            ("hapi_open_connections", "gauge"),
+            ("hapi_load_rejected_total", "counter"),
+            ("hapi_pool_workers", "gauge"),
+            ("hapi_pool_busy_workers", "gauge"),
+            ("hapi_pool_queued_jobs", "gauge"),
+            ("hapi_pool_jobs_total", "counter"),
+            ("hapi_pool_panics_total", "counter"),
+            ("hapi_pool_job_wait_seconds", "histogram"),
+            ("hapi_pool_job_run_seconds", "histogram")
+        ];
+
+        for (name, kind) in families.iter() {
+            assert!(out.contains(&format!("# HELP {} ", name)), "{} has no HELP", name);
+            assert!(out.contains(&format!("# TYPE {} {}\n", name, kind)), "{} isn't a {}", name, kind);
+        }
+        assert!(out.ends_with('\n'));
+    }
+
+    #[test]
+    fn empty_metrics_render_zeros() {
+        let out = render(&HttpMetrics::default());
+
+        assert!(samples(&out, "hapi_http_requests_total").is_empty());
+        assert!(samples(&out, "hapi_http_request_duration_seconds").is_empty());
+        assert_eq!(samples(&out, "hapi_http_requests_in_flight"), vec!["hapi_http_requests_in_flight 0"]);
+        assert_eq!(samples(&out, "hapi_pool_panics_total"), vec!["hapi_pool_panics_total 0"]);
+
+        let wait = samples(&out, "hapi_pool_job_wait_seconds");
+        assert_eq!(wait.len(), BUCKETS.len() + 3);
+        assert_eq!(wait[0], "hapi_pool_job_wait_seconds_bucket{le=\"0.001\"} 0");
+        assert_eq!(wait[BUCKETS.len()], "hapi_pool_job_wait_seconds_bucket{le=\"+Inf\"} 0");
+        assert_eq!(wait[BUCKETS.len() + 1], "hapi_pool_job_wait_seconds_sum 0");
+        assert_eq!(wait[BUCKETS.len() + 2], "hapi_pool_job_wait_seconds_count 0");
+    }
+
+    #[test]
+    fn requests_are_counted_by_route_method_and_status() {
+        let metrics = HttpMetrics::default();
+        let get = request(HTTPMethod::GET, Some("/items/{id}"));
+
+        metrics.request_finished(Some(&get), 200, None);
+        metrics.request_finished(Some(&get), 200, None);
+        metrics.request_finished(Some(&get), 404, None);
+        metrics.request_finished(Some(&request(HTTPMethod::POST, None)), 404, None);
+        metrics.request_finished(None, 400, None);
+
+        assert_eq!(samples(&render(&metrics), "hapi_http_requests_total"), vec![
+            "hapi_http_requests_total{route=\"/items/{id}\",method=\"GET\",status=\"200\"} 2",
+            "hapi_http_requests_total{route=\"/items/{id}\",method=\"GET\",status=\"404\"} 1",
+            "hapi_http_requests_total{route=\"unmatched\",method=\"POST\",status=\"404\"} 1",
+            "hapi_http_requests_total{route=\"unmatched\",method=\"unknown\",status=\"400\"} 1"
+        ]);
+    }
+
+    #[test]
+    fn in_flight_requests() {
+        let metrics = HttpMetrics::default();
+        let get = request(HTTPMethod::GET, None);
+
+        metrics.request_started();
+        metrics.request_started();
+        assert_eq!(metrics.get_in_flight(), 2);
+
+        metrics.request_finished(Some(&get), 200, None);
+        // Unparsed requests were never started
+        metrics.request_finished(None, 400, None);
+        assert_eq!(metrics.get_in_flight(), 1);
+        assert_eq!(samples(&render(&metrics), "hapi_http_requests_in_flight"), vec!["hapi_http_requests_in_flight 1"]);
+    }
+
+    #[test]
+    fn durations_fill_cumulative_buckets() {
+        let metrics = HttpMetrics::default();
+        let get = request(HTTPMethod::GET, Some("/"));
+
+        // On a bound, between bounds, and past the last one
+        metrics.request_finished(Some(&get), 200, Some(Duration::from_millis(1)));
+        metrics.request_finished(Some(&get), 200, Some(Duration::from_millis(30)));
+        metrics.request_finished(Some(&get), 200, Some(Duration::from_secs(11)));
+        metrics.request_finished(Some(&get), 500, None);
+
+        let lines = samples(&render(&metrics), "hapi_http_request_duration_seconds");
+        let labels = "route=\"/\",method=\"GET\"";
+        assert_eq!(lines.len(), BUCKETS.len() + 3);
+        assert_eq!(lines[0], format!("hapi_http_request_duration_seconds_bucket{{{},le=\"0.001\"}} 1", labels));
+        assert_eq!(lines[3], format!("hapi_http_request_duration_seconds_bucket{{{},le=\"0.025\"}} 1", labels));
+        assert_eq!(lines[4], format!("hapi_http_request_duration_seconds_bucket{{{},le=\"0.05\"}} 2", labels));
+        assert_eq!(lines[BUCKETS.len() - 1], format!("hapi_http_request_duration_seconds_bucket{{{},le=\"10\"}} 2", labels));
+        assert_eq!(lines[BUCKETS.len()], format!("hapi_http_request_duration_seconds_bucket{{{},le=\"+Inf\"}} 3", labels));
+        assert_eq!(lines[BUCKETS.len() + 1], format!("hapi_http_request_duration_seconds_sum{{{}}} 11.031", labels));
+        assert_eq!(lines[BUCKETS.len() + 2], format!("hapi_http_request_duration_seconds_count{{{}}} 3", labels));
+    }
+
+    #[test]
+    fn label_values_are_escaped() {
+        assert_eq!(escape("plain"), "plain");
+        assert_eq!(escape("a\\b\"c\nd"), "a\\\\b\\\"c\\nd");
+
+        let metrics = HttpMetrics::default();
+        metrics.request_finished(Some(&request(HTTPMethod::GET, Some("/\"quoted\"\n"))), 200, None);
+        assert_eq!(samples(&render(&metrics), "hapi_http_requests_total"), vec![
+            "hapi_http_requests_total{route=\"/\\\"quoted\\\"\\n\",method=\"GET\",status=\"200\"} 1"
+        ]);
+    }
+}
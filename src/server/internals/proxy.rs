@@ -0,0 +1,412 @@
+use super::super::super::http::Header;
+use super::super::super::http::request::info::Client;
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A block of addresses such as `10.0.0.0/8` or `2001:db8::/32`, a bare address is a block of one
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Cidr {
+    address: IpAddr,
+    prefix: u8
+}
+
+impl Cidr {
+    /// Prefixes longer than the address are cut down to it, parsing rejects them instead
+    pub fn new(address: IpAddr, prefix: u8) -> Self {
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        };
+
+        Self {
+            address,
+            prefix: prefix.min(max)
+        }
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 clients of a dual stack socket show up as `::ffff:a.b.c.d`
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(ip),
+            IpAddr::V4(_) => ip
+        };
+
+        match (self.address, ip) {
+            (IpAddr::V4(block), IpAddr::V4(ip)) => matches_prefix(u32::from(block) as u128, u32::from(ip) as u128, 32, self.prefix),
+            (IpAddr::V6(block), IpAddr::V6(ip)) => matches_prefix(u128::from(block), u128::from(ip), 128, self.prefix),
+            _ => false
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let (address, prefix) = match value.split_once('/') {
+            None => (value, None),
+            Some((address, prefix)) => (address, Some(prefix))
+        };
+
+        let address: IpAddr = address.trim().parse().map_err(|_| format!("Invalid address in {}", value))?;
+        let max = match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        };
+        let prefix = match prefix {
+            None => max,
+            Some(prefix) => match prefix.trim().parse::<u8>() {
+                Ok(prefix) if prefix <= max => prefix,
+                _ => return Err(format!("Invalid prefix length in {}", value))
+            }
+        };
+
+        Ok(Self::new(address, prefix))
+    }
+}
+
+fn matches_prefix(block: u128, ip: u128, bits: u8, prefix: u8) -> bool {
+    match prefix {
+        0 => true,
+        _ => (block ^ ip) >> (bits - prefix) == 0
+    }
+}
+
+/// Proxies whose `Forwarded` and `X-Forwarded-*` headers are believed
+#[derive(Debug, Clone, Default)]
+pub struct TrustedProxies {
+    proxies: Vec<Cidr>
+}
+
+impl TrustedProxies {
+    pub fn new(proxies: Vec<Cidr>) -> Self {
+        Self {
+            proxies
+        }
+    }
+
+    pub fn is_trusted(&self, ip: IpAddr) -> bool {
+        self.proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /// Walk the forwarded hops back from the peer for as long as they come from trusted proxies
+    /// `Forwarded` is preferred over the `X-Forwarded-*` headers when both are present
+    pub fn resolve(&self, peer: Option<SocketAddr>, headers: &Header, secure: bool) -> Client {
+        let mut client = Client::new(peer, secure, headers.find("Host").cloned());
+
+        let mut hops = match headers.find("Forwarded") {
+            Some(forwarded) => parse_forwarded(forwarded),
+            None => parse_x_forwarded(headers)
+        };
+
+        while let Some(hop) = hops.pop() {
+            match client.get_address() {
+                Some(address) if self.is_trusted(address) => (),
+                _ => break
+            }
+
+            client.forwarded(hop.address, hop.port, hop.proto, hop.host);
+        }
+
+        client
+    }
+}
+
+/// One proxy's account of the request it received
+#[derive(Debug, Default)]
+struct Hop {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    proto: Option<String>,
+    host: Option<String>
+}
+
+/// `Forwarded: for=192.0.2.60;proto=https;host=example.com, for="[2001:db8::1]:4711"`
+fn parse_forwarded(value: &str) -> Vec<Hop> {
+    split_unquoted(value, ',').iter().map(|element| {
+        let mut hop = Hop::default();
+        for pair in split_unquoted(element, ';') {
+            let (key, value) = match pair.split_once('=') {
+                None => continue,
+                Some((key, value)) => (key.trim().to_ascii_lowercase(), unquote(value.trim()))
+            };
+
+            match key.as_str() {
+                "for" => {
+                    let (address, port) = parse_node(&value);
+                    hop.address = address;
+                    hop.port = port;
+                },
+                "proto" => hop.proto = Some(value),
+                "host" => hop.host = Some(value),
+                _ => ()
+            }
+        }
+        hop
+    }).collect()
+}
+
+/// `X-Forwarded-For` lists every hop, `X-Forwarded-Proto` and `X-Forwarded-Host` line up with its last entries
+fn parse_x_forwarded(headers: &Header) -> Vec<Hop> {
+    let list = |name: &str| -> Vec<String> {
+        headers.find(name)
+            .map(|value| value.split(',').map(|item| String::from(item.trim())).filter(|item| !item.is_empty()).collect())
+            .unwrap_or_default()
+    };
+
+    let (protos, hosts) = (list("X-Forwarded-Proto"), list("X-Forwarded-Host"));
+    let mut hops: Vec<Hop> = list("X-Forwarded-For").iter().map(|node| {
+        let (address, port) = parse_node(node);
+        Hop {
+            address,
+            port,
+            ..Hop::default()
+        }
+    }).collect();
+
+    for (hop, proto) in hops.iter_mut().rev().zip(protos.into_iter().rev()) {
+        hop.proto = Some(proto);
+    }
+    for (hop, host) in hops.iter_mut().rev().zip(hosts.into_iter().rev()) {
+        hop.host = Some(host);
+    }
+    hops
+}
+
+/// `192.0.2.43`, `192.0.2.43:47011`, `2001:db8::1` or `[2001:db8::1]:4711`
+fn parse_node(node: &str) -> (Option<IpAddr>, Option<u16>) {
+    if let Ok(address) = node.parse::<SocketAddr>() {
+        return (Some(address.ip()), Some(address.port()));
+    }
+
+    let address = node.trim_start_matches('[').trim_end_matches(']');
+    (address.parse().ok(), None)
+}
+
+/// Split on `separator` except within quoted strings
+fn split_unquoted(value: &str, separator: char) -> Vec<String> {
+    let mut parts = vec![];
+    let (mut current, mut quoted) = (String::new(), false);
+
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            },
+            c if c == separator && !quoted => parts.push(std::mem::take(&mut current)),
+            c => current.push(c)
+        }
+    }
+    parts.push(current);
+
+    parts.into_iter().map(|part| String::from(part.trim())).filter(|part| !part.is_empty()).collect()
+}
+
+fn unquote(value: &str) -> String {
+    match value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+        true => value[1..value.len() - 1].replace("\\\"", "\""),
+        false => String::from(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Cidr, TrustedProxies, parse_node, split_unquoted, unquote};
+    use super::super::super::super::http::Header;
+    use std::net::{IpAddr, SocketAddr};
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    fn cidr(value: &str) -> Cidr {
+        value.parse().unwrap()
+    }
+
+    fn proxies(blocks: &[&str]) -> TrustedProxies {
+        TrustedProxies::new(blocks.iter().map(|block| cidr(block)).collect())
+    }
+
+    fn headers(list: &[(&str, &str)]) -> Header {
+        let mut headers = Header::new();
+        for &(name, value) in list {
+            headers.insert(String::from(name), String::from(value));
+        }
+        headers
+    }
+
+    fn peer(value: &str) -> Option<SocketAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn cidrs_parse() {
+        assert_eq!(cidr("10.0.0.0/8"), Cidr::new(ip("10.0.0.0"), 8));
+        assert_eq!(cidr(" 192.0.2.1 "), Cidr::new(ip("192.0.2.1"), 32));
+        assert_eq!(cidr("2001:db8::/32"), Cidr::new(ip("2001:db8::"), 32));
+        assert_eq!(cidr("::1"), Cidr::new(ip("::1"), 128));
+        assert_eq!(cidr("0.0.0.0/0"), Cidr::new(ip("0.0.0.0"), 0));
+        assert_eq!(cidr("10.0.0.0/32"), Cidr::new(ip("10.0.0.0"), 32));
+        assert_eq!(cidr("::/128"), Cidr::new(ip("::"), 128));
+    }
+
+    #[test]
+    fn invalid_cidrs_are_rejected() {
+        for value in ["", "/8", "example.com/8", "10.0.0/8", "10.0.0.0/", "10.0.0.0/x", "10.0.0.0/-1", "10.0.0.0/33", "::/129", "10.0.0.0/256"].iter() {
+            assert!(value.parse::<Cidr>().is_err(), "{} was accepted", value);
+        }
+    }
+
+    #[test]
+    fn long_prefixes_are_cut_down() {
+        assert_eq!(Cidr::new(ip("10.0.0.1"), 40), Cidr::new(ip("10.0.0.1"), 32));
+        assert_eq!(Cidr::new(ip("::1"), 200), Cidr::new(ip("::1"), 128));
+    }
+
+    #[test]
+    fn cidrs_contain_their_block() {
+        let block = cidr("10.0.0.0/8");
+        assert!(block.contains(ip("10.0.0.0")));
+        assert!(block.contains(ip("10.255.255.255")));
+        assert!(!block.contains(ip("11.0.0.0")));
+        assert!(!block.contains(ip("9.255.255.255")));
+
+        assert!(cidr("192.0.2.1").contains(ip("192.0.2.1")));
+        assert!(!cidr("192.0.2.1").contains(ip("192.0.2.2")));
+
+        assert!(cidr("2001:db8::/32").contains(ip("2001:db8:ffff::1")));
+        assert!(!cidr("2001:db8::/32").contains(ip("2001:db9::1")));
+    }
+
+    #[test]
+    fn zero_prefixes_contain_their_whole_family() {
+        assert!(cidr("0.0.0.0/0").contains(ip("255.255.255.255")));
+        assert!(cidr("::/0").contains(ip("ffff::1")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+        assert!(!cidr("::/0").contains(ip("10.0.0.1")));
+    }
+
+    #[test]
+    fn mapped_ipv4_addresses_match_ipv4_blocks() {
+        assert!(cidr("10.0.0.0/8").contains(ip("::ffff:10.1.2.3")));
+        assert!(!cidr("10.0.0.0/8").contains(ip("::ffff:11.1.2.3")));
+    }
+
+    #[test]
+    fn untrusted_peers_are_the_client() {
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("192.0.2.1:4000"), &headers(&[
+            ("Host", "example.com"),
+            ("X-Forwarded-For", "203.0.113.7"),
+            ("X-Forwarded-Proto", "https")
+        ]), false);
+
+        assert_eq!(client.get_address(), Some(ip("192.0.2.1")));
+        assert_eq!(client.get_port(), Some(4000));
+        assert_eq!(client.get_scheme(), "http");
+        assert_eq!(client.get_host(), Some(String::from("example.com")));
+    }
+
+    #[test]
+    fn no_peer_trusts_nothing() {
+        let client = proxies(&["0.0.0.0/0"]).resolve(None, &headers(&[("X-Forwarded-For", "203.0.113.7")]), true);
+        assert_eq!(client.get_address(), None);
+        assert_eq!(client.get_scheme(), "https");
+    }
+
+    #[test]
+    fn x_forwarded_headers_from_a_trusted_proxy() {
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("10.0.0.2:4000"), &headers(&[
+            ("Host", "internal"),
+            ("X-Forwarded-For", "203.0.113.7:5000"),
+            ("X-Forwarded-Proto", "HTTPS"),
+            ("X-Forwarded-Host", "example.com")
+        ]), false);
+
+        assert_eq!(client.get_address(), Some(ip("203.0.113.7")));
+        assert_eq!(client.get_port(), Some(5000));
+        assert_eq!(client.get_scheme(), "https");
+        assert_eq!(client.get_host(), Some(String::from("example.com")));
+    }
+
+    #[test]
+    fn the_walk_stops_at_the_first_untrusted_hop() {
+        let headers = headers(&[("X-Forwarded-For", "203.0.113.7, 198.51.100.9, 10.0.0.3")]);
+
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("10.0.0.2:4000"), &headers, false);
+        assert_eq!(client.get_address(), Some(ip("198.51.100.9")));
+        assert_eq!(client.get_port(), None);
+
+        // A spoofed leftmost entry is only believed when every hop after it is trusted
+        let client = proxies(&["10.0.0.0/8", "198.51.100.0/24"]).resolve(peer("10.0.0.2:4000"), &headers, false);
+        assert_eq!(client.get_address(), Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn x_forwarded_proto_and_host_line_up_with_the_last_hops() {
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("10.0.0.2:4000"), &headers(&[
+            ("X-Forwarded-For", "203.0.113.7, 10.0.0.3"),
+            ("X-Forwarded-Proto", "http"),
+            ("X-Forwarded-Host", "a.example, b.example")
+        ]), true);
+
+        assert_eq!(client.get_address(), Some(ip("203.0.113.7")));
+        // Only the last hop reported a proto, the scheme it set stays
+        assert_eq!(client.get_scheme(), "http");
+        assert_eq!(client.get_host(), Some(String::from("a.example")));
+    }
+
+    #[test]
+    fn forwarded_is_preferred_and_parsed() {
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("10.0.0.2:4000"), &headers(&[
+            ("Forwarded", "for=\"[2001:db8::1]:4711\";proto=https;host=\"example.com\", For=10.0.0.3"),
+            ("X-Forwarded-For", "203.0.113.7")
+        ]), false);
+
+        assert_eq!(client.get_address(), Some(ip("2001:db8::1")));
+        assert_eq!(client.get_port(), Some(4711));
+        assert_eq!(client.get_scheme(), "https");
+        assert_eq!(client.get_host(), Some(String::from("example.com")));
+    }
+
+    #[test]
+    fn obfuscated_hops_end_the_walk() {
+        let client = proxies(&["0.0.0.0/0"]).resolve(peer("10.0.0.2:4000"), &headers(&[
+            ("Forwarded", "for=192.0.2.60, for=unknown;proto=https, for=10.0.0.3")
+        ]), false);
+
+        assert_eq!(client.get_address(), None);
+        assert_eq!(client.get_port(), None);
+        assert_eq!(client.get_scheme(), "https");
+    }
+
+    #[test]
+    fn malformed_forwarded_elements_are_skipped() {
+        let client = proxies(&["10.0.0.0/8"]).resolve(peer("10.0.0.2:4000"), &headers(&[
+            ("Forwarded", "garbage;for=192.0.2.60;;by, ,")
+        ]), false);
+
+        assert_eq!(client.get_address(), Some(ip("192.0.2.60")));
+        assert_eq!(client.get_scheme(), "http");
+    }
+
+    #[test]
+    fn nodes() {
+        assert_eq!(parse_node("192.0.2.43"), (Some(ip("192.0.2.43")), None));
+        assert_eq!(parse_node("192.0.2.43:47011"), (Some(ip("192.0.2.43")), Some(47011)));
+        assert_eq!(parse_node("2001:db8::1"), (Some(ip("2001:db8::1")), None));
+        assert_eq!(parse_node("[2001:db8::1]"), (Some(ip("2001:db8::1")), None));
+        assert_eq!(parse_node("[2001:db8::1]:4711"), (Some(ip("2001:db8::1")), Some(4711)));
+        assert_eq!(parse_node("unknown"), (None, None));
+        assert_eq!(parse_node("_hidden"), (None, None));
+        assert_eq!(parse_node("192.0.2.43:99999"), (None, None));
+    }
+
+    #[test]
+    fn quoted_strings() {
+        assert_eq!(split_unquoted("a; \"b;c\" ;; d", ';'), vec!["a", "\"b;c\"", "d"]);
+        assert_eq!(unquote("\"a\\\"b\""), "a\"b");
+        assert_eq!(unquote("\""), "\"");
+        assert_eq!(unquote("plain"), "plain");
+    }
+}
@@ -0,0 +1,327 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+// A v1 header is at most 107 bytes including its CRLF
+const V1_MAX_LENGTH: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Whether connections start with an HAProxy PROXY protocol header
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum ProxyProtocol {
+    #[default]
+    Off,
+    /// Use a v1 or v2 header when a connection starts with one
+    Accept,
+    /// Close connections that don't start with a v1 or v2 header
+    Require
+}
+
+/// The connection as the proxy in front of the server received it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct ProxyHeader {
+    source: Option<SocketAddr>,
+    destination: Option<SocketAddr>
+}
+
+impl ProxyHeader {
+    /// The client that connected to the proxy, `None` for health checks and unknown protocols
+    pub fn get_source(&self) -> Option<SocketAddr> {
+        self.source
+    }
+
+    /// The address the client connected to
+    pub fn get_destination(&self) -> Option<SocketAddr> {
+        self.destination
+    }
+}
+
+/// How far the start of a connection is from holding a PROXY header
+#[derive(Debug)]
+pub enum ProxyParse {
+    /// Read more, the bytes so far could still be a header
+    Incomplete,
+    /// The connection starts with something else
+    Missing,
+    Invalid,
+    /// The header and how many bytes it took
+    Done(ProxyHeader, usize)
+}
+
+pub fn parse(buffer: &[u8]) -> ProxyParse {
+    if buffer.starts_with(V2_SIGNATURE) {
+        return parse_v2(buffer);
+    }
+    if buffer.starts_with(V1_PREFIX) {
+        return parse_v1(buffer);
+    }
+
+    match V2_SIGNATURE.starts_with(buffer) || V1_PREFIX.starts_with(buffer) {
+        true => ProxyParse::Incomplete,
+        false => ProxyParse::Missing
+    }
+}
+
+/// `PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\n` or `PROXY UNKNOWN\r\n`
+fn parse_v1(buffer: &[u8]) -> ProxyParse {
+    let end = match buffer.windows(2).position(|window| window == b"\r\n") {
+        Some(end) if end + 2 <= V1_MAX_LENGTH => end,
+        Some(_) => return ProxyParse::Invalid,
+        None if buffer.len() < V1_MAX_LENGTH => return ProxyParse::Incomplete,
+        None => return ProxyParse::Invalid
+    };
+
+    let line = match std::str::from_utf8(&buffer[..end]) {
+        Ok(line) => line,
+        Err(_) => return ProxyParse::Invalid
+    };
+
+    let fields: Vec<&str> = line.split(' ').collect();
+    let header = match fields.get(1).copied() {
+        Some("UNKNOWN") => ProxyHeader { source: None, destination: None },
+        Some("TCP4") | Some("TCP6") if fields.len() == 6 => {
+            let address = |ip: &str, port: &str| -> Option<SocketAddr> {
+                Some(SocketAddr::new(ip.parse::<IpAddr>().ok()?, port.parse().ok()?))
+            };
+            match (address(fields[2], fields[4]), address(fields[3], fields[5])) {
+                (Some(source), Some(destination)) if source.is_ipv4() == (fields[1] == "TCP4") && destination.is_ipv4() == source.is_ipv4() => ProxyHeader {
+                    source: Some(source),
+                    destination: Some(destination)
+                },
+                _ => return ProxyParse::Invalid
+            }
+        },
+        _ => return ProxyParse::Invalid
+    };
+
+    ProxyParse::Done(header, end + 2)
+}
+
+/// The signature, version and command, address family and protocol, address length and the addresses
+fn parse_v2(buffer: &[u8]) -> ProxyParse {
+    if buffer.len() < 16 {
+        return ProxyParse::Incomplete;
+    }
+
+    let (version, command, family) = (buffer[12] >> 4, buffer[12] & 0x0f, buffer[13] >> 4);
+    let length = 16 + u16::from_be_bytes([buffer[14], buffer[15]]) as usize;
+    if version != 2 || command > 1 {
+        return ProxyParse::Invalid;
+    }
+    if buffer.len() < length {
+        return ProxyParse::Incomplete;
+    }
+
+    let addresses = &buffer[16..length];
+    let (source, destination) = match (command, family) {
+        // LOCAL connections come from the proxy itself, such as health checks
+        (0, _) => (None, None),
+        // IPv4
+        (1, 1) if addresses.len() >= 12 => {
+            let ip = |at: usize| IpAddr::V4(Ipv4Addr::new(addresses[at], addresses[at + 1], addresses[at + 2], addresses[at + 3]));
+            (
+                Some(SocketAddr::new(ip(0), port(addresses, 8))),
+                Some(SocketAddr::new(ip(4), port(addresses, 10)))
+            )
+        },
+        // IPv6
+        (1, 2) if addresses.len() >= 36 => {
+            let ip = |at: usize| {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(&addresses[at..at + 16]);
+                IpAddr::V6(Ipv6Addr::from(octets))
+            };
+            (
+                Some(SocketAddr::new(ip(0), port(addresses, 32))),
+                Some(SocketAddr::new(ip(16), port(addresses, 34)))
+            )
+        },
+        (1, 1) | (1, 2) => return ProxyParse::Invalid,
+        // Unspecified and unix socket addresses say nothing useful about the client
+        _ => (None, None)
+    };
+
+    ProxyParse::Done(ProxyHeader { source, destination }, length)
+}
+
+fn port(addresses: &[u8], at: usize) -> u16 {
+    u16::from_be_bytes([addresses[at], addresses[at + 1]])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProxyHeader, ProxyParse, V2_SIGNATURE, parse};
+    use std::net::SocketAddr;
+
+    fn address(value: &str) -> Option<SocketAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    fn done(buffer: &[u8]) -> (ProxyHeader, usize) {
+        match parse(buffer) {
+            ProxyParse::Done(header, length) => (header, length),
+            other => panic!("{:?} for {:?}", other, String::from_utf8_lossy(buffer))
+        }
+    }
+
+    fn is_incomplete(buffer: &[u8]) -> bool {
+        matches!(parse(buffer), ProxyParse::Incomplete)
+    }
+
+    fn is_invalid(buffer: &[u8]) -> bool {
+        matches!(parse(buffer), ProxyParse::Invalid)
+    }
+
+    /// A v2 header with the version and command byte, the family and protocol byte and the address block
+    fn v2(version_command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend([version_command, family]);
+        header.extend((addresses.len() as u16).to_be_bytes());
+        header.extend(addresses);
+        header
+    }
+
+    #[test]
+    fn other_protocols_are_missing_a_header() {
+        assert!(matches!(parse(b"GET / HTTP/1.1\r\n"), ProxyParse::Missing));
+        assert!(matches!(parse(b"PROXY"), ProxyParse::Incomplete));
+        assert!(matches!(parse(b"PROXYTCP4"), ProxyParse::Missing));
+        assert!(matches!(parse(b"\r\n\r\nGET"), ProxyParse::Missing));
+    }
+
+    #[test]
+    fn partial_prefixes_are_incomplete() {
+        assert!(is_incomplete(b""));
+        assert!(is_incomplete(b"PRO"));
+        assert!(is_incomplete(b"\r\n\r\n\0"));
+        assert!(is_incomplete(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443"));
+        assert!(is_incomplete(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r"));
+    }
+
+    #[test]
+    fn v1_tcp4() {
+        let (header, length) = done(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET / HTTP/1.1\r\n");
+        assert_eq!(header.get_source(), address("192.0.2.1:56324"));
+        assert_eq!(header.get_destination(), address("198.51.100.1:443"));
+        assert_eq!(length, 45);
+    }
+
+    #[test]
+    fn v1_tcp6() {
+        let (header, length) = done(b"PROXY TCP6 2001:db8::1 2001:db8::2 4711 443\r\n");
+        assert_eq!(header.get_source(), address("[2001:db8::1]:4711"));
+        assert_eq!(header.get_destination(), address("[2001:db8::2]:443"));
+        assert_eq!(length, 45);
+    }
+
+    #[test]
+    fn v1_unknown() {
+        let (header, length) = done(b"PROXY UNKNOWN\r\n");
+        assert_eq!((header.get_source(), header.get_destination()), (None, None));
+        assert_eq!(length, 15);
+
+        let (header, _) = done(b"PROXY UNKNOWN ffff::1 ffff::2 1 2\r\n");
+        assert_eq!(header.get_source(), None);
+    }
+
+    #[test]
+    fn malformed_v1_headers_are_invalid() {
+        let invalid: [&[u8]; 12] = [
+            b"PROXY \r\n",
+            b"PROXY UDP4 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443 extra\r\n",
+            b"PROXY TCP4  192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.256 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 65536 443\r\n",
+            b"PROXY TCP4 192.0.2.1 198.51.100.1 -1 443\r\n",
+            b"PROXY TCP4 2001:db8::1 2001:db8::2 4711 443\r\n",
+            b"PROXY TCP6 192.0.2.1 198.51.100.1 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 2001:db8::2 56324 443\r\n",
+            b"PROXY TCP4 192.0.2.1 \xff 56324 443\r\n"
+        ];
+        for buffer in invalid.iter() {
+            assert!(is_invalid(buffer), "{:?} was accepted", String::from_utf8_lossy(buffer));
+        }
+    }
+
+    #[test]
+    fn v1_headers_are_at_most_107_bytes() {
+        let mut line = b"PROXY UNKNOWN ".to_vec();
+        line.resize(105, b'x');
+        assert!(is_incomplete(&line));
+        assert!(matches!(parse(&[line.as_slice(), b"\r\n"].concat()), ProxyParse::Done(_, 107)));
+
+        line.push(b'x');
+        assert!(is_incomplete(&line));
+        assert!(is_invalid(&[line.as_slice(), b"\r\n"].concat()));
+
+        line.push(b'x');
+        assert!(is_invalid(&line));
+    }
+
+    #[test]
+    fn v2_ipv4() {
+        let mut buffer = v2(0x21, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0x01, 0xbb]);
+        buffer.extend(b"GET");
+
+        let (header, length) = done(&buffer);
+        assert_eq!(header.get_source(), address("192.0.2.1:56324"));
+        assert_eq!(header.get_destination(), address("198.51.100.1:443"));
+        assert_eq!(length, 28);
+    }
+
+    #[test]
+    fn v2_ipv6() {
+        let mut addresses = vec![0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+        addresses.extend([0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2]);
+        addresses.extend([0x12, 0x67, 0x01, 0xbb]);
+
+        let (header, length) = done(&v2(0x21, 0x21, &addresses));
+        assert_eq!(header.get_source(), address("[2001:db8::1]:4711"));
+        assert_eq!(header.get_destination(), address("[2001:db8::2]:443"));
+        assert_eq!(length, 52);
+    }
+
+    #[test]
+    fn v2_skips_tlvs() {
+        let (header, length) = done(&v2(0x21, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 80, 0, 81, 0x04, 0x00, 0x01, 0xff]));
+        assert_eq!(header.get_source(), address("192.0.2.1:80"));
+        assert_eq!(length, 32);
+    }
+
+    #[test]
+    fn v2_local_and_unspecified_have_no_addresses() {
+        let (header, length) = done(&v2(0x20, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 80, 0, 81]));
+        assert_eq!((header.get_source(), header.get_destination()), (None, None));
+        assert_eq!(length, 28);
+
+        let (header, length) = done(&v2(0x21, 0x00, &[]));
+        assert_eq!(header.get_source(), None);
+        assert_eq!(length, 16);
+
+        let (header, length) = done(&v2(0x21, 0x31, &[0; 216]));
+        assert_eq!(header.get_source(), None);
+        assert_eq!(length, 232);
+    }
+
+    #[test]
+    fn truncated_v2_headers_are_incomplete() {
+        let buffer = v2(0x21, 0x11, &[192, 0, 2, 1, 198, 51, 100, 1, 0, 80, 0, 81]);
+        for length in 0..buffer.len() {
+            assert!(is_incomplete(&buffer[..length]), "{} bytes were not incomplete", length);
+        }
+    }
+
+    #[test]
+    fn malformed_v2_headers_are_invalid() {
+        // Version 1 and 3, commands past PROXY
+        assert!(is_invalid(&v2(0x11, 0x11, &[0; 12])));
+        assert!(is_invalid(&v2(0x31, 0x11, &[0; 12])));
+        assert!(is_invalid(&v2(0x22, 0x11, &[0; 12])));
+        // Address blocks too short for their family
+        assert!(is_invalid(&v2(0x21, 0x11, &[0; 11])));
+        assert!(is_invalid(&v2(0x21, 0x21, &[0; 35])));
+        // Already invalid before the addresses arrive
+        assert!(is_invalid(&v2(0x11, 0x11, &[0; 12])[..16]));
+    }
+}
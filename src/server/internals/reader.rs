@@ -0,0 +1,371 @@
+use super::connection::Connection;
+use super::options::ServerOptions;
+use super::proxy_protocol::{self, ProxyHeader, ProxyParse, ProxyProtocol};
+use std::io::{self, Read};
+use std::time::{Duration, Instant};
+
+// Headers larger than this are answered with a 431
+const MAX_HEADER_SIZE: usize = 64 * 1024;
+
+/// Why a request couldn't be read off the connection
+#[derive(Debug)]
+pub enum ReadError {
+    /// The client connected but never started sending
+    Idle,
+    /// The client closed the connection before sending a request
+    Closed,
+    HeaderTimeout,
+    BodyTimeout,
+    HeaderTooLarge,
+    /// The `Content-Length` is over the payload limit, the body was left unread
+    PayloadTooLarge,
+    /// The connection didn't start with the PROXY protocol header it had to or started with a broken one
+    InvalidProxyHeader,
+    Io(io::Error)
+}
+
+/// Bounds on how long a client may take to send a request, how large its body may be, and what comes before it
+#[derive(Debug, Copy, Clone, Default)]
+pub struct ReadTimeouts {
+    idle: Option<Duration>,
+    header: Option<Duration>,
+    body: Option<Duration>,
+    socket: Option<Duration>,
+    max_payload: usize,
+    proxy_protocol: ProxyProtocol
+}
+
+impl ReadTimeouts {
+    pub fn new(options: &ServerOptions) -> Self {
+        Self {
+            idle: options.get_idle_timeout(),
+            header: options.get_header_read_timeout(),
+            body: options.get_body_read_timeout(),
+            socket: options.get_socket_timeout(),
+            max_payload: options.get_max_payload_size(),
+            proxy_protocol: options.get_proxy_protocol()
+        }
+    }
+
+    pub fn get_idle(&self) -> Option<Duration> {
+        self.idle
+    }
+
+    pub fn get_header(&self) -> Option<Duration> {
+        self.header
+    }
+
+    pub fn get_socket(&self) -> Option<Duration> {
+        self.socket
+    }
+
+    /// Read the PROXY protocol header off the raw connection, before any TLS
+    /// Whatever follows it is left on the connection for the request
+    pub fn read_proxy_header(&self, stream: &mut Connection) -> Result<Option<ProxyHeader>, ReadError> {
+        if self.proxy_protocol == ProxyProtocol::Off {
+            return Ok(None);
+        }
+
+        let mut buffer = vec![];
+        let mut chunk = [0; 4096];
+        self.read_first(stream, &mut buffer, &mut chunk)?;
+
+        // The PROXY header counts towards the header timeout
+        let header_deadline = self.header.map(|timeout| Instant::now() + timeout);
+        loop {
+            match proxy_protocol::parse(&buffer) {
+                ProxyParse::Done(header, length) => {
+                    stream.unread(&buffer[length..]);
+                    return Ok(Some(header));
+                },
+                ProxyParse::Missing if self.proxy_protocol == ProxyProtocol::Accept => {
+                    stream.unread(&buffer);
+                    return Ok(None);
+                },
+                ProxyParse::Missing | ProxyParse::Invalid => return Err(ReadError::InvalidProxyHeader),
+                ProxyParse::Incomplete => match read_before(stream, &mut chunk, header_deadline, self.socket) {
+                    Ok(0) => return Err(ReadError::Closed),
+                    Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+                    Err(ref e) if is_timeout(e) => return Err(ReadError::HeaderTimeout),
+                    Err(e) => return Err(ReadError::Io(e))
+                }
+            }
+        }
+    }
+
+    /// Read the request head and its `Content-Length` body
+    pub fn read_request(&self, stream: &mut Connection) -> Result<Vec<u8>, ReadError> {
+        stream.set_write_timeout(self.socket).map_err(ReadError::Io)?;
+
+        let mut buffer = vec![];
+        let mut chunk = [0; 4096];
+        self.read_first(stream, &mut buffer, &mut chunk)?;
+
+        // Read until the end of the headers
+        let header_deadline = self.header.map(|timeout| Instant::now() + timeout);
+        let header_end = loop {
+            if let Some(end) = find_header_end(&buffer) {
+                break end;
+            }
+            if buffer.len() > MAX_HEADER_SIZE {
+                return Err(ReadError::HeaderTooLarge);
+            }
+
+            match read_before(stream, &mut chunk, header_deadline, self.socket) {
+                Ok(0) => return Err(ReadError::Closed),
+                Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+                Err(ref e) if is_timeout(e) => return Err(ReadError::HeaderTimeout),
+                Err(e) => return Err(ReadError::Io(e))
+            }
+        };
+
+        // Read the rest of the body
+        let body_length = content_length(&buffer[..header_end]);
+        let request_end = match header_end.checked_add(body_length) {
+            Some(end) if body_length <= self.max_payload => end,
+            _ => return Err(ReadError::PayloadTooLarge)
+        };
+        let body_deadline = self.body.map(|timeout| Instant::now() + timeout);
+        while buffer.len() < request_end {
+            match read_before(stream, &mut chunk, body_deadline, self.socket) {
+                Ok(0) => break,
+                Ok(size) => buffer.extend_from_slice(&chunk[..size]),
+                Err(ref e) if is_timeout(e) => return Err(ReadError::BodyTimeout),
+                Err(e) => return Err(ReadError::Io(e))
+            }
+        }
+
+        Ok(buffer)
+    }
+
+    /// Wait for the first bytes
+    fn read_first(&self, stream: &mut Connection, buffer: &mut Vec<u8>, chunk: &mut [u8]) -> Result<(), ReadError> {
+        stream.set_read_timeout(shortest(self.idle, self.socket)).map_err(ReadError::Io)?;
+        match stream.read(chunk) {
+            Ok(0) => Err(ReadError::Closed),
+            Ok(size) => {
+                buffer.extend_from_slice(&chunk[..size]);
+                Ok(())
+            },
+            Err(ref e) if is_timeout(e) => Err(ReadError::Idle),
+            // Clients hanging up during the TLS handshake
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => Err(ReadError::Closed),
+            Err(e) => Err(ReadError::Io(e))
+        }
+    }
+}
+
+/// Read once, giving up when the deadline or the socket timeout passes
+fn read_before(stream: &mut Connection, chunk: &mut [u8], deadline: Option<Instant>, socket: Option<Duration>) -> io::Result<usize> {
+    let remaining = match deadline {
+        None => None,
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(remaining) if remaining > Duration::from_millis(0) => Some(remaining),
+            _ => return Err(io::Error::new(io::ErrorKind::TimedOut, "read deadline passed"))
+        }
+    };
+
+    stream.set_read_timeout(shortest(remaining, socket))?;
+    stream.read(chunk)
+}
+
+fn shortest(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, None) => a,
+        (None, b) => b
+    }
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    e.kind() == io::ErrorKind::WouldBlock || e.kind() == io::ErrorKind::TimedOut
+}
+
+/// Bytes of the buffer taken by the request it starts with, anything after it was sent ahead of the response
+pub fn request_length(buffer: &[u8]) -> usize {
+    match find_header_end(buffer) {
+        None => buffer.len(),
+        Some(header_end) => match header_end.checked_add(content_length(&buffer[..header_end])) {
+            None => buffer.len(),
+            Some(end) => end.min(buffer.len())
+        }
+    }
+}
+
+/// Index just past the blank line ending the headers
+fn find_header_end(buffer: &[u8]) -> Option<usize> {
+    buffer.windows(4).position(|window| window == b"\r\n\r\n").map(|i| i + 4)
+}
+
+fn content_length(head: &[u8]) -> usize {
+    String::from_utf8_lossy(head).split("\r\n")
+        .filter_map(|line| {
+            let mut split_line = line.splitn(2, ':');
+            match (split_line.next(), split_line.next()) {
+                (Some(key), Some(value)) if key.trim().eq_ignore_ascii_case("Content-Length") => parse_length(value.trim()),
+                _ => None
+            }
+        })
+        .next()
+        .unwrap_or(0)
+}
+
+/// Lengths too large for a `usize` saturate so they are still over any limit
+fn parse_length(value: &str) -> Option<usize> {
+    match value.parse() {
+        Ok(length) => Some(length),
+        Err(_) if !value.is_empty() && value.bytes().all(|b| b.is_ascii_digit()) => Some(usize::MAX),
+        Err(_) => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_length, parse_length, request_length, ReadError, ReadTimeouts};
+    use super::super::connection::Connection;
+    use std::io::Write;
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    fn timeouts() -> ReadTimeouts {
+        ReadTimeouts {
+            idle: Some(Duration::from_secs(5)),
+            header: Some(Duration::from_secs(5)),
+            body: Some(Duration::from_secs(5)),
+            socket: None,
+            max_payload: 1024,
+            ..ReadTimeouts::default()
+        }
+    }
+
+    // The client end and the server end of a loopback connection
+    fn pair() -> (TcpStream, Connection) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        (client, Connection::new(server))
+    }
+
+    #[test]
+    fn lengths_parse_and_saturate() {
+        assert_eq!(parse_length("0"), Some(0));
+        assert_eq!(parse_length("42"), Some(42));
+        assert_eq!(parse_length("99999999999999999999999999"), Some(usize::MAX));
+        assert_eq!(parse_length(""), None);
+        assert_eq!(parse_length("-1"), None);
+        assert_eq!(parse_length("12abc"), None);
+    }
+
+    #[test]
+    fn content_length_is_case_insensitive_and_defaults_to_zero() {
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\ncontent-length: 5\r\n\r\n"), 5);
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nCONTENT-LENGTH:7\r\n\r\n"), 7);
+        assert_eq!(content_length(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n"), 0);
+        assert_eq!(content_length(b"POST / HTTP/1.1\r\nContent-Length: x\r\n\r\n"), 0);
+    }
+
+    #[test]
+    fn request_length_stops_at_the_body() {
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 3\r\n\r\nabcGET / HTTP/1.1\r\n\r\n";
+        assert_eq!(request_length(request), 41);
+        assert_eq!(request_length(b"GET / HTTP/1.1\r\n\r\nGET /next"), 18);
+        // Incomplete heads and bodies take the whole buffer
+        assert_eq!(request_length(b"GET / HTTP/1.1\r\nHost"), 20);
+        assert_eq!(request_length(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab"), 41);
+        assert_eq!(request_length(b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n"), 60);
+    }
+
+    #[test]
+    fn requests_are_read_in_pieces() {
+        let (mut client, mut server) = pair();
+        let writer = thread::spawn(move || {
+            client.write_all(b"POST / HTTP/1.1\r\nContent-").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            client.write_all(b"Length: 4\r\n\r\nab").unwrap();
+            thread::sleep(Duration::from_millis(20));
+            client.write_all(b"cd").unwrap();
+            client
+        });
+
+        let request = timeouts().read_request(&mut server).unwrap();
+        assert_eq!(request, b"POST / HTTP/1.1\r\nContent-Length: 4\r\n\r\nabcd".to_vec());
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn silent_clients_hit_the_idle_timeout() {
+        let (_client, mut server) = pair();
+        let timeouts = ReadTimeouts {
+            idle: Some(Duration::from_millis(50)),
+            ..timeouts()
+        };
+
+        let started = Instant::now();
+        assert!(matches!(timeouts.read_request(&mut server), Err(ReadError::Idle)));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn slow_headers_hit_the_header_timeout() {
+        let (mut client, mut server) = pair();
+        let timeouts = ReadTimeouts {
+            header: Some(Duration::from_millis(100)),
+            ..timeouts()
+        };
+
+        // Trickling bytes doesn't extend the deadline
+        let writer = thread::spawn(move || {
+            for _ in 0..20 {
+                if client.write_all(b"X").is_err() {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(20));
+            }
+        });
+
+        assert!(matches!(timeouts.read_request(&mut server), Err(ReadError::HeaderTimeout)));
+        drop(server);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn slow_bodies_hit_the_body_timeout() {
+        let (mut client, mut server) = pair();
+        let timeouts = ReadTimeouts {
+            body: Some(Duration::from_millis(50)),
+            ..timeouts()
+        };
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nab").unwrap();
+
+        assert!(matches!(timeouts.read_request(&mut server), Err(ReadError::BodyTimeout)));
+    }
+
+    #[test]
+    fn oversized_requests_are_refused() {
+        let (mut client, mut server) = pair();
+        client.write_all(b"POST / HTTP/1.1\r\nContent-Length: 2048\r\n\r\n").unwrap();
+        assert!(matches!(timeouts().read_request(&mut server), Err(ReadError::PayloadTooLarge)));
+
+        let (mut client, mut server) = pair();
+        let writer = thread::spawn(move || {
+            client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+            let header = format!("X-Big: {}\r\n", "a".repeat(1000));
+            for _ in 0..70 {
+                if client.write_all(header.as_bytes()).is_err() {
+                    break;
+                }
+            }
+        });
+        assert!(matches!(timeouts().read_request(&mut server), Err(ReadError::HeaderTooLarge)));
+        drop(server);
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn closed_connections_are_reported() {
+        let (client, mut server) = pair();
+        drop(client);
+        assert!(matches!(timeouts().read_request(&mut server), Err(ReadError::Closed)));
+    }
+}
@@ -1,32 +1,89 @@
+use std::future::Future;
 use std::sync::Arc;
+use std::time::Duration;
 use std::vec::Vec;
 
+use super::executor::BoxFuture;
 use super::super::super::http::{
+    HTTPError,
     HTTPMethod,
+    IntoResponse,
     request::Request,
     response::Response
 };
 
-pub type RouteHandler = Box<Fn(&Request, &mut Response) -> Result<(), String> + Send + Sync + 'static>;
+pub type RouteHandler = Box<dyn Fn(&Request) -> Result<Response, HTTPError> + Send + Sync + 'static>;
+
+/// Gets its own copy of the request as the future may outlive the connection handling code
+pub type AsyncRouteHandler = Box<dyn Fn(Request) -> BoxFuture<Result<Response, HTTPError>> + Send + Sync + 'static>;
+
+#[derive(Clone)]
+pub enum Handler {
+    Sync(Arc<RouteHandler>),
+    Async(Arc<AsyncRouteHandler>)
+}
 
 #[derive(Clone)]
 pub struct Route {
     method: Vec<HTTPMethod>,
     path: String,
-    handler: Arc<RouteHandler>
+    handler: Handler,
+    server_timeout: Option<Duration>,
+    auth: Option<String>
 }
 
 impl Route {
-    pub fn new(mut method: Vec<HTTPMethod>, path: String, handler: Arc<RouteHandler>) -> Self {
+    pub fn new<F, R, E>(mut method: Vec<HTTPMethod>, path: String, handler: F) -> Self
+        where F: Fn(&Request) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        method.sort();
+        method.dedup();
+        Self {
+            method,
+            path,
+            handler: Handler::Sync(Arc::new(Box::new(move |req: &Request| handler(req).map_err(Into::into).and_then(IntoResponse::try_into_response)))),
+            server_timeout: None,
+            auth: None
+        }
+    }
+
+    /// A route whose handler is an `async fn`, run on the server's executor instead of the worker threads
+    /// Reading the request and writing its response still happen on blocking sockets
+    pub fn new_async<F, Fut, R, E>(mut method: Vec<HTTPMethod>, path: String, handler: F) -> Self
+        where F: Fn(Request) -> Fut + Send + Sync + 'static,
+              Fut: Future<Output = Result<R, E>> + Send + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
         method.sort();
         method.dedup();
+
         Self {
             method,
             path,
-            handler
+            handler: Handler::Async(Arc::new(Box::new(move |req: Request| {
+                let future = handler(req);
+                Box::pin(async move { future.await.map_err(Into::into).and_then(IntoResponse::try_into_response) }) as BoxFuture<_>
+            }))),
+            server_timeout: None,
+            auth: None
         }
     }
 
+    /// Answer with a `503` if the handler hasn't responded within the timeout, like hapi's `timeout.server`
+    pub fn server_timeout(mut self, timeout: Duration) -> Self {
+        self.server_timeout = Some(timeout);
+        self
+    }
+
+    /// Authenticate requests with the strategy registered under this name before calling the handler
+    pub fn auth(mut self, strategy: &str) -> Self {
+        self.auth = Some(String::from(strategy));
+        self
+    }
+
     pub fn get_method(&self) -> Vec<HTTPMethod> {
         self.method.to_vec()
     }
@@ -35,11 +92,20 @@ impl Route {
         self.path.clone()
     }
 
-    pub fn get_handler(&self) -> Arc<RouteHandler> {
-        Arc::clone(&self.handler)
+    pub fn get_handler(&self) -> Handler {
+        self.handler.clone()
+    }
+
+
+    pub fn get_server_timeout(&self) -> Option<Duration> {
+        self.server_timeout
+    }
+
+    pub fn get_auth(&self) -> Option<String> {
+        self.auth.clone()
     }
 
-    pub fn is_route_match(&self, method: HTTPMethod, path: String) -> bool { // TODO: Change from bool to Result to allow for a 405 to be sent if path matches but is not a correct method
+    pub fn is_route_match(&self, method: HTTPMethod, path: String) -> bool {
         match self.is_path_match(path) {
             true => {
                 match self.method.iter().find(|&&verb| verb == method) {
@@ -52,7 +118,7 @@ impl Route {
     }
 
     #[allow(unused_variables)]
-    fn is_path_match(&self, path: String) -> bool {
+    pub fn is_path_match(&self, path: String) -> bool {
         let is_grabbing = false;
         let mut path_so_far = String::from("");
 
@@ -85,11 +151,9 @@ impl Default for Route {
         Self {
             method: vec![HTTPMethod::GET, HTTPMethod::POST],
             path: String::from("/"),
-            handler: Arc::new(Box::new(|req: &Request, res: &mut Response| {
-                res.set_body(req.get_payload());
-                res.write(true);
-                Ok(())
-            }))
+            handler: Handler::Sync(Arc::new(Box::new(|req: &Request| Ok(req.get_payload().into_response())))),
+            server_timeout: None,
+            auth: None
         }
     }
 }
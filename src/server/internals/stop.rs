@@ -0,0 +1,20 @@
+use std::sync::mpsc;
+
+/// Stops a running server from another thread, cheap to clone
+#[derive(Clone)]
+pub struct StopHandle {
+    sender: mpsc::Sender<()>
+}
+
+impl StopHandle {
+    pub fn new(sender: mpsc::Sender<()>) -> Self {
+        Self {
+            sender
+        }
+    }
+
+    /// Stop accepting connections and return from `Server::run` once the requests being handled are answered
+    pub fn stop(&self) {
+        self.sender.send(()).unwrap_or_default();
+    }
+}
@@ -1,4 +1,4 @@
-
+use std::any::Any;
 
 pub trait FnBox {
     fn call_box(self: Box<Self>);
@@ -10,4 +10,28 @@ impl<F: FnOnce()> FnBox for F {
     }
 }
 
-pub type Job = Box<FnBox + Send + 'static>;
+pub type Job = Box<dyn FnBox + Send + 'static>;
+
+/// A job that runs for as long as its worker, each run calls a fresh copy so it can start over after a panic
+pub trait ContinuousFnBox {
+    fn call_copy(&self);
+}
+
+impl<F: FnOnce() + Clone> ContinuousFnBox for F {
+    fn call_copy(&self) {
+        (self.clone())();
+    }
+}
+
+pub type ContinuousJob = Box<dyn ContinuousFnBox + Send + 'static>;
+
+/// The message a job panicked with, if it is a string
+pub fn panic_message(panic: &(dyn Any + Send)) -> String {
+    match panic.downcast_ref::<&str>() {
+        Some(message) => String::from(*message),
+        None => match panic.downcast_ref::<String>() {
+            Some(message) => message.clone(),
+            None => String::from("Box<dyn Any>")
+        }
+    }
+}
@@ -1,12 +1,9 @@
-use super::job::Job;
-use super::RouteHandler;
-use super::{Request, Response};
-use std::sync::Arc;
+use super::job::{ContinuousJob, Job};
+use std::time::Instant;
 
 #[allow(dead_code)]
 pub enum Message {
-    NewContinuousJob(Job),
-    NewJob(Job),
-    HandlerJob(Arc<RouteHandler>, Request, Response),
-    Terminate
+    NewContinuousJob(ContinuousJob),
+    /// A job and when it was queued
+    NewJob(Job, Instant)
 }
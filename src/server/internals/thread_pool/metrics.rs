@@ -0,0 +1,83 @@
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+
+// Upper bounds of the histogram buckets in seconds, the last bucket catches everything above
+pub const BUCKETS: [f64; 12] = [0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Counts durations into fixed buckets
+#[derive(Debug, Default)]
+pub struct Histogram {
+    counts: [AtomicU64; 13],
+    sum_micros: AtomicU64
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+        let bucket = BUCKETS.iter().position(|bound| seconds <= *bound).unwrap_or(BUCKETS.len());
+
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Observations per bucket of `BUCKETS` plus the overflow bucket, not cumulative
+    pub fn get_counts(&self) -> Vec<u64> {
+        self.counts.iter().map(|count| count.load(Ordering::Relaxed)).collect()
+    }
+
+    pub fn get_count(&self) -> u64 {
+        self.get_counts().iter().sum()
+    }
+
+    pub fn get_sum(&self) -> Duration {
+        Duration::from_micros(self.sum_micros.load(Ordering::Relaxed))
+    }
+}
+
+/// What the thread pool is doing
+#[derive(Debug, Default)]
+pub struct PoolMetrics {
+    pub(super) workers: AtomicUsize,
+    pub(super) busy: AtomicUsize,
+    pub(super) queued: AtomicUsize,
+    pub(super) jobs: AtomicU64,
+    pub(super) panics: AtomicU64,
+    pub(super) wait_time: Histogram,
+    pub(super) run_time: Histogram
+}
+
+impl PoolMetrics {
+    pub fn get_workers(&self) -> usize {
+        self.workers.load(Ordering::Relaxed)
+    }
+
+    /// Workers currently running a job
+    pub fn get_busy(&self) -> usize {
+        self.busy.load(Ordering::Relaxed)
+    }
+
+    /// Jobs waiting for a worker
+    pub fn get_queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Jobs finished, including the ones that panicked
+    pub fn get_jobs(&self) -> u64 {
+        self.jobs.load(Ordering::Relaxed)
+    }
+
+    /// Jobs that panicked, route handler panics are answered with a `500` before they reach the pool
+    pub fn get_panics(&self) -> u64 {
+        self.panics.load(Ordering::Relaxed)
+    }
+
+    /// Time jobs spent in the queue
+    pub fn get_wait_time(&self) -> &Histogram {
+        &self.wait_time
+    }
+
+    /// Time jobs spent running
+    pub fn get_run_time(&self) -> &Histogram {
+        &self.run_time
+    }
+}
@@ -1,81 +1,307 @@
 pub mod job;
+pub mod metrics;
 mod message;
 mod worker;
 
+use std::mem;
 use std::vec::Vec;
-use std::sync::{Arc, mpsc, Mutex};
+use std::sync::{Arc, mpsc, Mutex, PoisonError};
+use std::sync::mpsc::TrySendError;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use self::job::{FnBox};
-use self::worker::Worker;
+use self::metrics::PoolMetrics;
+use super::log::Logger;
+use self::worker::{Reaping, Worker};
 use self::message::Message;
-use super::route::RouteHandler;
-use super::super::super::http::{
-    request::Request,
-    response::Response
-};
 
 pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: mpsc::SyncSender<Message>
+    workers: Mutex<Vec<Worker>>,
+    receiver: Arc<Mutex<mpsc::Receiver<Message>>>,
+    sender: mpsc::SyncSender<Message>,
+    metrics: Arc<PoolMetrics>,
+    max_workers: usize,
+    reaping: Reaping,
+    next_id: AtomicUsize,
+    logger: Logger
 }
 
 impl ThreadPool {
-    pub fn new(size: usize) -> Self {
-        assert!(size > 0);
-        assert!(size as u8 <= u8::max_value());
+    pub fn new(size: usize, queue_capacity: usize, logger: Logger) -> Self {
+        Self::with_sizing(size, size, None, queue_capacity, logger)
+    }
+
+    /// A pool starting with `min_workers` that grows up to `max_workers` when every worker is busy,
+    /// extra workers leave again after being idle for `idle_timeout`
+    pub fn with_sizing(min_workers: usize, max_workers: usize, idle_timeout: Option<Duration>, queue_capacity: usize, logger: Logger) -> Self {
+        assert!(min_workers > 0);
+        assert!(max_workers >= min_workers);
 
-        let (sender, receiver) = mpsc::sync_channel(2);
+        let (sender, receiver) = mpsc::sync_channel(queue_capacity);
         let receiver = Arc::new(Mutex::new(receiver));
-        let mut workers = Vec::with_capacity(size);
+        let metrics = Arc::new(PoolMetrics::default());
+        let reaping = Reaping {
+            min_workers,
+            idle_timeout: match max_workers > min_workers {
+                true => idle_timeout,
+                false => None
+            }
+        };
+        let mut workers = Vec::with_capacity(max_workers);
 
-        for id in 0..size {
-            workers.push(Worker::new(id as u8, Arc::clone(&receiver)));
+        for id in 0..min_workers {
+            workers.push(Worker::new(id, Arc::clone(&receiver), Arc::clone(&metrics), reaping, logger.clone()));
         }
 
         Self {
-            workers,
-            sender
+            workers: Mutex::new(workers),
+            receiver,
+            sender,
+            metrics,
+            max_workers,
+            reaping,
+            next_id: AtomicUsize::new(min_workers),
+            logger
         }
     }
 
-    pub fn execute_handler(&self, handler: Arc<RouteHandler>, req: Request, res: Response) {
-        match self.sender.send(Message::HandlerJob(handler, req, res)) {
+    pub fn get_metrics(&self) -> Arc<PoolMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    pub fn execute<F>(&self, f: F)
+        where F: FnOnce() + Send + 'static
+    {
+        self.respawn_dead_workers();
+        self.grow();
+
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+        match self.sender.send(Message::NewJob(Box::new(f), Instant::now())) {
             Ok(_) => return,
-            Err(t) => self.log_error(t)
+            Err(t) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                self.log_error(t)
+            }
         }
     }
 
+    /// Queue `f` to be called with `state` unless the queue is full, `state` is handed back if it was
+    pub fn try_execute<T, F>(&self, state: T, f: F) -> Result<(), T>
+        where T: Send + 'static,
+              F: FnOnce(T) + Send + 'static
+    {
+        self.respawn_dead_workers();
+        self.grow();
+
+        // The job takes the state out of a shared slot so a rejected one can hand it back
+        let slot = Arc::new(Mutex::new(Some(state)));
+        let job_slot = Arc::clone(&slot);
+        let job = move || {
+            if let Some(state) = job_slot.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                f(state);
+            }
+        };
+
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+        match self.sender.try_send(Message::NewJob(Box::new(job), Instant::now())) {
+            Ok(_) => Ok(()),
+            Err(t) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                if let TrySendError::Disconnected(_) = t {
+                    self.logger.error(&["worker", "error"], "Failed to queue job: sending on a closed channel");
+                }
+                match slot.lock().unwrap_or_else(PoisonError::into_inner).take() {
+                    Some(state) => Err(state),
+                    None => Ok(())
+                }
+            }
+        }
+    }
+
+    /// Run a copy of the job on every worker
     pub fn execute_job<F>(&self, f: F)
         where F: FnBox + FnOnce() + Send + Clone + 'static
     {
+        self.respawn_dead_workers();
+
         let job = Box::new(f);
-        for _worker in &self.workers
-            {
+        let size = self.workers.lock().unwrap_or_else(PoisonError::into_inner).len();
+        for _ in 0..size {
             let cloned_job = job.clone();
             match self.sender.send(Message::NewContinuousJob(cloned_job)) {
-                Ok(_) => return,
+                Ok(_) => continue,
                 Err(t) => self.log_error(t)
             }
         }
     }
 
+    /// Add a worker when none is free to pick up the next job
+    fn grow(&self) {
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+        let pending = self.metrics.get_busy() + self.metrics.get_queued();
+
+        if workers.len() < self.max_workers && pending >= workers.len() {
+            workers.push(self.spawn_worker());
+        }
+    }
+
+    /// Replace workers whose thread died so the pool keeps its capacity, forget the ones that retired
+    fn respawn_dead_workers(&self) {
+        let mut workers = self.workers.lock().unwrap_or_else(PoisonError::into_inner);
+
+        workers.retain(|worker| !worker.is_retired());
+        for worker in workers.iter_mut() {
+            if !worker.is_alive() {
+                self.logger.warn(&["worker", "respawn"], format!("Worker {} died, respawning it", worker.get_id()));
+                *worker = Worker::new(worker.get_id(), Arc::clone(&self.receiver), Arc::clone(&self.metrics), self.reaping, self.logger.clone());
+            }
+        }
+    }
+
+    fn spawn_worker(&self) -> Worker {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        Worker::new(id, Arc::clone(&self.receiver), Arc::clone(&self.metrics), self.reaping, self.logger.clone())
+    }
+
     fn log_error(&self, err: mpsc::SendError<Message>) {
-        eprintln!("{}", err);
+        self.logger.error(&["worker", "error"], format!("Failed to queue job: {}", err));
     }
 }
 
 impl Drop for ThreadPool {
     fn drop(&mut self) {
-        println!("Sending terminate message to all workers!");
-        println!("{}", self.workers.len());
+        // Hang up on the workers, they finish the queued jobs and exit once the queue is empty
+        let (disconnected, _) = mpsc::sync_channel(0);
+        drop(mem::replace(&mut self.sender, disconnected));
+
+        let workers = self.workers.get_mut().unwrap_or_else(PoisonError::into_inner);
+        self.logger.debug(&["worker", "stop"], format!("Waiting for {} workers to shut down", workers.len()));
 
-        for _ in &mut self.workers {
-            self.sender.send(Message::Terminate).unwrap();
+        for worker in workers.drain(..) {
+            drop(worker);
         }
+    }
+}
 
-        println!("Shutting down all workers!");
+#[cfg(test)]
+mod tests {
+    use super::ThreadPool;
+    use super::super::log::Logger;
+    use std::sync::{Arc, Mutex, mpsc};
+    use std::thread;
+    use std::time::{Duration, Instant};
 
-        for worker in self.workers.iter() {
-            drop(worker);
+    fn eventually<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition never became true");
+            thread::sleep(Duration::from_millis(5));
         }
     }
+
+    /// A job blocking its worker until the test sends on the returned channel
+    fn blocking() -> (mpsc::Sender<()>, impl Fn() -> Box<dyn FnOnce() + Send>) {
+        let (release, released) = mpsc::channel::<()>();
+        let released = Arc::new(Mutex::new(released));
+        let job = move || -> Box<dyn FnOnce() + Send> {
+            let released = Arc::clone(&released);
+            Box::new(move || {
+                let released = released.lock().unwrap();
+                released.recv_timeout(Duration::from_secs(5)).unwrap_or_default();
+            })
+        };
+        (release, job)
+    }
+
+    #[test]
+    fn elastic_pools_grow_up_to_the_maximum() {
+        let pool = ThreadPool::with_sizing(1, 3, Some(Duration::from_secs(60)), 16, Logger::default());
+        let metrics = pool.get_metrics();
+        assert_eq!(metrics.get_workers(), 1);
+
+        let (release, job) = blocking();
+        for _ in 0..4 {
+            pool.execute(job());
+            thread::sleep(Duration::from_millis(10));
+        }
+        eventually(|| metrics.get_busy() == 3);
+        assert_eq!(metrics.get_workers(), 3);
+        assert_eq!(metrics.get_queued(), 1);
+
+        for _ in 0..4 {
+            release.send(()).unwrap();
+        }
+        eventually(|| metrics.get_jobs() == 4);
+        assert_eq!(metrics.get_workers(), 3);
+    }
+
+    #[test]
+    fn idle_extra_workers_retire_down_to_the_minimum() {
+        let pool = ThreadPool::with_sizing(1, 3, Some(Duration::from_millis(30)), 16, Logger::default());
+        let metrics = pool.get_metrics();
+
+        let (release, job) = blocking();
+        for _ in 0..3 {
+            pool.execute(job());
+            thread::sleep(Duration::from_millis(10));
+        }
+        eventually(|| metrics.get_workers() == 3);
+        for _ in 0..3 {
+            release.send(()).unwrap();
+        }
+        eventually(|| metrics.get_jobs() == 3);
+
+        eventually(|| metrics.get_workers() == 1);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(metrics.get_workers(), 1);
+
+        // The pool grows again past the retired workers
+        for _ in 0..2 {
+            pool.execute(job());
+            thread::sleep(Duration::from_millis(10));
+        }
+        eventually(|| metrics.get_busy() == 2);
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        eventually(|| metrics.get_jobs() == 5);
+    }
+
+    #[test]
+    fn fixed_pools_keep_their_workers() {
+        let pool = ThreadPool::with_sizing(2, 2, Some(Duration::from_millis(10)), 16, Logger::default());
+        let metrics = pool.get_metrics();
+        pool.execute(|| ());
+        eventually(|| metrics.get_jobs() == 1);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(metrics.get_workers(), 2);
+    }
+
+    #[test]
+    fn full_queues_hand_the_state_back() {
+        let pool = ThreadPool::new(1, 1, Logger::default());
+        let metrics = pool.get_metrics();
+        let (release, job) = blocking();
+
+        pool.execute(job());
+        eventually(|| metrics.get_busy() == 1);
+        assert_eq!(pool.try_execute(1, |_| ()), Ok(()));
+        assert_eq!(pool.try_execute(2, |_| ()), Err(2));
+
+        release.send(()).unwrap();
+        eventually(|| metrics.get_jobs() == 2);
+    }
+
+    #[test]
+    fn workers_survive_panicking_jobs() {
+        let pool = ThreadPool::new(1, 4, Logger::default());
+        let metrics = pool.get_metrics();
+        let (sender, receiver) = mpsc::channel();
+
+        pool.execute(|| panic!("job failed"));
+        pool.execute(move || sender.send(()).unwrap_or_default());
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+        eventually(|| metrics.get_jobs() == 2);
+        assert_eq!(metrics.get_panics(), 1);
+        assert_eq!(metrics.get_workers(), 1);
+    }
 }
@@ -1,15 +1,38 @@
 use std::thread;
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::{Arc, Mutex, mpsc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use super::job::{ContinuousJob, Job, panic_message};
 use super::message::Message;
+use super::metrics::PoolMetrics;
+use super::super::log::Logger;
+
+const CONTINUOUS_JOB_RESTART_DELAY: Duration = Duration::from_millis(100);
+
+/// How a worker may leave an elastic pool
+#[derive(Copy, Clone)]
+pub struct Reaping {
+    pub min_workers: usize,
+    pub idle_timeout: Option<Duration>
+}
 
 pub struct Worker {
-    id: u8,
-    thread: Option<thread::JoinHandle<()>>
+    id: usize,
+    thread: Option<thread::JoinHandle<()>>,
+    retired: Arc<AtomicBool>,
+    logger: Logger
 }
 
 impl Worker {
-    pub fn new(id: u8, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Self {
-        println!("A new worker: {}", id);
+    pub fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Message>>>, metrics: Arc<PoolMetrics>, reaping: Reaping, logger: Logger) -> Self {
+        logger.debug(&["worker", "start"], format!("Worker {} started", id));
+        metrics.workers.fetch_add(1, Ordering::Relaxed);
+
+        let retired = Arc::new(AtomicBool::new(false));
+        let worker_retired = Arc::clone(&retired);
+        let worker_logger = logger.clone();
+
         Self {
             id,
             thread: Some(thread::spawn(move || {
@@ -19,53 +42,115 @@ impl Worker {
                         Err(_) => break
                     };
 
-                    let message = match unlocked.recv() {
+                    let received = match reaping.idle_timeout {
+                        None => unlocked.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                        Some(timeout) => unlocked.recv_timeout(timeout)
+                    };
+
+                    let message = match received {
                         Ok(message) => message,
-                        Err(error) => {
-                            println!("Worker error: {:?}", error);
+                        Err(mpsc::RecvTimeoutError::Timeout) => match Self::try_retire(&metrics, reaping.min_workers) {
+                            true => {
+                                worker_logger.debug(&["worker", "stop"], format!("Worker {} was idle for too long, shutting down", id));
+                                worker_retired.store(true, Ordering::SeqCst);
+                                return;
+                            },
+                            false => continue
+                        },
+                        // The pool was dropped and every queued job is done
+                        Err(mpsc::RecvTimeoutError::Disconnected) => {
+                            worker_logger.debug(&["worker", "stop"], format!("Worker {} is shutting down", id));
                             break;
                         }
                     };
 
                     match message {
                         Message::NewContinuousJob(job) => {
-                            println!("Worker {} got a new continuous job!", id);
+                            worker_logger.trace(&["worker", "job"], format!("Worker {} got a new continuous job", id));
                             drop(unlocked);
-                            job.call_box();
-                        },
-                        Message::NewJob(job) => {
-                            println!("Worker {} got a new job!", id);
-                            job.call_box();
+                            Self::run_continuous_job(id, job, &metrics, &worker_logger);
                         },
-                        Message::HandlerJob(handler, req, mut res) => {
-                            println!("Worker {} got a new handler job!", id);
-                            match handler(&req, &mut res) {
-                                Ok(_) => continue,
-                                Err(e) => Self::log_error(e)
-                            }
-                        }
-                        Message::Terminate => {
-                            println!("Worker {} is shutting down", id);
-                            break;
+                        Message::NewJob(job, queued_at) => {
+                            worker_logger.trace(&["worker", "job"], format!("Worker {} got a new job", id));
+                            drop(unlocked);
+                            metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                            metrics.wait_time.observe(queued_at.elapsed());
+                            Self::run_job(id, job, &metrics, &worker_logger);
                         }
                     }
                 }
-            }))
+
+                metrics.workers.fetch_sub(1, Ordering::Relaxed);
+            })),
+            retired,
+            logger
         }
     }
 
-    pub fn get_id(&self) -> u8 {
+    pub fn get_id(&self) -> usize {
         self.id
     }
 
-    fn log_error(err: String) {
-        eprintln!("{}", err);
+    /// Whether the worker thread is still running, it only stops on its own once the pool is dropped
+    /// or when it retires from an elastic pool
+    pub fn is_alive(&self) -> bool {
+        match self.thread {
+            None => false,
+            Some(ref thread) => !thread.is_finished()
+        }
+    }
+
+    /// Whether the worker left the pool for being idle rather than dying
+    pub fn is_retired(&self) -> bool {
+        self.retired.load(Ordering::SeqCst)
+    }
+
+    /// Leave the pool if it has more than the minimum number of workers
+    fn try_retire(metrics: &PoolMetrics, min_workers: usize) -> bool {
+        metrics.workers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |workers| match workers > min_workers {
+                true => Some(workers - 1),
+                false => None
+            })
+            .is_ok()
+    }
+
+    /// Run the job, keeping the worker thread alive if it panics
+    fn run_job(id: usize, job: Job, metrics: &PoolMetrics, logger: &Logger) {
+        let started = Instant::now();
+        metrics.busy.fetch_add(1, Ordering::Relaxed);
+
+        if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| job.call_box())) {
+            logger.error(&["worker", "panic"], format!("Worker {} job panicked: {}", id, panic_message(&*panic)));
+            metrics.panics.fetch_add(1, Ordering::Relaxed);
+        }
+
+        metrics.busy.fetch_sub(1, Ordering::Relaxed);
+        metrics.jobs.fetch_add(1, Ordering::Relaxed);
+        metrics.run_time.observe(started.elapsed());
+    }
+
+    /// Run the job until it returns, starting it over if it panics so the worker never sits idle
+    fn run_continuous_job(id: usize, job: ContinuousJob, metrics: &PoolMetrics, logger: &Logger) {
+        let started = Instant::now();
+        metrics.busy.fetch_add(1, Ordering::Relaxed);
+
+        while let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| job.call_copy())) {
+            logger.error(&["worker", "panic"], format!("Worker {} continuous job panicked, restarting it: {}", id, panic_message(&*panic)));
+            metrics.panics.fetch_add(1, Ordering::Relaxed);
+            // Don't spin on a job that panics straight away
+            thread::sleep(CONTINUOUS_JOB_RESTART_DELAY);
+        }
+
+        metrics.busy.fetch_sub(1, Ordering::Relaxed);
+        metrics.jobs.fetch_add(1, Ordering::Relaxed);
+        metrics.run_time.observe(started.elapsed());
     }
 }
 
 impl Drop for Worker {
     fn drop(&mut self) {
-        println!("Dropping worker {}", self.id);
+        self.logger.trace(&["worker"], format!("Dropping worker {}", self.id));
         if let Some(thread) = self.thread.take() {
             thread.join().unwrap_or_default();
         }
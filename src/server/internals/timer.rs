@@ -0,0 +1,98 @@
+use super::log::Logger;
+use super::thread_pool::job::{panic_message, FnBox, Job};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Runs jobs once their deadline passes, all on one background thread
+pub struct Timer {
+    sender: mpsc::Sender<(Instant, Job)>,
+    logger: Logger
+}
+
+impl Timer {
+    pub fn new(logger: Logger) -> Self {
+        let (sender, receiver) = mpsc::channel::<(Instant, Job)>();
+        let timer_logger = logger.clone();
+
+        thread::spawn(move || {
+            let mut pending: Vec<(Instant, Job)> = vec![];
+
+            loop {
+                // Sleep until the next deadline or a new job arrives
+                let next = pending.iter().map(|(deadline, _)| *deadline).min();
+                let received = match next {
+                    None => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+                    Some(deadline) => receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+                };
+
+                match received {
+                    Ok(job) => pending.push(job),
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break
+                }
+
+                let now = Instant::now();
+                let (due, waiting): (Vec<_>, Vec<_>) = pending.drain(..).partition(|(deadline, _)| *deadline <= now);
+                pending = waiting;
+
+                // A panicking job would take every other pending job down with the thread
+                for (_, job) in due {
+                    if let Err(panic) = panic::catch_unwind(AssertUnwindSafe(|| job.call_box())) {
+                        timer_logger.error(&["timer", "panic"], format!("Timer job panicked: {}", panic_message(&*panic)));
+                    }
+                }
+            }
+        });
+
+        Self {
+            sender,
+            logger
+        }
+    }
+
+    /// Run the job after the delay, jobs must not block as they share the timer thread
+    pub fn schedule<F>(&self, delay: Duration, f: F)
+        where F: FnBox + Send + 'static
+    {
+        if let Err(e) = self.sender.send((Instant::now() + delay, Box::new(f))) {
+            self.logger.error(&["timer", "error"], format!("Failed to schedule timer job: {}", e));
+        }
+    }
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new(Logger::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Timer;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn jobs_run_in_deadline_order() {
+        let timer = Timer::default();
+        let (sender, receiver) = mpsc::channel();
+        let late = sender.clone();
+        timer.schedule(Duration::from_millis(60), move || late.send(2).unwrap_or_default());
+        timer.schedule(Duration::from_millis(10), move || sender.send(1).unwrap_or_default());
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).ok(), Some(1));
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).ok(), Some(2));
+    }
+
+    #[test]
+    fn a_panicking_job_does_not_stop_the_timer() {
+        let timer = Timer::default();
+        let (sender, receiver) = mpsc::channel();
+        timer.schedule(Duration::from_millis(0), || panic!("timer job"));
+        timer.schedule(Duration::from_millis(20), move || sender.send(()).unwrap_or_default());
+
+        assert!(receiver.recv_timeout(Duration::from_secs(5)).is_ok());
+    }
+}
@@ -0,0 +1,90 @@
+use super::super::super::http::request::trace::Span;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, mpsc};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// Receives the spans of every sampled request once it has been answered
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, spans: Vec<Span>);
+}
+
+impl<F> SpanExporter for F
+    where F: Fn(Vec<Span>) + Send + Sync
+{
+    fn export(&self, spans: Vec<Span>) {
+        self(spans)
+    }
+}
+
+/// Posts spans as OTLP JSON to a collector's `/v1/traces`, from a background thread so responses never wait on it
+pub struct OtlpHttpExporter {
+    sender: mpsc::Sender<String>,
+    service_name: String,
+    failed: Arc<AtomicU64>
+}
+
+impl OtlpHttpExporter {
+    /// Export to the collector listening at `address`, such as `127.0.0.1:4318`
+    pub fn new(address: &str, service_name: &str) -> Self {
+        let (sender, receiver) = mpsc::channel::<String>();
+        let failed = Arc::new(AtomicU64::new(0));
+        let (address, thread_failed) = (String::from(address), Arc::clone(&failed));
+
+        thread::spawn(move || {
+            for body in receiver {
+                if post(&address, &body).is_err() {
+                    thread_failed.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        });
+
+        Self {
+            sender,
+            service_name: String::from(service_name),
+            failed
+        }
+    }
+
+    /// Batches the collector couldn't be sent or refused
+    pub fn get_failed(&self) -> u64 {
+        self.failed.load(Ordering::Relaxed)
+    }
+}
+
+impl SpanExporter for OtlpHttpExporter {
+    fn export(&self, spans: Vec<Span>) {
+        let body = serde_json::json!({
+            "resourceSpans": [{
+                "resource": {
+                    "attributes": [{ "key": "service.name", "value": { "stringValue": self.service_name } }]
+                },
+                "scopeSpans": [{
+                    "scope": { "name": "hapi_rs" },
+                    "spans": spans.iter().map(Span::to_otlp).collect::<Vec<_>>()
+                }]
+            }]
+        });
+
+        self.sender.send(body.to_string()).unwrap_or_default();
+    }
+}
+
+fn post(address: &str, body: &str) -> std::io::Result<()> {
+    let mut stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    write!(
+        stream,
+        "POST /v1/traces HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        address, body.len(), body
+    )?;
+
+    let mut status = [0; 12];
+    stream.read_exact(&mut status)?;
+    match status[9] {
+        b'2' => Ok(()),
+        _ => Err(std::io::Error::other("collector refused the spans"))
+    }
+}
@@ -1,18 +1,49 @@
 pub(crate) mod internals;
 
+pub use self::internals::access_log::AccessLogFormat;
+pub use self::internals::auth::AuthStrategy;
+#[cfg(feature = "tls")]
+pub use self::internals::auth::ClientCertificateAuth;
+#[cfg(feature = "tls")]
+pub use super::http::request::certificate::{ClientCertificate, SubjectAltName};
+pub use self::internals::events::{EventFilter, EventName, ResponseEvent, ServerEvent};
+pub use super::http::request::log::{LogLevel, RequestChannel};
+pub use self::internals::executor::{BoxFuture, Executor, ThreadExecutor};
+pub use self::internals::fallback::FallbackHandler;
+pub use self::internals::load::LoadMetrics;
+pub use self::internals::log::{LogEvent, LogSink, Logger};
 pub use self::internals::options::ServerOptions;
-pub use self::internals::route::{Route, RouteHandler};
+pub use self::internals::prometheus::HttpMetrics;
+pub use self::internals::thread_pool::metrics::{Histogram, PoolMetrics};
+pub use self::internals::route::{AsyncRouteHandler, Handler, Route, RouteHandler};
+pub use super::http::request::credentials::Credentials;
+pub use super::http::request::info::Client;
+pub use self::internals::proxy::{Cidr, TrustedProxies};
+pub use self::internals::proxy_protocol::{ProxyHeader, ProxyProtocol};
+pub use self::internals::stop::StopHandle;
+#[cfg(feature = "tls")]
+pub use self::internals::tls::{ClientAuth, TlsCertificate};
+pub use self::internals::tracing::{OtlpHttpExporter, SpanExporter};
+pub use super::http::request::trace::{Span, SpanKind, TraceContext};
 
+use self::internals::connection::{Connection, Sink};
+use self::internals::context::ServerContext;
+use self::internals::executor::CatchUnwind;
+use self::internals::reader::{request_length, ReadError};
 use self::internals::thread_pool::ThreadPool;
-use super::http::HTTPStatusCodes;
-use super::http::HTTPVersion;
+use self::internals::fallback::to_fallback_handler;
+use self::internals::http2::{self, Http2Connection};
+use self::internals::load::ConnectionGuard;
+use super::http::{HTTPError, HTTPMethod, IntoResponse, Response};
 use super::http::request::Request;
-use super::http::response::Response;
-use super::http::HTTP;
+use super::http::h2::PREFACE;
+use std::fmt::Display;
+use std::io::{self, ErrorKind};
 use std::vec::Vec;
-use std::net::{TcpListener, TcpStream};
-use std::sync::{Arc, Mutex};
-use std::io::*;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, TcpStream};
+use std::sync::{Arc, mpsc};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 
 
 pub struct Server {
@@ -21,7 +52,9 @@ pub struct Server {
     routes: Vec<Route>,
     server_thread_pool: ThreadPool,
     worker_thread_pool: ThreadPool,
-    default_http_version: HTTPVersion
+    context: ServerContext,
+    stop_sender: mpsc::Sender<()>,
+    stop_receiver: mpsc::Receiver<()>
 }
 
 impl Server {
@@ -29,118 +62,746 @@ impl Server {
         assert!(options.get_server_threads() > 0);
         assert!(options.get_worker_threads() > 0);
 
-        Self {
+        let context = ServerContext::new(options);
+        let logger = context.get_logger().clone();
+        let (stop_sender, stop_receiver) = mpsc::channel();
+
+        let mut server = Self {
             port: options.get_port(),
             host: options.get_host(),
             routes: vec![],
-            server_thread_pool: ThreadPool::new(options.get_server_threads()),
-            worker_thread_pool: ThreadPool::new(options.get_worker_threads()),
-            default_http_version: options.get_default_http_version()
+            server_thread_pool: ThreadPool::new(options.get_server_threads(), options.get_server_threads(), logger.clone()),
+            worker_thread_pool: ThreadPool::with_sizing(
+                options.get_worker_threads(),
+                options.get_max_worker_threads(),
+                Some(options.get_worker_idle_timeout()),
+                options.get_queue_capacity(),
+                logger
+            ),
+            context,
+            stop_sender,
+            stop_receiver
+        };
+
+        if let (Some(path), Some(metrics)) = (options.get_metrics_route(), server.context.get_metrics()) {
+            let (pool, load) = (server.get_pool_metrics(), server.get_load_metrics());
+            server.route(Route::new(vec![HTTPMethod::GET], path, move |_req| -> Result<Response, HTTPError> {
+                Ok(Response::new(metrics.render(&pool, &load).into_bytes()).type_("text/plain; version=0.0.4; charset=utf-8"))
+            }));
         }
+
+        server
     }
 
     pub fn route(&mut self, new_route: Route) {
         // TODO: Add more route validation here
+        if let Some(strategy) = new_route.get_auth() {
+            assert!(self.context.has_auth_strategy(&strategy), "Unknown authentication strategy {} in {}", strategy, new_route.get_path());
+        }
+        self.context.get_events().emit(ServerEvent::Route { method: new_route.get_method(), path: new_route.get_path() });
         self.routes.push(new_route);
     }
 
+    /// Register an auth strategy for routes to name with `Route::auth`, before adding those routes
+    pub fn auth_strategy<S: AuthStrategy + 'static>(&mut self, name: &str, strategy: S) {
+        self.context.add_auth_strategy(String::from(name), Arc::new(strategy));
+    }
+
+    /// Subscribe to server events, pass an `EventName` or an `EventFilter` narrowing them down by channel or tags
+    pub fn on<T, F>(&mut self, filter: T, listener: F)
+        where T: Into<EventFilter>,
+              F: Fn(&ServerEvent) + Send + Sync + 'static
+    {
+        self.context.get_events().on(filter.into(), listener);
+    }
+
+    /// A handle that stops the server once it has started
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle::new(self.stop_sender.clone())
+    }
+
+    /// Run async handlers on this executor instead of the built-in `ThreadExecutor`
+    pub fn executor(&mut self, executor: Arc<dyn Executor>) {
+        self.context.set_executor(executor);
+    }
+
+    /// Live counters of open connections, queued requests and load rejections
+    pub fn get_load_metrics(&self) -> Arc<LoadMetrics> {
+        self.context.get_load().get_metrics()
+    }
+
+    /// Log a server event, `Request::log` ties events to a request instead
+    pub fn log<D: Display>(&self, level: LogLevel, tags: &[&str], data: D) {
+        self.context.get_logger().log(level, tags, data);
+    }
+
+    /// A handle to the server's logger, for use inside handlers or other threads
+    pub fn get_logger(&self) -> Logger {
+        self.context.get_logger().clone()
+    }
+
+    /// Request counts and durations by route, `None` unless the options set a metrics route
+    pub fn get_http_metrics(&self) -> Option<Arc<HttpMetrics>> {
+        self.context.get_metrics()
+    }
+
+    /// Live counters of the worker pool: its size, busy workers, queue depth, jobs run and their timings
+    pub fn get_pool_metrics(&self) -> Arc<PoolMetrics> {
+        self.worker_thread_pool.get_metrics()
+    }
+
+    /**
+     * Fallback Handlers
+    */
+
+    /// Answer requests no route matches
+    pub fn not_found<F, R, E>(&mut self, handler: F)
+        where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        self.context.get_fallbacks_mut().set_not_found(to_fallback_handler(handler));
+    }
+
+    /// Answer unmatched requests under a path prefix such as `/api` or `/api/{p*}`, the longest prefix wins
+    pub fn not_found_prefix<F, R, E>(&mut self, prefix: String, handler: F)
+        where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        self.context.get_fallbacks_mut().add_not_found_prefix(prefix, to_fallback_handler(handler));
+    }
+
+    /// Answer requests whose path matches a route but whose method doesn't, the error carries the `Allow` header
+    pub fn method_not_allowed<F, R, E>(&mut self, handler: F)
+        where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        self.context.get_fallbacks_mut().set_method_not_allowed(to_fallback_handler(handler));
+    }
+
+    /// Answer requests that couldn't be parsed, there is no `Request` to pass in this case
+    pub fn bad_request<F, R, E>(&mut self, handler: F)
+        where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        self.context.get_fallbacks_mut().set_bad_request(to_fallback_handler(handler));
+    }
+
+    /// Answer requests whose route handler failed
+    pub fn handler_error<F, R, E>(&mut self, handler: F)
+        where F: Fn(Option<&Request>, HTTPError) -> Result<R, E> + Send + Sync + 'static,
+              R: IntoResponse,
+              E: Into<HTTPError>
+    {
+        self.context.get_fallbacks_mut().set_handler_error(to_fallback_handler(handler));
+    }
+
+    /// Serve forever, use `run` instead to get control back once a `StopHandle` stops the server
     pub fn start(self) -> ! {
+        self.run();
+
+        // Stopped, but callers of `start` expect it to never return
+        loop {
+            thread::park();
+        }
+    }
+
+    /// Serve until a `StopHandle` stops the server, returning once the requests in flight are answered
+    pub fn run(self) {
         let host_url = format!("{}:{}", self.host, self.port);
-        let listener = TcpListener::bind(host_url).unwrap();
-        let worker_listener = Arc::new(Mutex::new(listener.try_clone().unwrap()));
-        let mut shared_routes = Arc::new(self.routes.to_vec());
+        let listener = Arc::new(TcpListener::bind(&host_url).unwrap());
+        let address = listener.local_addr().unwrap();
+        let shared_routes = Arc::new(self.routes.to_vec());
         let worker_thread_pool = Arc::new(self.worker_thread_pool);
-        let http_version = self.default_http_version.clone();
+        let context = Arc::new(self.context);
+        let server_context = Arc::clone(&context);
 
         // Begin Accepting Connections on all Server Threads
+        // Every server thread accepts on its own handle to the socket and queues connections for the workers
         let server_thread_job = move || {
+            let acceptor = listener.try_clone().unwrap();
             loop {
-                match worker_listener.lock().unwrap().accept() {
+                match acceptor.accept() {
                     Err(_) => continue,
-                    Ok((stream, _addr)) => handle_connection(stream, &mut shared_routes, Arc::clone(&worker_thread_pool), http_version)
+                    Ok(_) if context.is_stopping() => break,
+                    Ok((stream, _addr)) => handle_connection(stream, &shared_routes, &worker_thread_pool, &context)
                 }
             }
         };
 
         self.server_thread_pool.execute_job(server_thread_job);
 
-        loop {}
+        let uri = format!("{}://{}", server_context.get_scheme(), host_url);
+        server_context.get_logger().info(&["server", "start"], format!("Server started at {}", uri));
+        server_context.get_events().emit(ServerEvent::Start { uri });
+
+        // Our own sender keeps the channel open so this only returns on a stop
+        self.stop_receiver.recv().unwrap_or_default();
+        server_context.set_stopping();
+
+        // Wake every server thread blocked on accept
+        let wake_address = match address.ip() {
+            IpAddr::V4(ip) if ip.is_unspecified() => (IpAddr::V4(Ipv4Addr::LOCALHOST), address.port()).into(),
+            IpAddr::V6(ip) if ip.is_unspecified() => (IpAddr::V6(Ipv6Addr::LOCALHOST), address.port()).into(),
+            _ => address
+        };
+        for _ in 0..self.server_thread_pool.get_metrics().get_workers() {
+            TcpStream::connect(wake_address).map(drop).unwrap_or_default();
+        }
+
+        // Dropping the pools waits for the server threads and the requests in flight
+        drop(self.server_thread_pool);
+        // HTTP/2 connections answer the streams they have open before closing
+        server_context.get_http2_connections().wait_closed();
+        server_context.get_logger().info(&["server", "stop"], "Server stopped");
+        server_context.get_events().emit(ServerEvent::Stop);
     }
 }
 
 impl Default for Server {
     fn default() -> Self {
+        let (stop_sender, stop_receiver) = mpsc::channel();
+
         Self {
             port: 3000,
             host: String::from("localhost"),
             routes: vec![],
-            server_thread_pool: ThreadPool::new(1),
-            worker_thread_pool: ThreadPool::new(2),
-            default_http_version: HTTPVersion::HTTP20
+            server_thread_pool: ThreadPool::new(1, 1, Logger::default()),
+            worker_thread_pool: ThreadPool::new(2, ServerOptions::default().get_queue_capacity(), Logger::default()),
+            context: ServerContext::default(),
+            stop_sender,
+            stop_receiver
         }
     }
 }
 
-fn handle_connection(mut stream: TcpStream, routes: &mut Arc<Vec<Route>>, pool: Arc<ThreadPool>, http_version: HTTPVersion) {
-    let mut buffer:[u8; 512] = [0; 512];
-    stream.read(&mut buffer).unwrap();
+/// Where a request is sent once it has been read
+enum Dispatch {
+    Route(Arc<RouteHandler>, Option<Duration>, Option<String>),
+    AsyncRoute(Arc<AsyncRouteHandler>, Option<Duration>, Option<String>),
+    NotFound,
+    MethodNotAllowed(Vec<HTTPMethod>),
+    BadRequest
+}
+
+fn handle_connection(stream: TcpStream, routes: &Arc<Vec<Route>>, pool: &Arc<ThreadPool>, context: &Arc<ServerContext>) {
+    let context = Arc::clone(context);
+    let accepted = Instant::now();
+    let mut stream = Connection::new(stream);
 
-    let request = match buffer.len() != 0 {
-        false => {
-            // Request has no content
-            // TODO: Allow user to override generic response with custom route
-            stream.write(HTTP::get_generic_response_string(HTTPStatusCodes::BadRequest400, http_version).as_ref()).unwrap();
-            None
+    // Too many open connections
+    // Service Unavailable
+    let connection = match context.get_load().try_connect() {
+        None => return context.overloaded(None, stream.into()),
+        Some(t) => t
+    };
+
+    let proxy = match context.get_read_timeouts().read_proxy_header(&mut stream) {
+        Ok(proxy) => proxy,
+        Err(ReadError::Idle) | Err(ReadError::Closed) => return,
+        Err(ReadError::Io(e)) => return context.get_logger().error(&["request", "error"], format!("Failed to read request: {}", e)),
+        // Not from the proxy we're behind, a broken one, or one too slow to send its header, don't answer
+        Err(_) => return context.get_logger().warn(&["request", "proxy", "error"], "Connection without a valid PROXY protocol header")
+    };
+
+    #[cfg(feature = "tls")]
+    if let Some(tls) = context.get_tls() {
+        match tls.accept() {
+            Ok(session) => stream.start_tls(session),
+            Err(e) => return context.get_logger().error(&["request", "tls", "error"], format!("Failed to start TLS: {}", e))
+        }
+    }
+
+    let buffer = match context.get_read_timeouts().read_request(&mut stream) {
+        Ok(buffer) => buffer,
+        // Nothing to answer
+        Err(ReadError::Idle) | Err(ReadError::Closed) | Err(ReadError::InvalidProxyHeader) => return,
+        // Failed handshakes, such as clients without a valid certificate
+        Err(ReadError::Io(ref e)) if stream.is_secure() && e.kind() == ErrorKind::InvalidData => {
+            return context.get_logger().warn(&["request", "tls", "error"], format!("TLS error: {}", e));
+        },
+        Err(ReadError::Io(e)) => return context.get_logger().error(&["request", "error"], format!("Failed to read request: {}", e)),
+        // Client too slow
+        // Request Timeout
+        Err(ReadError::HeaderTimeout) | Err(ReadError::BodyTimeout) => {
+            return context.reject(HTTPError::request_timeout("Request Timeout"), None, stream.into());
         },
-        true => {
-            Request::parse_request(&buffer)
+        Err(ReadError::HeaderTooLarge) => {
+            return context.reject(HTTPError::request_header_fields_too_large("Request Header Fields Too Large"), None, stream.into());
+        }
+        Err(ReadError::PayloadTooLarge) => {
+            return context.reject(HTTPError::payload_too_large("Payload Too Large"), None, stream.into());
         }
     };
 
-    // Get the String response to write to the stream
-    match request {
-        None => {
-            // Request didn't parse correctly
-            // Bad Request
-            // TODO: Allow user to override generic response with custom route
-            stream.write(HTTP::get_generic_response_string(HTTPStatusCodes::BadRequest400, http_version).as_ref()).unwrap();
-        },
-        Some(some_request) => {
-            if cfg!(debug_assertions) {
-                println!("{:?}", some_request.get_method());
-                println!("{:?}", some_request.get_path());
-                println!("{:?}", some_request.get_version());
-                println!("{}", some_request.get_headers().get_headers_formatted());
-                println!("{}", some_request.get_payload());
+    // HTTP/2 chosen through ALPN, or with prior knowledge where the preface's first line reads like a request head
+    if stream.is_http2() || buffer.starts_with(&PREFACE[..18]) {
+        return serve_http2(Http2Connection::new(stream, &context, proxy), buffer, None, connection, routes, pool, &context);
+    }
+
+    let length = request_length(&buffer);
+    let request = Request::parse_request(&buffer[..length]).map(|mut request| {
+        context.track_request(&mut request, accepted, &stream, proxy);
+        request
+    });
+
+    // h2c upgrade, HTTP/2 over TLS is only ever chosen through ALPN
+    if let Some(request) = request.as_ref().filter(|_| !stream.is_secure()) {
+        if let Some(settings) = http2::upgrade_settings(request) {
+            let upgrade = request.clone();
+            return serve_http2(Http2Connection::new(stream, &context, proxy), buffer[length..].to_vec(), Some((upgrade, settings)), connection, routes, pool, &context);
+        }
+    }
+
+    dispatch(request, stream.into(), Some(connection), routes, pool, &context);
+}
+
+/// Serve an HTTP/2 connection on a thread of its own, its streams go through the router and the workers like any request
+/// `upgrade` holds the request that asked for h2c and the client's settings from it
+fn serve_http2(http2: io::Result<Http2Connection>, received: Vec<u8>, upgrade: Option<(Request, Vec<(u16, u32)>)>, connection: ConnectionGuard, routes: &Arc<Vec<Route>>, pool: &Arc<ThreadPool>, context: &Arc<ServerContext>) {
+    let http2 = match http2 {
+        Ok(http2) => http2,
+        Err(e) => return context.get_logger().error(&["request", "http2", "error"], format!("Failed to start HTTP/2 connection: {}", e))
+    };
+
+    let (routes, pool, thread_context) = (Arc::clone(routes), Arc::clone(pool), Arc::clone(context));
+    let open = context.get_http2_connections().open();
+    let spawned = thread::Builder::new().name(String::from("http2-connection")).spawn(move || {
+        let (_connection, _open) = (connection, open);
+        // Streams the workers have no room for yet wait on their connection, the client already limits how many it opens
+        let dispatch = move |request, stream| try_dispatch(request, stream, None, &routes, &pool, &thread_context);
+        match upgrade {
+            None => http2.serve(&received, dispatch),
+            Some((request, settings)) => http2.serve_upgrade(request, &settings, &received, dispatch)
+        }
+    });
+
+    if let Err(e) = spawned {
+        context.get_logger().error(&["request", "http2", "error"], format!("Failed to start HTTP/2 connection thread: {}", e));
+    }
+}
+
+/// Route a request read off the connection, or answer a 400 if it didn't parse, on the workers
+/// HTTP/1 requests hold on to their connection's place among the open connections until answered
+fn dispatch(request: Option<Request>, stream: Sink, connection: Option<ConnectionGuard>, routes: &[Route], pool: &Arc<ThreadPool>, context: &Arc<ServerContext>) {
+    // Too many requests waiting for a worker
+    // Service Unavailable
+    if let Err(rejected) = try_dispatch(request, stream, connection, routes, pool, context) {
+        let (request, stream) = *rejected;
+        context.queue_full(request.as_ref(), stream);
+    }
+}
+
+/// Like `dispatch`, but hands the request back instead of answering a 503 when the worker queue is full
+fn try_dispatch<S>(mut request: Option<Request>, stream: S, connection: Option<ConnectionGuard>, routes: &[Route], pool: &Arc<ThreadPool>, context: &Arc<ServerContext>) -> Result<(), Box<(Option<Request>, S)>>
+    where S: Into<Sink> + Send + 'static
+{
+    let context = Arc::clone(context);
+    let dispatch = match request {
+        // Request didn't parse correctly
+        // Bad Request
+        None => Dispatch::BadRequest,
+        Some(ref mut some_request) => find_route(routes, some_request)
+    };
+
+    let ticket = context.get_load().enqueue();
+
+    if let (Dispatch::AsyncRoute(handler, timeout, auth), Some(request)) = (&dispatch, &request) {
+        let (handler, timeout, auth, mut request, pool) = (Arc::clone(handler), *timeout, auth.clone(), request.clone(), Arc::clone(pool));
+
+        let stream = stream.into();
+        context.get_executor().spawn(Box::pin(async move {
+            // Waited too long for the executor
+            // Service Unavailable
+            if !context.get_load().start(ticket) {
+                return pool.execute(move || context.overloaded(Some(&request), stream));
             }
 
-            // Valid request
-            // Searching for matching route in order it was added
-            for route in routes.to_vec().iter_mut() {
-                match route.is_route_match(some_request.get_method(), some_request.get_path()) {
-                    true => {
-                        // Route exists
-                        // Call route handler
-                        let handler_box = route.get_handler();
-                        let mut response = Response::from_stream(stream);
-                        response.set_version(http_version);
-                        pool.execute_handler(handler_box, some_request, response);
-                        return;
-                    },
-                    false => continue
+            let responded = context.begin_route(timeout, &request, &stream);
+            let result = match context.authenticate(auth.as_deref(), &mut request) {
+                Err(e) => Err(e),
+                Ok(()) => {
+                    let started = SystemTime::now();
+                    let result = match CatchUnwind(handler(request.clone())).await {
+                        Ok(result) => result,
+                        Err(panic) => Err(context.handler_panicked(&request, &*panic))
+                    };
+                    request.record_phase("handler", started);
+                    result
                 }
+            };
+
+            // Writing to the connection blocks so leave it to the workers
+            pool.execute(move || {
+                let _connection = connection;
+                context.finish_route(result, responded, request, stream);
+            });
+        }));
+        return Ok(());
+    }
+
+    pool.try_execute((request, stream), move |(request, stream)| {
+        let _connection = connection;
+        let stream = stream.into();
+
+        // Waited too long for a worker
+        // Service Unavailable
+        if !context.get_load().start(ticket) {
+            return context.overloaded(request.as_ref(), stream);
+        }
+
+        match (dispatch, request) {
+            (Dispatch::Route(handler, timeout, auth), Some(request)) => context.handle_route(handler, timeout, auth, request, stream),
+            (Dispatch::NotFound, Some(request)) => context.not_found(request, stream),
+            (Dispatch::MethodNotAllowed(allowed), Some(request)) => context.method_not_allowed(request, allowed, stream),
+            (Dispatch::AsyncRoute(..), Some(_)) => unreachable!("async routes run on the executor"),
+            _ => context.bad_request(stream)
+        }
+    }).map_err(Box::new)
+}
+
+fn find_route(routes: &[Route], request: &mut Request) -> Dispatch {
+    // Valid request
+    // Searching for matching route in order it was added
+    let mut allowed = vec![];
+    for route in routes.iter() {
+        match route.is_route_match(request.get_method(), request.get_path()) {
+            // Route exists
+            // Call route handler
+            true => {
+                request.set_route(route.get_path());
+                return match route.get_handler() {
+                    Handler::Sync(handler) => Dispatch::Route(handler, route.get_server_timeout(), route.get_auth()),
+                    Handler::Async(handler) => Dispatch::AsyncRoute(handler, route.get_server_timeout(), route.get_auth())
+                };
+            },
+            false => if route.is_path_match(request.get_path()) {
+                allowed.extend(route.get_method());
             }
+        }
+    }
+
+    match allowed.is_empty() {
+        // Route was not found
+        // Send 404
+        true => Dispatch::NotFound,
+        // Path exists with other methods
+        // Send 405
+        false => {
+            allowed.sort();
+            allowed.dedup();
+            Dispatch::MethodNotAllowed(allowed)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{EventName, Route, Server, ServerOptions, StopHandle};
+    use super::super::http::{ErrorFormat, HTTPError, HTTPMethod, HTTPVersion, Request, Response};
+    use super::super::http::h2::PREFACE;
+    use super::super::http::h2::frame::Frame;
+    use super::super::http::h2::hpack::{Decoder, Encoder};
+    use std::io::{Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::{Mutex, mpsc};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Options for a server on a free local port
+    fn options(worker_threads: usize) -> ServerOptions {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        ServerOptions::new(String::from("127.0.0.1"), port as u32, 1, worker_threads, HTTPVersion::HTTP11)
+    }
+
+    /// A server running on its own thread until stopped
+    struct Running {
+        port: u16,
+        stop: StopHandle,
+        thread: thread::JoinHandle<()>
+    }
 
-            // Route was not found
-            // Send 404
-            // TODO: Allow user to override generic response with custom route
-            let not_found_response = HTTP::get_generic_response_string(HTTPStatusCodes::NotFound404, some_request.get_version());
+    impl Running {
+        fn start(options: &ServerOptions, mut server: Server) -> Self {
+            let (started, on_start) = mpsc::channel();
+            let started = Mutex::new(started);
+            server.on(EventName::Start, move |_event| started.lock().unwrap().send(()).unwrap_or_default());
+            let stop = server.stop_handle();
+            let thread = thread::spawn(move || server.run());
+            on_start.recv_timeout(Duration::from_secs(5)).unwrap();
 
-            if cfg!(debug_assertions) {
-                println!("404\n{}", not_found_response);
+            Self {
+                port: options.get_port() as u16,
+                stop,
+                thread
             }
+        }
 
-            stream.write(not_found_response.as_ref()).unwrap();
+        /// Send a raw request on a new connection and read the response until the server closes it
+        fn send(&self, request: &str) -> String {
+            let mut client = self.connect(request);
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            response
         }
-    };
+
+        fn connect(&self, request: &str) -> TcpStream {
+            let mut client = TcpStream::connect(("127.0.0.1", self.port)).unwrap();
+            client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            client.write_all(request.as_bytes()).unwrap();
+            client
+        }
+
+        fn stop(self) {
+            self.stop.stop();
+            self.thread.join().unwrap();
+        }
+    }
+
+    /// Wait for a condition other threads bring about
+    fn eventually<F: Fn() -> bool>(condition: F) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !condition() {
+            assert!(Instant::now() < deadline, "condition never became true");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    fn get(path: &str) -> String {
+        format!("GET {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n", path)
+    }
+
+    #[test]
+    fn the_default_queue_absorbs_bursts() {
+        assert!(ServerOptions::default().get_queue_capacity() >= 1024);
+    }
+
+    #[test]
+    fn requests_past_a_full_queue_get_a_503() {
+        let mut options = options(1);
+        options.set_queue_capacity(1);
+        options.set_retry_after(7);
+        let mut server = Server::new(&options);
+
+        // The handler holds its worker until the test lets it go
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/slow"), move |_req: &Request| -> Result<&'static str, HTTPError> {
+            released.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap_or_default();
+            Ok("done")
+        }));
+        let (load, pool) = (server.get_load_metrics(), server.get_pool_metrics());
+        let running = Running::start(&options, server);
+
+        let mut busy = running.connect(&get("/slow"));
+        eventually(|| pool.get_busy() == 1);
+        let mut queued = running.connect(&get("/slow"));
+        eventually(|| load.get_queued() == 1);
+
+        let shed = running.send(&get("/slow"));
+        assert!(shed.starts_with("HTTP/1.1 503 Service Unavailable\r\n"), "{}", shed);
+        assert!(shed.contains("Retry-After: 7\r\n"), "{}", shed);
+        assert_eq!(load.get_rejected_queue_full(), 1);
+        assert_eq!(load.get_rejected(), 1);
+
+        release.send(()).unwrap();
+        release.send(()).unwrap();
+        for client in [&mut busy, &mut queued] {
+            let mut response = String::new();
+            client.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        }
+        eventually(|| pool.get_jobs() == 2);
+        assert_eq!(load.get_queued(), 0);
+        running.stop();
+    }
+
+    #[test]
+    fn http10_requests_get_http10_responses() {
+        let options = options(1);
+        let mut server = Server::new(&options);
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/"), |_req: &Request| -> Result<Response, HTTPError> {
+            Ok(Response::new(b"hello".to_vec()).header("Connection", "keep-alive"))
+        }));
+        let running = Running::start(&options, server);
+
+        // The connection closes after the response, as HTTP/1.0 has no persistent connections
+        let response = running.send("GET / HTTP/1.0\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+        assert!(!response.contains("Connection:"), "{}", response);
+        assert!(response.ends_with("\r\n\r\nhello"), "{}", response);
+        running.stop();
+    }
+
+    #[test]
+    fn error_formats_follow_lowercase_accept_headers() {
+        let mut options = options(1);
+        options.set_error_format(ErrorFormat::Problem);
+        let running = Running::start(&options, Server::new(&options));
+
+        let text = running.send("GET /missing HTTP/1.1\r\nHost: localhost\r\naccept: text/plain\r\nConnection: close\r\n\r\n");
+        assert!(text.starts_with("HTTP/1.1 404 Not Found\r\n"), "{}", text);
+        assert!(text.contains("Content-Type: text/plain; charset=utf-8\r\n"), "{}", text);
+
+        let json = running.send(&get("/missing"));
+        assert!(json.contains("Content-Type: application/problem+json\r\n"), "{}", json);
+        running.stop();
+    }
+
+    #[test]
+    fn stopping_answers_the_requests_in_flight() {
+        let options = options(1);
+        let mut server = Server::new(&options);
+        let (release, released) = mpsc::channel::<()>();
+        let released = Mutex::new(released);
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/slow"), move |_req: &Request| -> Result<&'static str, HTTPError> {
+            released.lock().unwrap().recv_timeout(Duration::from_secs(5)).unwrap_or_default();
+            Ok("done")
+        }));
+        let pool = server.get_pool_metrics();
+        let running = Running::start(&options, server);
+
+        let mut client = running.connect(&get("/slow"));
+        eventually(|| pool.get_busy() == 1);
+
+        // `run` only returns once the handler finished
+        let (port, stop, thread) = (running.port, running.stop, running.thread);
+        stop.stop();
+        thread::sleep(Duration::from_millis(50));
+        assert!(!thread.is_finished());
+
+        release.send(()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("done"), "{}", response);
+        thread.join().unwrap();
+
+        // No longer accepting connections
+        assert!(TcpStream::connect(("127.0.0.1", port)).is_err());
+    }
+
+    #[test]
+    fn panicking_handlers_get_a_500() {
+        let options = options(1);
+        let mut server = Server::new(&options);
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/panic"), |_req: &Request| -> Result<&'static str, HTTPError> {
+            panic!("secret detail")
+        }));
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/ok"), |_req: &Request| -> Result<&'static str, HTTPError> {
+            Ok("fine")
+        }));
+        let pool = server.get_pool_metrics();
+        let running = Running::start(&options, server);
+
+        let response = running.send(&get("/panic"));
+        assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"), "{}", response);
+        assert!(!response.contains("secret detail"), "{}", response);
+
+        // The only worker survived and the pool never saw the panic
+        let response = running.send(&get("/ok"));
+        assert!(response.ends_with("fine"), "{}", response);
+        assert_eq!(pool.get_panics(), 0);
+        running.stop();
+    }
+
+    /// A GET on stream 1 of a prior knowledge or upgraded connection
+    fn h2_get(path: &str) -> Vec<u8> {
+        let headers: Vec<(String, String)> = [(":method", "GET"), (":scheme", "http"), (":authority", "localhost"), (":path", path)].iter()
+            .map(|&(name, value)| (String::from(name), String::from(value)))
+            .collect();
+        Frame::Headers { stream_id: 1, block: Encoder::new().encode(&headers), end_stream: true, end_headers: true, priority: None }.encode()
+    }
+
+    /// The status and body of the response on stream 1, skipping everything else the server sends
+    fn h2_response(client: &mut TcpStream, mut buffer: Vec<u8>) -> (String, String) {
+        let (mut decoder, mut status) = (Decoder::new(), None);
+        loop {
+            match Frame::parse(&buffer, 16384).unwrap() {
+                Some((frame, size)) => {
+                    buffer.drain(..size);
+                    match frame {
+                        Frame::Headers { stream_id: 1, block, .. } => status = Some(decoder.decode(&block).unwrap().remove(0).1),
+                        Frame::Data { stream_id: 1, data, end_stream: true, .. } => return (status.unwrap(), String::from_utf8(data).unwrap()),
+                        _ => ()
+                    }
+                },
+                None => {
+                    let mut chunk = [0; 4096];
+                    let size = client.read(&mut chunk).unwrap();
+                    assert!(size > 0, "connection closed before the response");
+                    buffer.extend_from_slice(&chunk[..size]);
+                }
+            }
+        }
+    }
+
+    fn h2_server() -> (ServerOptions, Server) {
+        let options = options(1);
+        let mut server = Server::new(&options);
+        server.route(Route::new(vec![HTTPMethod::GET], String::from("/h2"), |req: &Request| -> Result<String, HTTPError> {
+            Ok(req.get_version().to_string())
+        }));
+        (options, server)
+    }
+
+    #[test]
+    fn prior_knowledge_clients_speak_http2() {
+        let (options, server) = h2_server();
+        let running = Running::start(&options, server);
+
+        let mut request = PREFACE.to_vec();
+        request.extend_from_slice(&Frame::Settings { ack: false, settings: vec![] }.encode());
+        request.extend_from_slice(&h2_get("/h2"));
+        let mut client = TcpStream::connect(("127.0.0.1", running.port)).unwrap();
+        client.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        client.write_all(&request).unwrap();
+
+        assert_eq!(h2_response(&mut client, vec![]), (String::from("200"), String::from("HTTP/2.0")));
+        drop(client);
+        running.stop();
+    }
+
+    #[test]
+    fn h2c_upgrades_answer_on_stream_one() {
+        let (options, server) = h2_server();
+        let running = Running::start(&options, server);
+
+        let mut client = running.connect("GET /h2 HTTP/1.1\r\nHost: localhost\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n");
+        let mut received = vec![];
+        let head_end = loop {
+            if let Some(end) = received.windows(4).position(|window| window == b"\r\n\r\n") {
+                break end + 4;
+            }
+            let mut chunk = [0; 4096];
+            let size = client.read(&mut chunk).unwrap();
+            assert!(size > 0, "connection closed before switching protocols");
+            received.extend_from_slice(&chunk[..size]);
+        };
+        let head = String::from_utf8_lossy(&received[..head_end]).into_owned();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"), "{}", head);
+        assert!(head.contains("Upgrade: h2c\r\n"), "{}", head);
+
+        let mut preface = PREFACE.to_vec();
+        preface.extend_from_slice(&Frame::Settings { ack: false, settings: vec![] }.encode());
+        client.write_all(&preface).unwrap();
+        // The request itself came over HTTP/1.1, only its response goes out on stream 1
+        assert_eq!(h2_response(&mut client, received[head_end..].to_vec()), (String::from("200"), String::from("HTTP/1.1")));
+        drop(client);
+        running.stop();
+    }
+
+    #[test]
+    fn upgrades_are_ignored_on_http10() {
+        let (options, server) = h2_server();
+        let running = Running::start(&options, server);
+
+        let response = running.send("GET /h2 HTTP/1.0\r\nConnection: Upgrade, HTTP2-Settings\r\nUpgrade: h2c\r\nHTTP2-Settings: \r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"), "{}", response);
+        assert!(response.ends_with("HTTP/1.0"), "{}", response);
+        running.stop();
+    }
 }